@@ -1,5 +1,20 @@
-use crate::{errors::BytecodeSerializerError, operation::Op};
+use crate::{
+    errors::BytecodeSerializerError,
+    index::Extractor,
+    operation::{Op, SetOperation},
+    query::{Aggregate, Comparison, Condition, Field, Filter, KeyRange, Predicate},
+    snapshot::Snapshot,
+    types::bitmap::BitOperation,
+    types::json::{JsonPath, PathSegment},
+    types::sorted_set::SortedSet,
+    types::stream::{ConsumerGroup, PendingEntry, Stream, StreamId},
+    types::timeseries::{Aggregation, TimeSeries},
+    types::Value,
+};
+use bytes::Bytes;
 use lazy_static::lazy_static;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::ops::Bound;
 
 /*
 TODO LIST
@@ -17,12 +32,84 @@ Provide more detailed error messages for debugging.
 const START_MAGIC: u32 = 0xDEFEC8ED;
 const END_MAGIC: u32 = 0xB00B1E5;
 const PROTOCOL_VERSION: u8 = 0b0000_0000;
+// Same as PROTOCOL_VERSION with the LSN as a varint right after the operation code.
+const LSN_PROTOCOL_VERSION: u8 = 0b0000_0001;
 const PROTOCOL_BITMASK: u8 = 0b1111_0000;
 const OPERATION_BITMASK: u8 = 0b0000_1111;
 const SET_OPERATION: u8 = 0b0000_0001;
 const GET_OPERATION: u8 = 0b0000_0010;
 const DEL_OPERATION: u8 = 0b0000_0100;
+const EXTENDED_OPERATION: u8 = 0b0000_1000;
+const LPUSH_OPERATION: u8 = 0x10;
+const RPUSH_OPERATION: u8 = 0x11;
+const LPOP_OPERATION: u8 = 0x12;
+const RPOP_OPERATION: u8 = 0x13;
+const LRANGE_OPERATION: u8 = 0x14;
+const LLEN_OPERATION: u8 = 0x15;
+const BLPOP_OPERATION: u8 = 0x16;
+const BRPOP_OPERATION: u8 = 0x17;
+const HSET_OPERATION: u8 = 0x20;
+const HGET_OPERATION: u8 = 0x21;
+const HDEL_OPERATION: u8 = 0x22;
+const HGETALL_OPERATION: u8 = 0x23;
+const HINCRBY_OPERATION: u8 = 0x24;
+const HKEYS_OPERATION: u8 = 0x25;
+const ZADD_OPERATION: u8 = 0x30;
+const ZREM_OPERATION: u8 = 0x31;
+const ZSCORE_OPERATION: u8 = 0x32;
+const ZRANGE_OPERATION: u8 = 0x33;
+const ZRANGEBYSCORE_OPERATION: u8 = 0x34;
+const ZRANK_OPERATION: u8 = 0x35;
+const SADD_OPERATION: u8 = 0x40;
+const SREM_OPERATION: u8 = 0x41;
+const SISMEMBER_OPERATION: u8 = 0x42;
+const SMEMBERS_OPERATION: u8 = 0x43;
+const SCARD_OPERATION: u8 = 0x44;
+const SETOP_OPERATION: u8 = 0x45;
+const SETOPSTORE_OPERATION: u8 = 0x46;
+const SETBIT_OPERATION: u8 = 0x50;
+const GETBIT_OPERATION: u8 = 0x51;
+const BITCOUNT_OPERATION: u8 = 0x52;
+const BITPOS_OPERATION: u8 = 0x53;
+const BITOP_OPERATION: u8 = 0x54;
+const PFADD_OPERATION: u8 = 0x60;
+const PFCOUNT_OPERATION: u8 = 0x61;
+const PFMERGE_OPERATION: u8 = 0x62;
+const XADD_OPERATION: u8 = 0x70;
+const XRANGE_OPERATION: u8 = 0x71;
+const XREAD_OPERATION: u8 = 0x72;
+const XGROUPCREATE_OPERATION: u8 = 0x73;
+const XREADGROUP_OPERATION: u8 = 0x74;
+const XACK_OPERATION: u8 = 0x75;
+const XPENDING_OPERATION: u8 = 0x76;
+const TSCREATE_OPERATION: u8 = 0x80;
+const TSADD_OPERATION: u8 = 0x81;
+const TSRANGE_OPERATION: u8 = 0x82;
+const TSAGGREGATE_OPERATION: u8 = 0x83;
+const JSONSET_OPERATION: u8 = 0x90;
+const JSONGET_OPERATION: u8 = 0x91;
+const JSONARRAPPEND_OPERATION: u8 = 0x92;
+const INDEXCREATE_OPERATION: u8 = 0xA0;
+const INDEXDROP_OPERATION: u8 = 0xA1;
+const INDEXFIND_OPERATION: u8 = 0xA2;
+const SEARCHCREATE_OPERATION: u8 = 0xA3;
+const SEARCHDROP_OPERATION: u8 = 0xA4;
+const SEARCH_OPERATION: u8 = 0xA5;
+const SCAN_OPERATION: u8 = 0xB0;
+const AGGREGATE_OPERATION: u8 = 0xB1;
+const STATS_OPERATION: u8 = 0xC0;
+const CHECKPOINT_OPERATION: u8 = 0xC1;
 const CRC_POLYNOMIAL: u32 = 0xedb88320;
+const SNAPSHOT_MAGIC: u32 = 0x5AFEC0DE;
+const SNAPSHOT_VERSION: u8 = 0;
+const STRING_VALUE: u8 = 0;
+const LIST_VALUE: u8 = 1;
+const HASH_VALUE: u8 = 2;
+const SORTED_SET_VALUE: u8 = 3;
+const SET_VALUE: u8 = 4;
+const STREAM_VALUE: u8 = 5;
+const TIMESERIES_VALUE: u8 = 6;
+const JSON_VALUE: u8 = 7;
 lazy_static! {
     static ref START_MAGIC_BYTES: [u8; 4] = START_MAGIC.to_le_bytes();
     static ref END_MAGIC_BYTES: [u8; 4] = END_MAGIC.to_le_bytes();
@@ -45,159 +132,1156 @@ lazy_static! {
 
 pub struct BytecodeSerializer;
 
+/// A record read back from the WAL. `lsn` is `None` for records written before
+/// records carried one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub op: Op,
+    pub lsn: Option<u64>,
+}
+
 impl BytecodeSerializer {
     pub fn op_to_bytes(operation: &Op) -> Vec<u8> {
+        Self::encode(operation, None)
+    }
+
+    /// A WAL record for `operation` carrying its log sequence number.
+    pub fn record_to_bytes(operation: &Op, lsn: u64) -> Vec<u8> {
+        Self::encode(operation, Some(lsn))
+    }
+
+    fn encode(operation: &Op, lsn: Option<u64>) -> Vec<u8> {
         let mut bytes: Vec<u8> = vec![];
 
         bytes.extend(START_MAGIC_BYTES.iter());
         match operation {
+            Op::SET {
+                timestamp,
+                key,
+                value,
+            } => {
+                Self::write_header(&mut bytes, SET_OPERATION, *timestamp, key);
+                Self::write_bytes(&mut bytes, value);
+            }
             Op::GET { timestamp, key } => {
-                let header = 0 as u8 | PROTOCOL_VERSION | GET_OPERATION;
-                bytes.push(header);
+                Self::write_header(&mut bytes, GET_OPERATION, *timestamp, key);
+            }
+            Op::DEL { timestamp, key } => {
+                Self::write_header(&mut bytes, DEL_OPERATION, *timestamp, key);
+            }
+            Op::LPUSH {
+                timestamp,
+                key,
+                values,
+            } => {
+                Self::write_header(&mut bytes, LPUSH_OPERATION, *timestamp, key);
+                Self::write_list(&mut bytes, values);
+            }
+            Op::RPUSH {
+                timestamp,
+                key,
+                values,
+            } => {
+                Self::write_header(&mut bytes, RPUSH_OPERATION, *timestamp, key);
+                Self::write_list(&mut bytes, values);
+            }
+            Op::LPOP { timestamp, key } => {
+                Self::write_header(&mut bytes, LPOP_OPERATION, *timestamp, key);
+            }
+            Op::RPOP { timestamp, key } => {
+                Self::write_header(&mut bytes, RPOP_OPERATION, *timestamp, key);
+            }
+            Op::LRANGE {
+                timestamp,
+                key,
+                start,
+                stop,
+            } => {
+                Self::write_header(&mut bytes, LRANGE_OPERATION, *timestamp, key);
+                Self::write_signed(&mut bytes, *start);
+                Self::write_signed(&mut bytes, *stop);
+            }
+            Op::LLEN { timestamp, key } => {
+                Self::write_header(&mut bytes, LLEN_OPERATION, *timestamp, key);
+            }
+            Op::BLPOP {
+                timestamp,
+                keys,
+                timeout,
+            } => {
+                Self::write_header(&mut bytes, BLPOP_OPERATION, *timestamp, &[]);
+                Self::write_list(&mut bytes, keys);
+                Self::write_float(&mut bytes, *timeout);
+            }
+            Op::BRPOP {
+                timestamp,
+                keys,
+                timeout,
+            } => {
+                Self::write_header(&mut bytes, BRPOP_OPERATION, *timestamp, &[]);
+                Self::write_list(&mut bytes, keys);
+                Self::write_float(&mut bytes, *timeout);
+            }
+            Op::HSET {
+                timestamp,
+                key,
+                fields,
+            } => {
+                Self::write_header(&mut bytes, HSET_OPERATION, *timestamp, key);
+                Self::write_pairs(&mut bytes, fields);
+            }
+            Op::HGET {
+                timestamp,
+                key,
+                field,
+            } => {
+                Self::write_header(&mut bytes, HGET_OPERATION, *timestamp, key);
+                Self::write_bytes(&mut bytes, field);
+            }
+            Op::HDEL {
+                timestamp,
+                key,
+                fields,
+            } => {
+                Self::write_header(&mut bytes, HDEL_OPERATION, *timestamp, key);
+                Self::write_list(&mut bytes, fields);
+            }
+            Op::HGETALL { timestamp, key } => {
+                Self::write_header(&mut bytes, HGETALL_OPERATION, *timestamp, key);
+            }
+            Op::HINCRBY {
+                timestamp,
+                key,
+                field,
+                increment,
+            } => {
+                Self::write_header(&mut bytes, HINCRBY_OPERATION, *timestamp, key);
+                Self::write_bytes(&mut bytes, field);
+                Self::write_signed(&mut bytes, *increment);
+            }
+            Op::HKEYS { timestamp, key } => {
+                Self::write_header(&mut bytes, HKEYS_OPERATION, *timestamp, key);
+            }
+            Op::ZADD {
+                timestamp,
+                key,
+                members,
+            } => {
+                Self::write_header(&mut bytes, ZADD_OPERATION, *timestamp, key);
+                bytes.extend(Self::convert_to_varint(members.len()));
+                for (score, member) in members {
+                    Self::write_float(&mut bytes, *score);
+                    Self::write_bytes(&mut bytes, member);
+                }
+            }
+            Op::ZREM {
+                timestamp,
+                key,
+                members,
+            } => {
+                Self::write_header(&mut bytes, ZREM_OPERATION, *timestamp, key);
+                Self::write_list(&mut bytes, members);
+            }
+            Op::ZSCORE {
+                timestamp,
+                key,
+                member,
+            } => {
+                Self::write_header(&mut bytes, ZSCORE_OPERATION, *timestamp, key);
+                Self::write_bytes(&mut bytes, member);
+            }
+            Op::ZRANGE {
+                timestamp,
+                key,
+                start,
+                stop,
+            } => {
+                Self::write_header(&mut bytes, ZRANGE_OPERATION, *timestamp, key);
+                Self::write_signed(&mut bytes, *start);
+                Self::write_signed(&mut bytes, *stop);
+            }
+            Op::ZRANGEBYSCORE {
+                timestamp,
+                key,
+                min,
+                max,
+            } => {
+                Self::write_header(&mut bytes, ZRANGEBYSCORE_OPERATION, *timestamp, key);
+                Self::write_float(&mut bytes, *min);
+                Self::write_float(&mut bytes, *max);
+            }
+            Op::ZRANK {
+                timestamp,
+                key,
+                member,
+            } => {
+                Self::write_header(&mut bytes, ZRANK_OPERATION, *timestamp, key);
+                Self::write_bytes(&mut bytes, member);
+            }
+            Op::SADD {
+                timestamp,
+                key,
+                members,
+            } => {
+                Self::write_header(&mut bytes, SADD_OPERATION, *timestamp, key);
+                Self::write_list(&mut bytes, members);
+            }
+            Op::SREM {
+                timestamp,
+                key,
+                members,
+            } => {
+                Self::write_header(&mut bytes, SREM_OPERATION, *timestamp, key);
+                Self::write_list(&mut bytes, members);
+            }
+            Op::SISMEMBER {
+                timestamp,
+                key,
+                member,
+            } => {
+                Self::write_header(&mut bytes, SISMEMBER_OPERATION, *timestamp, key);
+                Self::write_bytes(&mut bytes, member);
+            }
+            Op::SMEMBERS { timestamp, key } => {
+                Self::write_header(&mut bytes, SMEMBERS_OPERATION, *timestamp, key);
+            }
+            Op::SCARD { timestamp, key } => {
+                Self::write_header(&mut bytes, SCARD_OPERATION, *timestamp, key);
+            }
+            // Multi key operations have no single key, the first key slot is
+            // left empty and the operand keys follow as a list.
+            Op::SETOP {
+                timestamp,
+                operation,
+                keys,
+            } => {
+                Self::write_header(&mut bytes, SETOP_OPERATION, *timestamp, &[]);
+                bytes.push(Self::set_operation_to_byte(*operation));
+                Self::write_list(&mut bytes, keys);
+            }
+            Op::SETOPSTORE {
+                timestamp,
+                operation,
+                destination,
+                keys,
+            } => {
+                Self::write_header(&mut bytes, SETOPSTORE_OPERATION, *timestamp, destination);
+                bytes.push(Self::set_operation_to_byte(*operation));
+                Self::write_list(&mut bytes, keys);
+            }
+            // Only the touched bit is logged, not the whole bitmap.
+            Op::SETBIT {
+                timestamp,
+                key,
+                offset,
+                bit,
+            } => {
+                Self::write_header(&mut bytes, SETBIT_OPERATION, *timestamp, key);
+                bytes.extend(Self::convert_to_varint(*offset as usize));
+                bytes.push(*bit as u8);
+            }
+            Op::GETBIT {
+                timestamp,
+                key,
+                offset,
+            } => {
+                Self::write_header(&mut bytes, GETBIT_OPERATION, *timestamp, key);
+                bytes.extend(Self::convert_to_varint(*offset as usize));
+            }
+            Op::BITCOUNT {
+                timestamp,
+                key,
+                range,
+            } => {
+                Self::write_header(&mut bytes, BITCOUNT_OPERATION, *timestamp, key);
+                Self::write_optional_signed(&mut bytes, range.map(|(start, _)| start));
+                Self::write_optional_signed(&mut bytes, range.map(|(_, end)| end));
+            }
+            Op::BITPOS {
+                timestamp,
+                key,
+                bit,
+                start,
+                end,
+            } => {
+                Self::write_header(&mut bytes, BITPOS_OPERATION, *timestamp, key);
+                bytes.push(*bit as u8);
+                Self::write_optional_signed(&mut bytes, *start);
+                Self::write_optional_signed(&mut bytes, *end);
+            }
+            Op::BITOP {
+                timestamp,
+                operation,
+                destination,
+                keys,
+            } => {
+                Self::write_header(&mut bytes, BITOP_OPERATION, *timestamp, destination);
+                bytes.push(Self::bit_operation_to_byte(*operation));
+                Self::write_list(&mut bytes, keys);
+            }
+            Op::PFADD {
+                timestamp,
+                key,
+                elements,
+            } => {
+                Self::write_header(&mut bytes, PFADD_OPERATION, *timestamp, key);
+                Self::write_list(&mut bytes, elements);
+            }
+            Op::PFCOUNT { timestamp, keys } => {
+                Self::write_header(&mut bytes, PFCOUNT_OPERATION, *timestamp, &[]);
+                Self::write_list(&mut bytes, keys);
+            }
+            Op::PFMERGE {
+                timestamp,
+                destination,
+                keys,
+            } => {
+                Self::write_header(&mut bytes, PFMERGE_OPERATION, *timestamp, destination);
+                Self::write_list(&mut bytes, keys);
+            }
+            Op::XADD {
+                timestamp,
+                key,
+                id,
+                fields,
+            } => {
+                Self::write_header(&mut bytes, XADD_OPERATION, *timestamp, key);
+                Self::write_optional_stream_id(&mut bytes, *id);
+                Self::write_pairs(&mut bytes, fields);
+            }
+            Op::XRANGE {
+                timestamp,
+                key,
+                start,
+                end,
+            } => {
+                Self::write_header(&mut bytes, XRANGE_OPERATION, *timestamp, key);
+                Self::write_stream_id(&mut bytes, *start);
+                Self::write_stream_id(&mut bytes, *end);
+            }
+            Op::XREAD {
+                timestamp,
+                key,
+                after,
+                count,
+            } => {
+                Self::write_header(&mut bytes, XREAD_OPERATION, *timestamp, key);
+                Self::write_stream_id(&mut bytes, *after);
+                Self::write_optional_signed(&mut bytes, count.map(|count| count as i64));
+            }
+            Op::XGROUPCREATE {
+                timestamp,
+                key,
+                group,
+                start,
+            } => {
+                Self::write_header(&mut bytes, XGROUPCREATE_OPERATION, *timestamp, key);
+                Self::write_bytes(&mut bytes, group);
+                Self::write_optional_stream_id(&mut bytes, *start);
+            }
+            Op::XREADGROUP {
+                timestamp,
+                key,
+                group,
+                consumer,
+                after,
+                count,
+            } => {
+                Self::write_header(&mut bytes, XREADGROUP_OPERATION, *timestamp, key);
+                Self::write_bytes(&mut bytes, group);
+                Self::write_bytes(&mut bytes, consumer);
+                Self::write_optional_stream_id(&mut bytes, *after);
+                Self::write_optional_signed(&mut bytes, count.map(|count| count as i64));
+            }
+            Op::XACK {
+                timestamp,
+                key,
+                group,
+                ids,
+            } => {
+                Self::write_header(&mut bytes, XACK_OPERATION, *timestamp, key);
+                Self::write_bytes(&mut bytes, group);
+                bytes.extend(Self::convert_to_varint(ids.len()));
+                for id in ids {
+                    Self::write_stream_id(&mut bytes, *id);
+                }
+            }
+            Op::XPENDING {
+                timestamp,
+                key,
+                group,
+            } => {
+                Self::write_header(&mut bytes, XPENDING_OPERATION, *timestamp, key);
+                Self::write_bytes(&mut bytes, group);
+            }
+            Op::TSCREATE {
+                timestamp,
+                key,
+                retention,
+            } => {
+                Self::write_header(&mut bytes, TSCREATE_OPERATION, *timestamp, key);
+                bytes.extend(Self::convert_to_varint(*retention as usize));
+            }
+            Op::TSADD {
+                timestamp,
+                key,
+                sample_time,
+                value,
+            } => {
+                Self::write_header(&mut bytes, TSADD_OPERATION, *timestamp, key);
+                Self::write_optional_signed(&mut bytes, *sample_time);
+                Self::write_float(&mut bytes, *value);
+            }
+            Op::TSRANGE {
+                timestamp,
+                key,
+                from,
+                to,
+            } => {
+                Self::write_header(&mut bytes, TSRANGE_OPERATION, *timestamp, key);
+                Self::write_signed(&mut bytes, *from);
+                Self::write_signed(&mut bytes, *to);
+            }
+            Op::TSAGGREGATE {
+                timestamp,
+                key,
+                from,
+                to,
+                aggregation,
+                bucket,
+            } => {
+                Self::write_header(&mut bytes, TSAGGREGATE_OPERATION, *timestamp, key);
+                Self::write_signed(&mut bytes, *from);
+                Self::write_signed(&mut bytes, *to);
+                bytes.push(Self::aggregation_to_byte(*aggregation));
+                bytes.extend(Self::convert_to_varint(*bucket as usize));
+            }
+            Op::JSONSET {
+                timestamp,
+                key,
+                path,
+                value,
+            } => {
+                Self::write_header(&mut bytes, JSONSET_OPERATION, *timestamp, key);
+                Self::write_json_path(&mut bytes, path);
+                Self::write_bytes(&mut bytes, value.to_string().as_bytes());
+            }
+            Op::JSONGET {
+                timestamp,
+                key,
+                path,
+            } => {
+                Self::write_header(&mut bytes, JSONGET_OPERATION, *timestamp, key);
+                Self::write_json_path(&mut bytes, path);
+            }
+            Op::JSONARRAPPEND {
+                timestamp,
+                key,
+                path,
+                values,
+            } => {
+                Self::write_header(&mut bytes, JSONARRAPPEND_OPERATION, *timestamp, key);
+                Self::write_json_path(&mut bytes, path);
+                bytes.extend(Self::convert_to_varint(values.len()));
+                for value in values {
+                    Self::write_bytes(&mut bytes, value.to_string().as_bytes());
+                }
+            }
+            Op::INDEXCREATE {
+                timestamp,
+                name,
+                prefix,
+                extractor,
+            } => {
+                Self::write_header(&mut bytes, INDEXCREATE_OPERATION, *timestamp, name);
+                Self::write_bytes(&mut bytes, prefix);
+                match extractor {
+                    Extractor::Value => bytes.push(0),
+                    Extractor::JsonField(path) => {
+                        bytes.push(1);
+                        Self::write_json_path(&mut bytes, path);
+                    }
+                }
+            }
+            Op::INDEXDROP { timestamp, name } => {
+                Self::write_header(&mut bytes, INDEXDROP_OPERATION, *timestamp, name);
+            }
+            Op::INDEXFIND {
+                timestamp,
+                name,
+                value,
+            } => {
+                Self::write_header(&mut bytes, INDEXFIND_OPERATION, *timestamp, name);
+                Self::write_bytes(&mut bytes, value);
+            }
+            Op::SEARCHCREATE { timestamp, prefix } => {
+                Self::write_header(&mut bytes, SEARCHCREATE_OPERATION, *timestamp, prefix);
+            }
+            Op::SEARCHDROP { timestamp, prefix } => {
+                Self::write_header(&mut bytes, SEARCHDROP_OPERATION, *timestamp, prefix);
+            }
+            Op::SEARCH {
+                timestamp,
+                prefix,
+                query,
+            } => {
+                Self::write_header(&mut bytes, SEARCH_OPERATION, *timestamp, prefix);
+                Self::write_bytes(&mut bytes, query);
+            }
+            Op::SCAN {
+                timestamp,
+                range,
+                filter,
+            } => {
+                Self::write_header(&mut bytes, SCAN_OPERATION, *timestamp, &[]);
+                Self::write_key_range(&mut bytes, range);
+                Self::write_optional_filter(&mut bytes, filter);
+            }
+            Op::AGGREGATE {
+                timestamp,
+                function,
+                range,
+                filter,
+            } => {
+                Self::write_header(&mut bytes, AGGREGATE_OPERATION, *timestamp, &[]);
+                bytes.push(Self::aggregate_to_byte(*function));
+                Self::write_key_range(&mut bytes, range);
+                Self::write_optional_filter(&mut bytes, filter);
+            }
+            Op::STATS { timestamp } => {
+                Self::write_header(&mut bytes, STATS_OPERATION, *timestamp, &[]);
+            }
+            Op::CHECKPOINT { timestamp } => {
+                Self::write_header(&mut bytes, CHECKPOINT_OPERATION, *timestamp, &[]);
+            }
+        }
 
-                let timestamp_bytes = Self::convert_to_varint(*timestamp as usize);
-                bytes.extend(timestamp_bytes);
+        if let Some(lsn) = lsn {
+            let header = bytes[4];
+            bytes[4] = (LSN_PROTOCOL_VERSION << 4) | (header & OPERATION_BITMASK);
+            let after_operation = match header & OPERATION_BITMASK {
+                EXTENDED_OPERATION => 6,
+                _ => 5,
+            };
+            bytes.splice(
+                after_operation..after_operation,
+                Self::convert_to_varint(lsn as usize),
+            );
+        }
 
-                let key_bytes = key.as_bytes();
-                bytes.extend(Self::convert_to_varint(key_bytes.len()));
-                bytes.extend(key_bytes);
+        let crc = Self::calculate_crc32(&bytes[4..]);
+        let crc_bytes = crc.to_le_bytes();
+        bytes.extend(Self::convert_to_varint(crc_bytes.len()));
+        bytes.extend(crc_bytes);
 
-                let crc = Self::calculate_crc32(&bytes[4..]);
-                let crc_bytes = crc.to_le_bytes();
-                let crc_len = crc_bytes.len();
-                let crc_var_int = Self::convert_to_varint(crc_len);
-                bytes.extend(crc_var_int);
-                bytes.extend(crc_bytes);
+        bytes.extend(END_MAGIC_BYTES.iter());
+        bytes
+    }
+
+    // Operation codes that do not fit into the 4 bit header nibble are written
+    // as EXTENDED_OPERATION followed by a full byte with the actual code.
+    fn write_header(bytes: &mut Vec<u8>, operation: u8, timestamp: i64, key: &[u8]) {
+        if operation & !OPERATION_BITMASK == 0 {
+            bytes.push((PROTOCOL_VERSION << 4) | operation);
+        } else {
+            bytes.push((PROTOCOL_VERSION << 4) | EXTENDED_OPERATION);
+            bytes.push(operation);
+        }
+        bytes.extend(Self::convert_to_varint(timestamp as usize));
+        Self::write_bytes(bytes, key);
+    }
+
+    fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+        bytes.extend(Self::convert_to_varint(data.len()));
+        bytes.extend_from_slice(data);
+    }
+
+    fn write_list(bytes: &mut Vec<u8>, items: &[Bytes]) {
+        bytes.extend(Self::convert_to_varint(items.len()));
+        for item in items {
+            Self::write_bytes(bytes, item);
+        }
+    }
+
+    fn write_pairs(bytes: &mut Vec<u8>, pairs: &[(Bytes, Bytes)]) {
+        bytes.extend(Self::convert_to_varint(pairs.len()));
+        for (first, second) in pairs {
+            Self::write_bytes(bytes, first);
+            Self::write_bytes(bytes, second);
+        }
+    }
+
+    fn set_operation_to_byte(operation: SetOperation) -> u8 {
+        match operation {
+            SetOperation::Union => 0,
+            SetOperation::Intersection => 1,
+            SetOperation::Difference => 2,
+        }
+    }
+
+    fn set_operation_from_byte(byte: u8) -> Result<SetOperation, BytecodeSerializerError> {
+        match byte {
+            0 => Ok(SetOperation::Union),
+            1 => Ok(SetOperation::Intersection),
+            2 => Ok(SetOperation::Difference),
+            _ => Err(BytecodeSerializerError::SerializationError(
+                "Invalid set operation".to_string(),
+            )),
+        }
+    }
+
+    fn bit_operation_to_byte(operation: BitOperation) -> u8 {
+        match operation {
+            BitOperation::And => 0,
+            BitOperation::Or => 1,
+            BitOperation::Xor => 2,
+            BitOperation::Not => 3,
+        }
+    }
+
+    fn bit_operation_from_byte(byte: u8) -> Result<BitOperation, BytecodeSerializerError> {
+        match byte {
+            0 => Ok(BitOperation::And),
+            1 => Ok(BitOperation::Or),
+            2 => Ok(BitOperation::Xor),
+            3 => Ok(BitOperation::Not),
+            _ => Err(BytecodeSerializerError::SerializationError(
+                "Invalid bit operation".to_string(),
+            )),
+        }
+    }
+
+    fn write_optional_filter(bytes: &mut Vec<u8>, filter: &Option<Filter>) {
+        match filter {
+            Some(filter) => {
+                bytes.push(1);
+                Self::write_filter(bytes, filter);
+            }
+            None => bytes.push(0),
+        }
+    }
+
+    fn aggregate_to_byte(function: Aggregate) -> u8 {
+        match function {
+            Aggregate::Count => 0,
+            Aggregate::Sum => 1,
+            Aggregate::Min => 2,
+            Aggregate::Max => 3,
+            Aggregate::Avg => 4,
+        }
+    }
+
+    fn aggregate_from_byte(byte: u8) -> Result<Aggregate, BytecodeSerializerError> {
+        match byte {
+            0 => Ok(Aggregate::Count),
+            1 => Ok(Aggregate::Sum),
+            2 => Ok(Aggregate::Min),
+            3 => Ok(Aggregate::Max),
+            4 => Ok(Aggregate::Avg),
+            _ => Err(BytecodeSerializerError::SerializationError(
+                "Invalid aggregate".to_string(),
+            )),
+        }
+    }
+
+    fn aggregation_to_byte(aggregation: Aggregation) -> u8 {
+        match aggregation {
+            Aggregation::Avg => 0,
+            Aggregation::Min => 1,
+            Aggregation::Max => 2,
+            Aggregation::Sum => 3,
+        }
+    }
 
-                bytes.extend(END_MAGIC_BYTES.iter());
-                bytes
+    fn aggregation_from_byte(byte: u8) -> Result<Aggregation, BytecodeSerializerError> {
+        match byte {
+            0 => Ok(Aggregation::Avg),
+            1 => Ok(Aggregation::Min),
+            2 => Ok(Aggregation::Max),
+            3 => Ok(Aggregation::Sum),
+            _ => Err(BytecodeSerializerError::SerializationError(
+                "Invalid aggregation".to_string(),
+            )),
+        }
+    }
+
+    fn write_optional_signed(bytes: &mut Vec<u8>, number: Option<i64>) {
+        match number {
+            Some(number) => {
+                bytes.push(1);
+                Self::write_signed(bytes, number);
+            }
+            None => bytes.push(0),
+        }
+    }
+
+    fn write_stream_id(bytes: &mut Vec<u8>, id: StreamId) {
+        bytes.extend(Self::convert_to_varint(id.ms as usize));
+        bytes.extend(Self::convert_to_varint(id.seq as usize));
+    }
+
+    fn write_optional_stream_id(bytes: &mut Vec<u8>, id: Option<StreamId>) {
+        match id {
+            Some(id) => {
+                bytes.push(1);
+                Self::write_stream_id(bytes, id);
+            }
+            None => bytes.push(0),
+        }
+    }
+
+    fn write_float(bytes: &mut Vec<u8>, number: f64) {
+        bytes.extend(number.to_le_bytes());
+    }
+
+    fn write_bound(bytes: &mut Vec<u8>, bound: &Bound<Bytes>) {
+        match bound {
+            Bound::Unbounded => bytes.push(0),
+            Bound::Included(key) => {
+                bytes.push(1);
+                Self::write_bytes(bytes, key);
+            }
+            Bound::Excluded(key) => {
+                bytes.push(2);
+                Self::write_bytes(bytes, key);
+            }
+        }
+    }
+
+    fn write_key_range(bytes: &mut Vec<u8>, range: &KeyRange) {
+        match range {
+            KeyRange::Prefix(prefix) => {
+                bytes.push(0);
+                Self::write_bytes(bytes, prefix);
+            }
+            KeyRange::Between(start, end) => {
+                bytes.push(1);
+                Self::write_bound(bytes, start);
+                Self::write_bound(bytes, end);
+            }
+        }
+    }
+
+    fn write_filter(bytes: &mut Vec<u8>, filter: &Filter) {
+        bytes.extend(Self::convert_to_varint(filter.any_of.len()));
+        for all_of in &filter.any_of {
+            bytes.extend(Self::convert_to_varint(all_of.len()));
+            for condition in all_of {
+                bytes.push(match condition.field {
+                    Field::Key => 0,
+                    Field::Value => 1,
+                });
+                let (tag, operand) = match &condition.predicate {
+                    Predicate::Equals(operand) => (0, operand),
+                    Predicate::NotEquals(operand) => (1, operand),
+                    Predicate::Prefix(operand) => (2, operand),
+                    Predicate::Contains(operand) => (3, operand),
+                    Predicate::Glob(operand) => (4, operand),
+                    Predicate::Compare(comparison, number) => {
+                        bytes.push(5);
+                        bytes.push(match comparison {
+                            Comparison::Less => 0,
+                            Comparison::LessOrEqual => 1,
+                            Comparison::Greater => 2,
+                            Comparison::GreaterOrEqual => 3,
+                        });
+                        Self::write_float(bytes, *number);
+                        continue;
+                    }
+                };
+                bytes.push(tag);
+                Self::write_bytes(bytes, operand);
+            }
+        }
+    }
+
+    fn write_json_path(bytes: &mut Vec<u8>, path: &JsonPath) {
+        bytes.extend(Self::convert_to_varint(path.segments().len()));
+        for segment in path.segments() {
+            match segment {
+                PathSegment::Field(field) => {
+                    bytes.push(0);
+                    Self::write_bytes(bytes, field.as_bytes());
+                }
+                PathSegment::Index(index) => {
+                    bytes.push(1);
+                    Self::write_signed(bytes, *index);
+                }
+            }
+        }
+    }
+
+    // Zigzag encoding keeps small negative numbers (e.g. list indexes) short.
+    fn write_signed(bytes: &mut Vec<u8>, number: i64) {
+        let zigzag = ((number << 1) ^ (number >> 63)) as u64;
+        bytes.extend(Self::convert_to_varint(zigzag as usize));
+    }
+
+    // Parses the record between the magics and returns it with its length, records
+    // carry no length of their own so this is the only way to find where one ends.
+    fn read_op(bytes: &[u8]) -> Result<(Record, usize), BytecodeSerializerError> {
+        let mut reader = RecordReader::new(bytes);
+        let header = reader.read_u8()?;
+        let protocol = (header & PROTOCOL_BITMASK) >> 4;
+        let operation = match header & OPERATION_BITMASK {
+            EXTENDED_OPERATION => reader.read_u8()?,
+            operation => operation,
+        };
+        let lsn = match protocol {
+            PROTOCOL_VERSION => None,
+            LSN_PROTOCOL_VERSION => Some(reader.read_varint()? as u64),
+            _ => {
+                return Err(BytecodeSerializerError::DeserializationError(
+                    "Unknown protocol version".to_string(),
+                ))
+            }
+        };
+
+        let timestamp = reader.read_varint()? as i64;
+        let key = reader.read_bytes()?;
+
+        let op = match operation {
+            SET_OPERATION => Op::SET {
+                timestamp,
+                key,
+                value: reader.read_bytes()?,
+            },
+            GET_OPERATION => Op::GET { timestamp, key },
+            DEL_OPERATION => Op::DEL { timestamp, key },
+            LPUSH_OPERATION => Op::LPUSH {
+                timestamp,
+                key,
+                values: reader.read_list()?,
+            },
+            RPUSH_OPERATION => Op::RPUSH {
+                timestamp,
+                key,
+                values: reader.read_list()?,
+            },
+            LPOP_OPERATION => Op::LPOP { timestamp, key },
+            RPOP_OPERATION => Op::RPOP { timestamp, key },
+            LRANGE_OPERATION => Op::LRANGE {
+                timestamp,
+                key,
+                start: reader.read_signed()?,
+                stop: reader.read_signed()?,
+            },
+            LLEN_OPERATION => Op::LLEN { timestamp, key },
+            BLPOP_OPERATION => Op::BLPOP {
+                timestamp,
+                keys: reader.read_list()?,
+                timeout: reader.read_float()?,
+            },
+            BRPOP_OPERATION => Op::BRPOP {
+                timestamp,
+                keys: reader.read_list()?,
+                timeout: reader.read_float()?,
+            },
+            HSET_OPERATION => Op::HSET {
+                timestamp,
+                key,
+                fields: reader.read_pairs()?,
+            },
+            HGET_OPERATION => Op::HGET {
+                timestamp,
+                key,
+                field: reader.read_bytes()?,
+            },
+            HDEL_OPERATION => Op::HDEL {
+                timestamp,
+                key,
+                fields: reader.read_list()?,
+            },
+            HGETALL_OPERATION => Op::HGETALL { timestamp, key },
+            HINCRBY_OPERATION => Op::HINCRBY {
+                timestamp,
+                key,
+                field: reader.read_bytes()?,
+                increment: reader.read_signed()?,
+            },
+            HKEYS_OPERATION => Op::HKEYS { timestamp, key },
+            ZADD_OPERATION => {
+                let len = reader.read_varint()?;
+                let mut members = Vec::with_capacity(len.min(1024));
+                for _ in 0..len {
+                    members.push((reader.read_float()?, reader.read_bytes()?));
+                }
+                Op::ZADD {
+                    timestamp,
+                    key,
+                    members,
+                }
+            }
+            ZREM_OPERATION => Op::ZREM {
+                timestamp,
+                key,
+                members: reader.read_list()?,
+            },
+            ZSCORE_OPERATION => Op::ZSCORE {
+                timestamp,
+                key,
+                member: reader.read_bytes()?,
+            },
+            ZRANGE_OPERATION => Op::ZRANGE {
+                timestamp,
+                key,
+                start: reader.read_signed()?,
+                stop: reader.read_signed()?,
+            },
+            ZRANGEBYSCORE_OPERATION => Op::ZRANGEBYSCORE {
+                timestamp,
+                key,
+                min: reader.read_float()?,
+                max: reader.read_float()?,
+            },
+            ZRANK_OPERATION => Op::ZRANK {
+                timestamp,
+                key,
+                member: reader.read_bytes()?,
+            },
+            SADD_OPERATION => Op::SADD {
+                timestamp,
+                key,
+                members: reader.read_list()?,
+            },
+            SREM_OPERATION => Op::SREM {
+                timestamp,
+                key,
+                members: reader.read_list()?,
+            },
+            SISMEMBER_OPERATION => Op::SISMEMBER {
+                timestamp,
+                key,
+                member: reader.read_bytes()?,
+            },
+            SMEMBERS_OPERATION => Op::SMEMBERS { timestamp, key },
+            SCARD_OPERATION => Op::SCARD { timestamp, key },
+            SETOP_OPERATION => Op::SETOP {
+                timestamp,
+                operation: Self::set_operation_from_byte(reader.read_u8()?)?,
+                keys: reader.read_list()?,
+            },
+            SETOPSTORE_OPERATION => Op::SETOPSTORE {
+                timestamp,
+                operation: Self::set_operation_from_byte(reader.read_u8()?)?,
+                destination: key,
+                keys: reader.read_list()?,
+            },
+            SETBIT_OPERATION => Op::SETBIT {
+                timestamp,
+                key,
+                offset: reader.read_varint()? as u64,
+                bit: reader.read_u8()? != 0,
+            },
+            GETBIT_OPERATION => Op::GETBIT {
+                timestamp,
+                key,
+                offset: reader.read_varint()? as u64,
+            },
+            BITCOUNT_OPERATION => {
+                let start = reader.read_optional_signed()?;
+                let end = reader.read_optional_signed()?;
+                Op::BITCOUNT {
+                    timestamp,
+                    key,
+                    range: start.zip(end),
+                }
             }
-            Op::DEL { timestamp, key } => {
-                let header = 0 as u8 | PROTOCOL_VERSION | DEL_OPERATION;
-                bytes.push(header);
-
-                let timestamp_bytes = Self::convert_to_varint(*timestamp as usize);
-                bytes.extend(timestamp_bytes);
-
-                let key_bytes = key.as_bytes();
-                bytes.extend(Self::convert_to_varint(key_bytes.len()));
-                bytes.extend(key_bytes);
-
-                let crc = Self::calculate_crc32(&bytes[4..]);
-                let crc_bytes = crc.to_le_bytes();
-                let crc_len = crc_bytes.len();
-                let crc_var_int = Self::convert_to_varint(crc_len);
-                bytes.extend(crc_var_int);
-                bytes.extend(crc_bytes);
-
-                bytes.extend(END_MAGIC_BYTES.iter());
-                bytes
+            BITPOS_OPERATION => Op::BITPOS {
+                timestamp,
+                key,
+                bit: reader.read_u8()? != 0,
+                start: reader.read_optional_signed()?,
+                end: reader.read_optional_signed()?,
+            },
+            BITOP_OPERATION => Op::BITOP {
+                timestamp,
+                operation: Self::bit_operation_from_byte(reader.read_u8()?)?,
+                destination: key,
+                keys: reader.read_list()?,
+            },
+            PFADD_OPERATION => Op::PFADD {
+                timestamp,
+                key,
+                elements: reader.read_list()?,
+            },
+            PFCOUNT_OPERATION => Op::PFCOUNT {
+                timestamp,
+                keys: reader.read_list()?,
+            },
+            PFMERGE_OPERATION => Op::PFMERGE {
+                timestamp,
+                destination: key,
+                keys: reader.read_list()?,
+            },
+            XADD_OPERATION => {
+                let id = reader.read_optional_stream_id()?;
+                Op::XADD {
+                    timestamp,
+                    key,
+                    id,
+                    fields: reader.read_pairs()?,
+                }
             }
-            Op::SET {
+            XRANGE_OPERATION => Op::XRANGE {
                 timestamp,
                 key,
-                value,
-            } => {
-                let header = 0 as u8 | (PROTOCOL_VERSION << 4) | SET_OPERATION;
-                bytes.push(header);
-
-                let timestamp_bytes = Self::convert_to_varint(*timestamp as usize);
-                bytes.extend(timestamp_bytes);
-
-                let key_bytes = key.as_bytes();
-                bytes.extend(Self::convert_to_varint(key_bytes.len()));
-                bytes.extend(key_bytes);
-
-                let value_bytes = value.as_bytes();
-                bytes.extend(Self::convert_to_varint(value_bytes.len()));
-                bytes.extend(value_bytes);
-
-                let crc = Self::calculate_crc32(&bytes[4..]);
-                let crc_bytes = crc.to_le_bytes();
-                let crc_len = crc_bytes.len();
-                let crc_var_int = Self::convert_to_varint(crc_len);
-                bytes.extend(crc_var_int);
-                bytes.extend(crc_bytes);
-                let magic_end_bytes = END_MAGIC.to_le_bytes();
-                bytes.extend(magic_end_bytes);
-                bytes
+                start: reader.read_stream_id()?,
+                end: reader.read_stream_id()?,
+            },
+            XREAD_OPERATION => Op::XREAD {
+                timestamp,
+                key,
+                after: reader.read_stream_id()?,
+                count: reader.read_optional_signed()?.map(|count| count as u64),
+            },
+            XGROUPCREATE_OPERATION => Op::XGROUPCREATE {
+                timestamp,
+                key,
+                group: reader.read_bytes()?,
+                start: reader.read_optional_stream_id()?,
+            },
+            XREADGROUP_OPERATION => Op::XREADGROUP {
+                timestamp,
+                key,
+                group: reader.read_bytes()?,
+                consumer: reader.read_bytes()?,
+                after: reader.read_optional_stream_id()?,
+                count: reader.read_optional_signed()?.map(|count| count as u64),
+            },
+            XACK_OPERATION => {
+                let group = reader.read_bytes()?;
+                let len = reader.read_varint()?;
+                let mut ids = Vec::with_capacity(len.min(1024));
+                for _ in 0..len {
+                    ids.push(reader.read_stream_id()?);
+                }
+                Op::XACK {
+                    timestamp,
+                    key,
+                    group,
+                    ids,
+                }
             }
-        }
-    }
-
-    pub fn op_from_bytes(bytes: Vec<u8>) -> Result<Op, BytecodeSerializerError> {
-        let header = bytes
-            .get(0)
-            .ok_or_else(|| BytecodeSerializerError::SerializationError("No header".to_string()))?;
-        let _protocol = header & PROTOCOL_BITMASK;
-        let operation = header & OPERATION_BITMASK;
-        let mut index = 1usize;
-
-        let (timestamp, idx) = BytecodeSerializer::convert_from_varint(&bytes[1..]);
-        let timestamp = timestamp as i64;
-        index += idx;
-        let (key_len, idx) = BytecodeSerializer::convert_from_varint(&bytes[index..]);
-
-        let key = String::from_utf8(bytes[index + idx..index + idx + key_len].to_vec())
-            .map_err(|_| BytecodeSerializerError::SerializationError("Invalid key".to_string()))?;
-        println!("{}", key);
-        index += idx + key_len;
-        match operation {
-            SET_OPERATION => {
-                let (value_len, idx) = BytecodeSerializer::convert_from_varint(&bytes[index..]);
-                let value = String::from_utf8(bytes[index + idx..index + idx + value_len].to_vec())
-                    .map_err(|_| {
-                        BytecodeSerializerError::SerializationError("Invalid value".to_string())
-                    })?;
-                index += idx + value_len;
-                let (crc_len, idx) = BytecodeSerializer::convert_from_varint(&bytes[index..]);
-                let crc = u32::from_le_bytes(
-                    bytes[index + idx..index + idx + crc_len]
-                        .try_into()
-                        .map_err(|_| {
-                            BytecodeSerializerError::SerializationError("Invalid crc".to_string())
-                        })?,
-                );
-                let bytes_before_crc = &bytes[..index];
-                Self::validate_crc32(bytes_before_crc, crc)?;
-
-                Ok(Op::SET {
+            XPENDING_OPERATION => Op::XPENDING {
+                timestamp,
+                key,
+                group: reader.read_bytes()?,
+            },
+            TSCREATE_OPERATION => Op::TSCREATE {
+                timestamp,
+                key,
+                retention: reader.read_varint()? as u64,
+            },
+            TSADD_OPERATION => Op::TSADD {
+                timestamp,
+                key,
+                sample_time: reader.read_optional_signed()?,
+                value: reader.read_float()?,
+            },
+            TSRANGE_OPERATION => Op::TSRANGE {
+                timestamp,
+                key,
+                from: reader.read_signed()?,
+                to: reader.read_signed()?,
+            },
+            TSAGGREGATE_OPERATION => Op::TSAGGREGATE {
+                timestamp,
+                key,
+                from: reader.read_signed()?,
+                to: reader.read_signed()?,
+                aggregation: Self::aggregation_from_byte(reader.read_u8()?)?,
+                bucket: reader.read_varint()? as u64,
+            },
+            JSONSET_OPERATION => Op::JSONSET {
+                timestamp,
+                key,
+                path: reader.read_json_path()?,
+                value: reader.read_json()?,
+            },
+            JSONGET_OPERATION => Op::JSONGET {
+                timestamp,
+                key,
+                path: reader.read_json_path()?,
+            },
+            JSONARRAPPEND_OPERATION => {
+                let path = reader.read_json_path()?;
+                let len = reader.read_varint()?;
+                let mut values = Vec::with_capacity(len.min(1024));
+                for _ in 0..len {
+                    values.push(reader.read_json()?);
+                }
+                Op::JSONARRAPPEND {
                     timestamp,
                     key,
-                    value,
-                })
-            }
-            GET_OPERATION => {
-                let (crc_len, idx) = BytecodeSerializer::convert_from_varint(&bytes[index..]);
-                let crc = u32::from_le_bytes(
-                    bytes[index + idx..index + idx + crc_len]
-                        .try_into()
-                        .map_err(|_| {
-                            BytecodeSerializerError::SerializationError("Invalid crc".to_string())
-                        })?,
-                );
-                let bytes_before_crc = &bytes[..index];
-                BytecodeSerializer::validate_crc32(bytes_before_crc, crc)?;
-                Ok(Op::GET { timestamp, key })
-            }
-            DEL_OPERATION => {
-                let (crc_len, idx) = BytecodeSerializer::convert_from_varint(&bytes[index..]);
-                let crc = u32::from_le_bytes(
-                    bytes[index + idx..index + idx + crc_len]
-                        .try_into()
-                        .map_err(|_| {
-                            BytecodeSerializerError::SerializationError("Invalid crc".to_string())
-                        })?,
-                );
-                let bytes_before_crc = &bytes[..index];
-                BytecodeSerializer::validate_crc32(bytes_before_crc, crc)?;
-
-                Ok(Op::DEL { timestamp, key })
+                    path,
+                    values,
+                }
             }
-            _ => Err(BytecodeSerializerError::SerializationError(
-                "Invalid operation".to_string(),
-            )),
-        }
+            INDEXCREATE_OPERATION => {
+                let prefix = reader.read_bytes()?;
+                let extractor = match reader.read_u8()? {
+                    0 => Extractor::Value,
+                    _ => Extractor::JsonField(reader.read_json_path()?),
+                };
+                Op::INDEXCREATE {
+                    timestamp,
+                    name: key,
+                    prefix,
+                    extractor,
+                }
+            }
+            INDEXDROP_OPERATION => Op::INDEXDROP {
+                timestamp,
+                name: key,
+            },
+            INDEXFIND_OPERATION => Op::INDEXFIND {
+                timestamp,
+                name: key,
+                value: reader.read_bytes()?,
+            },
+            SEARCHCREATE_OPERATION => Op::SEARCHCREATE {
+                timestamp,
+                prefix: key,
+            },
+            SEARCHDROP_OPERATION => Op::SEARCHDROP {
+                timestamp,
+                prefix: key,
+            },
+            SEARCH_OPERATION => Op::SEARCH {
+                timestamp,
+                prefix: key,
+                query: reader.read_bytes()?,
+            },
+            SCAN_OPERATION => Op::SCAN {
+                timestamp,
+                range: reader.read_key_range()?,
+                filter: reader.read_optional_filter()?,
+            },
+            AGGREGATE_OPERATION => Op::AGGREGATE {
+                timestamp,
+                function: Self::aggregate_from_byte(reader.read_u8()?)?,
+                range: reader.read_key_range()?,
+                filter: reader.read_optional_filter()?,
+            },
+            STATS_OPERATION => Op::STATS { timestamp },
+            CHECKPOINT_OPERATION => Op::CHECKPOINT { timestamp },
+            _ => {
+                return Err(BytecodeSerializerError::SerializationError(
+                    "Invalid operation".to_string(),
+                ))
+            }
+        };
+
+        let bytes_before_crc = reader.position();
+        let crc_len = reader.read_varint()?;
+        let crc =
+            u32::from_le_bytes(reader.read_slice(crc_len)?.try_into().map_err(|_| {
+                BytecodeSerializerError::SerializationError("Invalid crc".to_string())
+            })?);
+        Self::validate_crc32(&bytes[..bytes_before_crc], crc)?;
+
+        Ok((Record { op, lsn }, reader.position()))
     }
 
     fn convert_to_varint(number: usize) -> Vec<u8> {
@@ -234,7 +1318,12 @@ impl BytecodeSerializer {
     }
 
     fn calculate_crc32(bytes: &[u8]) -> u32 {
-        let mut crc = 0xffffffff;
+        Self::update_crc32(0, bytes)
+    }
+
+    /// Continues `crc`, the checksum of some earlier bytes, over `bytes`.
+    pub fn update_crc32(crc: u32, bytes: &[u8]) -> u32 {
+        let mut crc = crc ^ 0xffffffff;
         for byte in bytes {
             let index = (crc ^ (*byte as u32)) & 0xff;
             crc = LOOKUP_CRC_TABLE[index as usize] ^ (crc >> 8);
@@ -252,49 +1341,447 @@ impl BytecodeSerializer {
         Ok(())
     }
 
-    fn split_to_chunks(bytes: &[u8]) -> Vec<Vec<u8>> {
-        let magic_start = START_MAGIC.to_le_bytes();
-        let magic_end = END_MAGIC.to_le_bytes();
-        let mut chunks: Vec<Vec<u8>> = vec![];
-        let mut start = 0;
-        let mut index = 0;
-        let mut chunk: Vec<u8> = vec![];
-        if (bytes.len() == 0) {
-            return vec![];
+    /// Reads the framed record starting at `offset` and returns it with the
+    /// offset of the record after it.
+    pub fn read_record(
+        bytes: &[u8],
+        offset: usize,
+    ) -> Result<(Record, usize), BytecodeSerializerError> {
+        let invalid =
+            |message: &str| BytecodeSerializerError::DeserializationError(message.to_string());
+        let rest = bytes.get(offset..).unwrap_or_default();
+        let body = rest
+            .strip_prefix(&START_MAGIC_BYTES[..])
+            .ok_or_else(|| invalid("Missing start of record"))?;
+        let (record, len) = Self::read_op(body)?;
+        if !body[len..].starts_with(&END_MAGIC_BYTES[..]) {
+            return Err(invalid("Missing end of record"));
+        }
+        Ok((
+            record,
+            offset + START_MAGIC_BYTES.len() + len + END_MAGIC_BYTES.len(),
+        ))
+    }
+
+    /// Offset of the first valid record after `offset`, used to step over a
+    /// damaged one.
+    pub fn next_valid_record(bytes: &[u8], offset: usize) -> Option<usize> {
+        (offset + 1..bytes.len())
+            .filter(|start| bytes[*start..].starts_with(&START_MAGIC_BYTES[..]))
+            .find(|start| Self::read_record(bytes, *start).is_ok())
+    }
+
+    /// A snapshot file: magic, version, LSN and timestamp, the entries, the index
+    /// definitions as records, and a CRC32 of everything before it.
+    pub fn snapshot_to_bytes(snapshot: &Snapshot) -> Vec<u8> {
+        let mut bytes = SNAPSHOT_MAGIC.to_le_bytes().to_vec();
+        bytes.push(SNAPSHOT_VERSION);
+        bytes.extend(Self::convert_to_varint(snapshot.lsn as usize));
+        Self::write_signed(&mut bytes, snapshot.timestamp);
+        bytes.extend(Self::convert_to_varint(snapshot.entries.len()));
+        for (key, value) in &snapshot.entries {
+            Self::write_bytes(&mut bytes, key);
+            Self::write_value(&mut bytes, value);
+        }
+        bytes.extend(Self::convert_to_varint(snapshot.indexes.len()));
+        for op in &snapshot.indexes {
+            bytes.extend(Self::op_to_bytes(op));
         }
-        while index <= bytes.len() - 4 {
-            if bytes[index..index + 4] == magic_start {
-                start = index + 4
+        let crc = Self::calculate_crc32(&bytes);
+        bytes.extend(crc.to_le_bytes());
+        bytes
+    }
+
+    fn write_value(bytes: &mut Vec<u8>, value: &Value) {
+        match value {
+            Value::String(string) => {
+                bytes.push(STRING_VALUE);
+                Self::write_bytes(bytes, string);
+            }
+            Value::List(list) => {
+                bytes.push(LIST_VALUE);
+                bytes.extend(Self::convert_to_varint(list.len()));
+                for item in list {
+                    Self::write_bytes(bytes, item);
+                }
+            }
+            Value::Hash(hash) => {
+                bytes.push(HASH_VALUE);
+                bytes.extend(Self::convert_to_varint(hash.len()));
+                for (field, value) in hash {
+                    Self::write_bytes(bytes, field);
+                    Self::write_bytes(bytes, value);
+                }
+            }
+            Value::SortedSet(set) => {
+                bytes.push(SORTED_SET_VALUE);
+                bytes.extend(Self::convert_to_varint(set.len()));
+                for (member, score) in set.iter() {
+                    Self::write_bytes(bytes, member);
+                    Self::write_float(bytes, score);
+                }
             }
-            if bytes[index..index + 4] == magic_end && start != 0 {
-                chunk.extend_from_slice(&bytes[start..index]);
-                chunks.push(chunk.clone());
-                chunk.clear();
+            Value::Set(set) => {
+                bytes.push(SET_VALUE);
+                bytes.extend(Self::convert_to_varint(set.len()));
+                for member in set {
+                    Self::write_bytes(bytes, member);
+                }
+            }
+            Value::Stream(stream) => {
+                bytes.push(STREAM_VALUE);
+                Self::write_stream_id(bytes, stream.last_id());
+                let entries = stream.range(StreamId::MIN, StreamId::MAX);
+                bytes.extend(Self::convert_to_varint(entries.len()));
+                for (id, fields) in &entries {
+                    Self::write_stream_id(bytes, *id);
+                    Self::write_pairs(bytes, fields);
+                }
+                bytes.extend(Self::convert_to_varint(stream.groups().len()));
+                for (name, group) in stream.groups() {
+                    Self::write_bytes(bytes, name);
+                    Self::write_stream_id(bytes, group.last_delivered);
+                    bytes.extend(Self::convert_to_varint(group.pending.len()));
+                    for (id, pending) in &group.pending {
+                        Self::write_stream_id(bytes, *id);
+                        Self::write_bytes(bytes, &pending.consumer);
+                        Self::write_signed(bytes, pending.delivered_at);
+                        bytes.extend(Self::convert_to_varint(pending.delivery_count as usize));
+                    }
+                }
             }
-            index += 1
+            Value::TimeSeries(series) => {
+                bytes.push(TIMESERIES_VALUE);
+                bytes.extend(Self::convert_to_varint(series.retention() as usize));
+                let samples = series.range(i64::MIN, i64::MAX);
+                bytes.extend(Self::convert_to_varint(samples.len()));
+                for (timestamp, value) in samples {
+                    Self::write_signed(bytes, timestamp);
+                    Self::write_float(bytes, value);
+                }
+            }
+            Value::Json(document) => {
+                bytes.push(JSON_VALUE);
+                Self::write_bytes(bytes, document.to_string().as_bytes());
+            }
+        }
+    }
+
+    pub fn snapshot_from_bytes(bytes: &[u8]) -> Result<Snapshot, BytecodeSerializerError> {
+        let invalid =
+            |message: &str| BytecodeSerializerError::DeserializationError(message.to_string());
+        let (body, crc) = bytes
+            .split_last_chunk::<4>()
+            .ok_or_else(|| invalid("Snapshot is truncated"))?;
+        Self::validate_crc32(body, u32::from_le_bytes(*crc))?;
+        let mut reader = RecordReader::new(body);
+        if reader.read_slice(4)? != SNAPSHOT_MAGIC.to_le_bytes() {
+            return Err(invalid("Not a snapshot"));
+        }
+        if reader.read_u8()? != SNAPSHOT_VERSION {
+            return Err(invalid("Unknown snapshot version"));
+        }
+        let lsn = reader.read_varint()? as u64;
+        let timestamp = reader.read_signed()?;
+        let len = reader.read_varint()?;
+        let mut entries = Vec::with_capacity(len.min(1024));
+        for _ in 0..len {
+            entries.push((reader.read_bytes()?, reader.read_value()?));
+        }
+        let len = reader.read_varint()?;
+        let mut indexes = Vec::with_capacity(len.min(1024));
+        for _ in 0..len {
+            let start = reader.position();
+            let (record, next) = Self::read_record(body, start)?;
+            reader.read_slice(next - start)?;
+            indexes.push(record.op);
+        }
+        if reader.position() != body.len() {
+            return Err(invalid("Trailing bytes after snapshot"));
+        }
+        Ok(Snapshot {
+            lsn,
+            timestamp,
+            entries,
+            indexes,
+        })
+    }
+}
+
+// Bounds checked cursor over a single record, so truncated or garbled
+// records produce an error instead of a panic.
+struct RecordReader<'a> {
+    bytes: &'a [u8],
+    index: usize,
+}
+
+impl<'a> RecordReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, index: 0 }
+    }
+
+    fn position(&self) -> usize {
+        self.index
+    }
+
+    fn truncated() -> BytecodeSerializerError {
+        BytecodeSerializerError::SerializationError("Record is truncated".to_string())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, BytecodeSerializerError> {
+        let byte = *self.bytes.get(self.index).ok_or_else(Self::truncated)?;
+        self.index += 1;
+        Ok(byte)
+    }
+
+    fn read_varint(&mut self) -> Result<usize, BytecodeSerializerError> {
+        let rest = &self.bytes[self.index..];
+        let terminated = rest.iter().take(10).any(|byte| byte & 0b1000_0000 == 0);
+        if !terminated {
+            return Err(Self::truncated());
         }
+        let (number, len) = BytecodeSerializer::convert_from_varint(rest);
+        self.index += len;
+        Ok(number)
+    }
 
-        chunks
+    fn read_signed(&mut self) -> Result<i64, BytecodeSerializerError> {
+        let zigzag = self.read_varint()? as u64;
+        Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64))
     }
 
-    pub fn recover_from_chunks(chunks: Vec<Vec<u8>>) -> Result<Vec<Op>, BytecodeSerializerError> {
-        let mut ops: Vec<Op> = vec![];
-        for chunk in chunks {
-            let op = BytecodeSerializer::op_from_bytes(chunk)?;
-            ops.push(op);
+    fn read_optional_signed(&mut self) -> Result<Option<i64>, BytecodeSerializerError> {
+        match self.read_u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.read_signed()?)),
         }
-        Ok(ops)
     }
 
+    fn read_stream_id(&mut self) -> Result<StreamId, BytecodeSerializerError> {
+        let ms = self.read_varint()? as u64;
+        let seq = self.read_varint()? as u64;
+        Ok(StreamId::new(ms, seq))
+    }
+
+    fn read_optional_stream_id(&mut self) -> Result<Option<StreamId>, BytecodeSerializerError> {
+        match self.read_u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.read_stream_id()?)),
+        }
+    }
+
+    fn read_float(&mut self) -> Result<f64, BytecodeSerializerError> {
+        let bytes = self.read_slice(8)?;
+        Ok(f64::from_le_bytes(
+            bytes.try_into().map_err(|_| Self::truncated())?,
+        ))
+    }
+
+    fn read_slice(&mut self, len: usize) -> Result<&'a [u8], BytecodeSerializerError> {
+        let end = self.index.checked_add(len).ok_or_else(Self::truncated)?;
+        let slice = self
+            .bytes
+            .get(self.index..end)
+            .ok_or_else(Self::truncated)?;
+        self.index = end;
+        Ok(slice)
+    }
+
+    fn read_bytes(&mut self) -> Result<Bytes, BytecodeSerializerError> {
+        let len = self.read_varint()?;
+        Ok(Bytes::copy_from_slice(self.read_slice(len)?))
+    }
+
+    fn read_bound(&mut self) -> Result<Bound<Bytes>, BytecodeSerializerError> {
+        match self.read_u8()? {
+            0 => Ok(Bound::Unbounded),
+            1 => Ok(Bound::Included(self.read_bytes()?)),
+            _ => Ok(Bound::Excluded(self.read_bytes()?)),
+        }
+    }
+
+    fn read_key_range(&mut self) -> Result<KeyRange, BytecodeSerializerError> {
+        match self.read_u8()? {
+            0 => Ok(KeyRange::Prefix(self.read_bytes()?)),
+            _ => Ok(KeyRange::Between(self.read_bound()?, self.read_bound()?)),
+        }
+    }
+
+    fn read_optional_filter(&mut self) -> Result<Option<Filter>, BytecodeSerializerError> {
+        match self.read_u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.read_filter()?)),
+        }
+    }
+
+    fn read_filter(&mut self) -> Result<Filter, BytecodeSerializerError> {
+        let invalid =
+            || BytecodeSerializerError::DeserializationError("Invalid filter".to_string());
+        let groups = self.read_varint()?;
+        let mut any_of = Vec::with_capacity(groups.min(1024));
+        for _ in 0..groups {
+            let len = self.read_varint()?;
+            let mut all_of = Vec::with_capacity(len.min(1024));
+            for _ in 0..len {
+                let field = match self.read_u8()? {
+                    0 => Field::Key,
+                    1 => Field::Value,
+                    _ => return Err(invalid()),
+                };
+                let predicate = match self.read_u8()? {
+                    0 => Predicate::Equals(self.read_bytes()?),
+                    1 => Predicate::NotEquals(self.read_bytes()?),
+                    2 => Predicate::Prefix(self.read_bytes()?),
+                    3 => Predicate::Contains(self.read_bytes()?),
+                    4 => Predicate::Glob(self.read_bytes()?),
+                    5 => {
+                        let comparison = match self.read_u8()? {
+                            0 => Comparison::Less,
+                            1 => Comparison::LessOrEqual,
+                            2 => Comparison::Greater,
+                            3 => Comparison::GreaterOrEqual,
+                            _ => return Err(invalid()),
+                        };
+                        Predicate::Compare(comparison, self.read_float()?)
+                    }
+                    _ => return Err(invalid()),
+                };
+                all_of.push(Condition { field, predicate });
+            }
+            any_of.push(all_of);
+        }
+        Ok(Filter { any_of })
+    }
+
+    fn read_json(&mut self) -> Result<serde_json::Value, BytecodeSerializerError> {
+        let len = self.read_varint()?;
+        serde_json::from_slice(self.read_slice(len)?)
+            .map_err(|e| BytecodeSerializerError::DeserializationError(e.to_string()))
+    }
+
+    fn read_json_path(&mut self) -> Result<JsonPath, BytecodeSerializerError> {
+        let len = self.read_varint()?;
+        let mut segments = Vec::with_capacity(len.min(1024));
+        for _ in 0..len {
+            let segment = match self.read_u8()? {
+                0 => {
+                    let len = self.read_varint()?;
+                    let field = std::str::from_utf8(self.read_slice(len)?).map_err(|_| {
+                        BytecodeSerializerError::DeserializationError(
+                            "Invalid JSON path field".to_string(),
+                        )
+                    })?;
+                    PathSegment::Field(field.to_string())
+                }
+                _ => PathSegment::Index(self.read_signed()?),
+            };
+            segments.push(segment);
+        }
+        Ok(JsonPath::from(segments))
+    }
+
+    fn read_value(&mut self) -> Result<Value, BytecodeSerializerError> {
+        let value = match self.read_u8()? {
+            STRING_VALUE => Value::String(self.read_bytes()?),
+            LIST_VALUE => Value::List(VecDeque::from(self.read_list()?)),
+            HASH_VALUE => Value::Hash(self.read_pairs()?.into_iter().collect()),
+            SORTED_SET_VALUE => {
+                let mut set = SortedSet::new();
+                for _ in 0..self.read_varint()? {
+                    set.insert(self.read_bytes()?, self.read_float()?);
+                }
+                Value::SortedSet(set)
+            }
+            SET_VALUE => Value::Set(self.read_list()?.into_iter().collect::<BTreeSet<_>>()),
+            STREAM_VALUE => {
+                let last_id = self.read_stream_id()?;
+                let mut entries = BTreeMap::new();
+                for _ in 0..self.read_varint()? {
+                    entries.insert(self.read_stream_id()?, self.read_pairs()?);
+                }
+                let mut groups = BTreeMap::new();
+                for _ in 0..self.read_varint()? {
+                    let name = self.read_bytes()?;
+                    let mut group = ConsumerGroup {
+                        last_delivered: self.read_stream_id()?,
+                        pending: BTreeMap::new(),
+                    };
+                    for _ in 0..self.read_varint()? {
+                        let id = self.read_stream_id()?;
+                        let pending = PendingEntry {
+                            consumer: self.read_bytes()?,
+                            delivered_at: self.read_signed()?,
+                            delivery_count: self.read_varint()? as u64,
+                        };
+                        group.pending.insert(id, pending);
+                    }
+                    groups.insert(name, group);
+                }
+                Value::Stream(Stream::from_parts(entries, last_id, groups))
+            }
+            TIMESERIES_VALUE => {
+                let retention = self.read_varint()? as u64;
+                let mut series = TimeSeries::default();
+                for _ in 0..self.read_varint()? {
+                    series.add(self.read_signed()?, self.read_float()?);
+                }
+                // Set last so the samples are not trimmed one by one on the way in.
+                series.set_retention(retention);
+                Value::TimeSeries(series)
+            }
+            JSON_VALUE => Value::Json(self.read_json()?),
+            _ => {
+                return Err(BytecodeSerializerError::DeserializationError(
+                    "Invalid value type".to_string(),
+                ))
+            }
+        };
+        Ok(value)
+    }
+
+    fn read_list(&mut self) -> Result<Vec<Bytes>, BytecodeSerializerError> {
+        let len = self.read_varint()?;
+        let mut items = Vec::with_capacity(len.min(1024));
+        for _ in 0..len {
+            items.push(self.read_bytes()?);
+        }
+        Ok(items)
+    }
+
+    fn read_pairs(&mut self) -> Result<Vec<(Bytes, Bytes)>, BytecodeSerializerError> {
+        let len = self.read_varint()?;
+        let mut pairs = Vec::with_capacity(len.min(1024));
+        for _ in 0..len {
+            pairs.push((self.read_bytes()?, self.read_bytes()?));
+        }
+        Ok(pairs)
+    }
+}
+
+// Recovery reads segments record by record in log.rs, tests decode whole buffers.
+#[cfg(test)]
+impl BytecodeSerializer {
+    pub fn op_from_bytes(bytes: Vec<u8>) -> Result<Op, BytecodeSerializerError> {
+        Self::read_op(&bytes).map(|(record, _)| record.op)
+    }
+
+    /// Reads records back to back, failing on the first one that is damaged.
     pub fn recover_from_bytes(bytes: &[u8]) -> Result<Vec<Op>, BytecodeSerializerError> {
-        let chunks = Self::split_to_chunks(bytes);
-        Self::recover_from_chunks(chunks)
+        let mut ops = vec![];
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let (record, next) = Self::read_record(bytes, offset)?;
+            ops.push(record.op);
+            offset = next;
+        }
+        Ok(ops)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::stream::XAddId;
 
     #[test]
     fn test_conver_to_varint() {
@@ -392,10 +1879,7 @@ mod tests {
 
     #[test]
     fn test_op_to_bytes() {
-        let op = Op::GET {
-            key: "key".to_string(),
-            timestamp: 1234567890,
-        };
+        let op = Op::new_get(1234567890, "key");
         let op_bytes = BytecodeSerializer::op_to_bytes(&op);
         let expected = vec![
             237, 200, 254, 222, 2, 210, 133, 216, 204, 4, 3, 107, 101, 121, 4, 50, 178, 183, 170,
@@ -406,10 +1890,7 @@ mod tests {
 
     #[test]
     fn test_op_from_bytes() {
-        let op = Op::GET {
-            key: "key".to_string(),
-            timestamp: 1234567890,
-        };
+        let op = Op::new_get(1234567890, "key");
         let op_result = BytecodeSerializer::op_from_bytes(vec![
             2, 210, 133, 216, 204, 4, 3, 107, 101, 121, 4, 50, 178, 183, 170,
         ])
@@ -418,24 +1899,479 @@ mod tests {
     }
 
     #[test]
-    fn test_split_to_chunks() {
-        let magic_start = START_MAGIC.to_le_bytes();
-        let magic_end = END_MAGIC.to_le_bytes();
-        let mut test_buff: Vec<u8> = vec![];
-        let mut chunks: Vec<Vec<u8>> = vec![];
-        for x in 1690..1710 {
-            let x = x as i32;
-            let slice = x.to_le_bytes().to_vec();
-            chunks.push(slice)
+    fn test_op_roundtrip_non_utf8() {
+        let op = Op::new_set(
+            0,
+            Bytes::from_static(&[0xff, 0x00, 0xfe]),
+            Bytes::from_static(&[0xc3, 0x28, 0xa0, 0xa1]),
+        );
+        let bytes = BytecodeSerializer::op_to_bytes(&op);
+        let ops = BytecodeSerializer::recover_from_bytes(&bytes).unwrap();
+        assert_eq!(ops, vec![op]);
+    }
+
+    #[test]
+    fn test_list_ops_roundtrip() {
+        let ops = vec![
+            Op::LPUSH {
+                timestamp: 0,
+                key: Bytes::from("queue"),
+                values: vec![Bytes::from("a"), Bytes::from("b")],
+            },
+            Op::RPOP {
+                timestamp: 0,
+                key: Bytes::from("queue"),
+            },
+            Op::LRANGE {
+                timestamp: 0,
+                key: Bytes::from("queue"),
+                start: 0,
+                stop: -1,
+            },
+            Op::BLPOP {
+                timestamp: 0,
+                keys: vec![Bytes::from("queue"), Bytes::from("backup")],
+                timeout: 1.5,
+            },
+        ];
+        let mut bytes = vec![];
+        for op in &ops {
+            bytes.extend(BytecodeSerializer::op_to_bytes(op));
+        }
+        let recovered = BytecodeSerializer::recover_from_bytes(&bytes).unwrap();
+        assert_eq!(recovered, ops);
+    }
+
+    #[test]
+    fn test_hash_ops_roundtrip() {
+        let ops = vec![
+            Op::HSET {
+                timestamp: 0,
+                key: Bytes::from("user:42"),
+                fields: vec![(Bytes::from("name"), Bytes::from("ada"))],
+            },
+            Op::HINCRBY {
+                timestamp: 0,
+                key: Bytes::from("user:42"),
+                field: Bytes::from("visits"),
+                increment: -3,
+            },
+            Op::HDEL {
+                timestamp: 0,
+                key: Bytes::from("user:42"),
+                fields: vec![Bytes::from("name")],
+            },
+        ];
+        let mut bytes = vec![];
+        for op in &ops {
+            bytes.extend(BytecodeSerializer::op_to_bytes(op));
+        }
+        let recovered = BytecodeSerializer::recover_from_bytes(&bytes).unwrap();
+        assert_eq!(recovered, ops);
+    }
+
+    #[test]
+    fn test_sorted_set_ops_roundtrip() {
+        let ops = vec![
+            Op::ZADD {
+                timestamp: 0,
+                key: Bytes::from("board"),
+                members: vec![(1.5, Bytes::from("a")), (-2.0, Bytes::from("b"))],
+            },
+            Op::ZREM {
+                timestamp: 0,
+                key: Bytes::from("board"),
+                members: vec![Bytes::from("a")],
+            },
+        ];
+        let mut bytes = vec![];
+        for op in &ops {
+            bytes.extend(BytecodeSerializer::op_to_bytes(op));
+        }
+        let recovered = BytecodeSerializer::recover_from_bytes(&bytes).unwrap();
+        assert_eq!(recovered, ops);
+    }
+
+    #[test]
+    fn test_set_ops_roundtrip() {
+        let ops = vec![
+            Op::SADD {
+                timestamp: 0,
+                key: Bytes::from("tags"),
+                members: vec![Bytes::from("red"), Bytes::from("blue")],
+            },
+            Op::SETOPSTORE {
+                timestamp: 0,
+                operation: SetOperation::Difference,
+                destination: Bytes::from("out"),
+                keys: vec![Bytes::from("tags"), Bytes::from("other")],
+            },
+        ];
+        let mut bytes = vec![];
+        for op in &ops {
+            bytes.extend(BytecodeSerializer::op_to_bytes(op));
+        }
+        let recovered = BytecodeSerializer::recover_from_bytes(&bytes).unwrap();
+        assert_eq!(recovered, ops);
+    }
+
+    #[test]
+    fn test_hyperloglog_ops_roundtrip() {
+        let ops = vec![
+            Op::PFADD {
+                timestamp: 0,
+                key: Bytes::from("visitors"),
+                elements: vec![Bytes::from("alice"), Bytes::from_static(&[0xff, 0x00])],
+            },
+            Op::PFADD {
+                timestamp: 1,
+                key: Bytes::from("empty"),
+                elements: vec![],
+            },
+            Op::PFCOUNT {
+                timestamp: 2,
+                keys: vec![Bytes::from("visitors"), Bytes::from("empty")],
+            },
+            Op::PFMERGE {
+                timestamp: -3,
+                destination: Bytes::from("all"),
+                keys: vec![Bytes::from("visitors"), Bytes::from("empty")],
+            },
+        ];
+        let mut bytes = vec![];
+        for op in &ops {
+            bytes.extend(BytecodeSerializer::op_to_bytes(op));
+        }
+        let recovered = BytecodeSerializer::recover_from_bytes(&bytes).unwrap();
+        assert_eq!(recovered, ops);
+    }
+
+    #[test]
+    fn test_setbit_record_is_compact() {
+        let op = Op::SETBIT {
+            timestamp: 0,
+            key: Bytes::from("dau"),
+            offset: 1_000_000,
+            bit: true,
+        };
+        let bytes = BytecodeSerializer::op_to_bytes(&op);
+        assert!(bytes.len() < 32);
+        let recovered = BytecodeSerializer::recover_from_bytes(&bytes).unwrap();
+        assert_eq!(recovered, vec![op]);
+    }
+
+    #[test]
+    fn test_stream_ops_roundtrip() {
+        let ops = vec![
+            Op::XADD {
+                timestamp: 0,
+                key: Bytes::from("events"),
+                id: None,
+                fields: vec![(Bytes::from("type"), Bytes::from("click"))],
+            },
+            Op::XGROUPCREATE {
+                timestamp: 0,
+                key: Bytes::from("events"),
+                group: Bytes::from("workers"),
+                start: None,
+            },
+            Op::XREADGROUP {
+                timestamp: 0,
+                key: Bytes::from("events"),
+                group: Bytes::from("workers"),
+                consumer: Bytes::from("w1"),
+                after: None,
+                count: Some(10),
+            },
+            Op::XACK {
+                timestamp: 0,
+                key: Bytes::from("events"),
+                group: Bytes::from("workers"),
+                ids: vec![StreamId::new(1_700_000_000_000, 0)],
+            },
+        ];
+        let mut bytes = vec![];
+        for op in &ops {
+            bytes.extend(BytecodeSerializer::op_to_bytes(op));
+        }
+        let recovered = BytecodeSerializer::recover_from_bytes(&bytes).unwrap();
+        assert_eq!(recovered, ops);
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let bytes = |text: &str| Bytes::from(text.to_string());
+        let mut scores = SortedSet::new();
+        scores.insert(bytes("alice"), 2.5);
+        let mut stream = Stream::new();
+        stream
+            .add(
+                XAddId::Explicit(StreamId::new(5, 0)),
+                vec![(bytes("type"), bytes("click"))],
+            )
+            .unwrap();
+        stream
+            .create_group(bytes("workers"), Some(StreamId::MIN))
+            .unwrap();
+        stream
+            .read_group(&bytes("workers"), bytes("w1"), None, None, 1_000)
+            .unwrap();
+        let mut series = TimeSeries::default();
+        series.add(10, 1.5);
+        series.add(20, -3.0);
+        series.set_retention(100);
+        let snapshot = Snapshot {
+            lsn: 42,
+            timestamp: 7 << 16,
+            entries: vec![
+                (
+                    bytes("string"),
+                    Value::String(Bytes::from_static(&[0, 0xff])),
+                ),
+                (
+                    bytes("list"),
+                    Value::List(VecDeque::from(vec![bytes("a"), bytes("b")])),
+                ),
+                (
+                    bytes("hash"),
+                    Value::Hash(BTreeMap::from([(bytes("f"), bytes("v"))])),
+                ),
+                (bytes("zset"), Value::SortedSet(scores)),
+                (bytes("set"), Value::Set(BTreeSet::from([bytes("m")]))),
+                (bytes("stream"), Value::Stream(stream)),
+                (bytes("series"), Value::TimeSeries(series)),
+                (
+                    bytes("json"),
+                    Value::Json(serde_json::json!({"a": [1, "x"]})),
+                ),
+            ],
+            indexes: vec![Op::SEARCHCREATE {
+                timestamp: 0,
+                prefix: bytes("doc:"),
+            }],
+        };
+        let mut encoded = BytecodeSerializer::snapshot_to_bytes(&snapshot);
+        assert_eq!(
+            BytecodeSerializer::snapshot_from_bytes(&encoded).unwrap(),
+            snapshot
+        );
+
+        encoded[10] ^= 0xff;
+        assert!(BytecodeSerializer::snapshot_from_bytes(&encoded).is_err());
+        assert!(BytecodeSerializer::snapshot_from_bytes(&encoded[..3]).is_err());
+    }
+
+    #[test]
+    fn test_timeseries_ops_roundtrip() {
+        let ops = vec![
+            Op::TSCREATE {
+                timestamp: 0,
+                key: Bytes::from("cpu"),
+                retention: 86_400_000,
+            },
+            Op::TSADD {
+                timestamp: 0,
+                key: Bytes::from("cpu"),
+                sample_time: Some(1_700_000_000_000),
+                value: 0.75,
+            },
+            Op::TSAGGREGATE {
+                timestamp: 0,
+                key: Bytes::from("cpu"),
+                from: i64::MIN,
+                to: i64::MAX,
+                aggregation: Aggregation::Max,
+                bucket: 60_000,
+            },
+        ];
+        let mut bytes = vec![];
+        for op in &ops {
+            bytes.extend(BytecodeSerializer::op_to_bytes(op));
+        }
+        let recovered = BytecodeSerializer::recover_from_bytes(&bytes).unwrap();
+        assert_eq!(recovered, ops);
+    }
+
+    #[test]
+    fn test_json_ops_roundtrip() {
+        let ops = vec![
+            Op::JSONSET {
+                timestamp: 0,
+                key: Bytes::from("config"),
+                path: "$.db[\"pool size\"]".parse().unwrap(),
+                value: serde_json::json!({"min": 1, "max": [10, 20]}),
+            },
+            Op::JSONARRAPPEND {
+                timestamp: 0,
+                key: Bytes::from("config"),
+                path: "$.hosts[-1]".parse().unwrap(),
+                values: vec![serde_json::json!("a"), serde_json::json!(null)],
+            },
+            Op::INDEXCREATE {
+                timestamp: 0,
+                name: Bytes::from("by_email"),
+                prefix: Bytes::from("user:"),
+                extractor: Extractor::JsonField("$.email".parse().unwrap()),
+            },
+            Op::INDEXCREATE {
+                timestamp: 0,
+                name: Bytes::from("by_value"),
+                prefix: Bytes::new(),
+                extractor: Extractor::Value,
+            },
+            Op::SEARCHCREATE {
+                timestamp: 0,
+                prefix: Bytes::from("doc:"),
+            },
+            Op::SEARCH {
+                timestamp: 0,
+                prefix: Bytes::from("doc:"),
+                query: Bytes::from("red apples"),
+            },
+        ];
+        let mut bytes = vec![];
+        for op in &ops {
+            bytes.extend(BytecodeSerializer::op_to_bytes(op));
+        }
+        let recovered = BytecodeSerializer::recover_from_bytes(&bytes).unwrap();
+        assert_eq!(recovered, ops);
+    }
+
+    #[test]
+    fn test_scan_roundtrip() {
+        let ops = vec![
+            Op::SCAN {
+                timestamp: 0,
+                range: KeyRange::Between(Bound::Included(Bytes::from("a")), Bound::Unbounded),
+                filter: None,
+            },
+            Op::SCAN {
+                timestamp: 0,
+                range: KeyRange::Prefix(Bytes::from("user:")),
+                filter: Some(Filter {
+                    any_of: vec![
+                        vec![Condition {
+                            field: Field::Key,
+                            predicate: Predicate::Glob(Bytes::from("user:*")),
+                        }],
+                        vec![Condition {
+                            field: Field::Value,
+                            predicate: Predicate::Compare(Comparison::Less, -2.5),
+                        }],
+                    ],
+                }),
+            },
+        ];
+        let mut bytes = vec![];
+        for op in &ops {
+            bytes.extend(BytecodeSerializer::op_to_bytes(op));
+        }
+        let recovered = BytecodeSerializer::recover_from_bytes(&bytes).unwrap();
+        assert_eq!(recovered, ops);
+    }
+
+    #[test]
+    fn test_aggregate_roundtrip() {
+        let ops = vec![
+            Op::AGGREGATE {
+                timestamp: 0,
+                function: Aggregate::Count,
+                range: KeyRange::Prefix(Bytes::from("user:")),
+                filter: None,
+            },
+            Op::AGGREGATE {
+                timestamp: 0,
+                function: Aggregate::Avg,
+                range: KeyRange::Between(Bound::Unbounded, Bound::Included(Bytes::from("m"))),
+                filter: Some(Filter {
+                    any_of: vec![vec![Condition {
+                        field: Field::Value,
+                        predicate: Predicate::Compare(Comparison::GreaterOrEqual, 3.0),
+                    }]],
+                }),
+            },
+        ];
+        let mut bytes = vec![];
+        for op in &ops {
+            bytes.extend(BytecodeSerializer::op_to_bytes(op));
         }
+        let recovered = BytecodeSerializer::recover_from_bytes(&bytes).unwrap();
+        assert_eq!(recovered, ops);
+    }
+
+    #[test]
+    fn test_op_from_bytes_truncated() {
+        let result = BytecodeSerializer::op_from_bytes(vec![1, 0, 3, 107]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_records_carry_lsn() {
+        let ops = [
+            Op::new_set(7, "a", "1"),
+            // Extended operation codes take a second header byte.
+            Op::LPUSH {
+                timestamp: 8,
+                key: Bytes::from("l"),
+                values: vec![Bytes::from("x")],
+            },
+        ];
+        let mut bytes = BytecodeSerializer::op_to_bytes(&ops[0]);
+        let legacy = bytes.len();
+        bytes.extend(BytecodeSerializer::record_to_bytes(&ops[0], 300));
+        bytes.extend(BytecodeSerializer::record_to_bytes(&ops[1], 301));
+
+        let (first, second) = BytecodeSerializer::read_record(&bytes, 0).unwrap();
+        assert_eq!(
+            first,
+            Record {
+                op: ops[0].clone(),
+                lsn: None
+            }
+        );
+        assert_eq!(second, legacy);
+        let (record, third) = BytecodeSerializer::read_record(&bytes, second).unwrap();
+        assert_eq!(
+            record,
+            Record {
+                op: ops[0].clone(),
+                lsn: Some(300)
+            }
+        );
+        let (record, _) = BytecodeSerializer::read_record(&bytes, third).unwrap();
+        assert_eq!(
+            record,
+            Record {
+                op: ops[1].clone(),
+                lsn: Some(301)
+            }
+        );
+    }
 
-        for chunk in &chunks {
-            test_buff.extend_from_slice(&magic_start);
-            test_buff.extend_from_slice(&chunk);
-            test_buff.extend_from_slice(&magic_end);
+    #[test]
+    fn test_read_records_back_to_back() {
+        // Values that contain the magics must not split records.
+        let start = Bytes::copy_from_slice(&START_MAGIC.to_le_bytes());
+        let end = Bytes::copy_from_slice(&END_MAGIC.to_le_bytes());
+        let ops = vec![
+            Op::new_set(0, "a", end.clone()),
+            Op::new_set(0, start.clone(), "b"),
+            Op::new_del(0, "a"),
+        ];
+        let mut bytes = vec![];
+        for op in &ops {
+            bytes.extend(BytecodeSerializer::op_to_bytes(op));
         }
+        assert_eq!(BytecodeSerializer::recover_from_bytes(&bytes).unwrap(), ops);
 
-        let chunks_control = BytecodeSerializer::split_to_chunks(&test_buff);
-        assert_eq!(chunks, chunks_control);
+        let (_, second) = BytecodeSerializer::read_record(&bytes, 0).unwrap();
+        let (_, third) = BytecodeSerializer::read_record(&bytes, second).unwrap();
+        bytes[second + 6] ^= 0xff;
+        assert!(BytecodeSerializer::read_record(&bytes, second).is_err());
+        assert!(BytecodeSerializer::recover_from_bytes(&bytes).is_err());
+        assert_eq!(
+            BytecodeSerializer::next_valid_record(&bytes, second),
+            Some(third)
+        );
     }
 }
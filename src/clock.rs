@@ -0,0 +1,100 @@
+/// Source of wall clock time, in milliseconds since the epoch.
+pub trait Clock: Send + Sync {
+    fn now_millis(&self) -> i64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        chrono::Utc::now().timestamp_millis()
+    }
+}
+
+// Low bits of a timestamp count events within the same millisecond.
+const LOGICAL_BITS: u32 = 16;
+
+/// Milliseconds since the epoch a hybrid timestamp was taken at.
+pub fn physical_millis(timestamp: i64) -> i64 {
+    timestamp >> LOGICAL_BITS
+}
+
+/// Hybrid logical clock: wall clock milliseconds in the high bits and a counter
+/// in the low 16. Every reading is greater than the previous one, when the wall
+/// clock stalls or goes back the counter advances instead, carrying into the
+/// millisecond if it overflows.
+pub struct HybridClock {
+    wall: Box<dyn Clock>,
+    last: i64,
+}
+
+impl HybridClock {
+    pub fn new(wall: Box<dyn Clock>) -> Self {
+        Self { wall, last: 0 }
+    }
+
+    pub fn now(&mut self) -> i64 {
+        let wall = self.wall.now_millis().max(0) << LOGICAL_BITS;
+        self.last = wall.max(self.last + 1);
+        self.last
+    }
+
+    /// Moves the clock past a timestamp taken elsewhere, such as one read back
+    /// from the WAL, so that later readings still sort after it.
+    pub fn observe(&mut self, timestamp: i64) {
+        self.last = self.last.max(timestamp);
+    }
+}
+
+impl Default for HybridClock {
+    fn default() -> Self {
+        Self::new(Box::new(SystemClock))
+    }
+}
+
+/// Clock that only moves when told to, shared between a test and the store.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct ManualClock(std::sync::Arc<std::sync::atomic::AtomicI64>);
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn set(&self, millis: i64) {
+        self.0.store(millis, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now_millis(&self) -> i64 {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hybrid_clock_is_monotonic() {
+        let wall = ManualClock::default();
+        let mut clock = HybridClock::new(Box::new(wall.clone()));
+        wall.set(1_000);
+        let first = clock.now();
+        assert_eq!(physical_millis(first), 1_000);
+
+        // Same millisecond and a jump back both only advance the counter.
+        let second = clock.now();
+        wall.set(400);
+        let third = clock.now();
+        assert!(first < second && second < third);
+        assert_eq!(physical_millis(third), 1_000);
+
+        // Once the wall clock passes the last reading it is followed again.
+        wall.set(1_001);
+        assert_eq!(clock.now(), 1_001 << LOGICAL_BITS);
+
+        clock.observe(5_000 << LOGICAL_BITS);
+        assert!(clock.now() > 5_000 << LOGICAL_BITS);
+    }
+}
@@ -1,6 +1,10 @@
 use std::str::Utf8Error;
 use thiserror::Error;
 
+use crate::index::IndexError;
+use crate::types::json::JsonError;
+use crate::types::stream::StreamError;
+
 #[derive(Error, Debug)]
 pub enum LogError {
     #[error("UTF8 error")]
@@ -20,6 +24,33 @@ pub enum LogError {
 pub enum MemoryLayerErrors {
     #[error("Something gone wrong: {0}")]
     GenericError(String),
+
+    #[error("Operation against a key holding the wrong kind of value")]
+    WrongType,
+
+    #[error("Value is not an integer or out of range")]
+    NotAnInteger,
+
+    #[error("Stream error: {0}")]
+    StreamError(#[from] StreamError),
+
+    #[error("JSON error: {0}")]
+    JsonError(#[from] JsonError),
+
+    #[error("Index error: {0}")]
+    IndexError(#[from] IndexError),
+
+    #[error("Write was applied but could not be written to the WAL")]
+    NotDurable,
+
+    #[error("A checkpoint is already running")]
+    CheckpointRunning,
+
+    #[error("Checkpoint failed: {0}")]
+    CheckpointFailed(String),
+
+    #[error("Store is read-only")]
+    ReadOnly,
 }
 
 #[derive(Error, Debug)]
@@ -53,6 +84,9 @@ pub enum ParserError {
 
     #[error("No operations found")]
     NoOperations,
+
+    #[error("End of input")]
+    EndOfInput,
 }
 
 #[derive(Error, Debug)]
@@ -62,12 +96,40 @@ pub enum WALError {
 
     #[error("Error reading from WAL")]
     ReadError(String),
+
+    #[error("Invalid WAL manifest: {0}")]
+    Manifest(String),
+
+    #[error("WAL segment {0} does not match the manifest")]
+    ManifestMismatch(String),
+
+    #[error("WAL starts at LSN {first} but the newest snapshot needs records from {from}")]
+    MissingRecords { from: u64, first: u64 },
+
+    #[error("Corrupt record in WAL segment {segment} at byte {offset}: {reason}")]
+    Corrupted {
+        segment: String,
+        offset: usize,
+        reason: String,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("Error writing or reading snapshot")]
+    IO(#[from] std::io::Error),
+
+    #[error("Invalid snapshot {path}: {reason}")]
+    Invalid { path: String, reason: String },
 }
 
 #[derive(Error, Debug)]
 pub enum FileSystemError {
     #[error("Error creating directory {0}")]
     CreateDir(String),
+
+    #[error("Data directory {0} already holds data")]
+    NotEmpty(String),
 }
 
 #[derive(Error, Debug)]
@@ -82,13 +144,29 @@ pub enum PersistentLayerError {
     GenericError(String),
 }
 
-#[derive(Debug)]
+#[derive(Error, Debug)]
 pub enum KVStoreError {
+    #[error(transparent)]
     MemoryLayerError(MemoryLayerErrors),
+
+    #[error(transparent)]
     BytecodeSerializerError(BytecodeSerializerError),
+
+    #[error(transparent)]
     ParserError(ParserError),
+
+    #[error(transparent)]
     WALError(WALError),
+
+    #[error(transparent)]
+    SnapshotError(SnapshotError),
+
+    #[error(transparent)]
     FileSystemError(FileSystemError),
+
+    #[error(transparent)]
     PersistentLayerError(PersistentLayerError),
+
+    #[error(transparent)]
     LogError(LogError),
 }
@@ -1,63 +1,1734 @@
-use crate::operation::Op;
-use std::collections::BTreeMap;
+use crate::clock::physical_millis;
+use crate::errors::MemoryLayerErrors;
+use crate::index::{Extractor, IndexError, SecondaryIndex};
+use crate::operation::{Op, SetOperation};
+use crate::query::{Accumulator, Aggregate, KeyRange};
+use crate::reply::Reply;
+use crate::search::FullTextIndex;
+use crate::types::bitmap::{self, BitOperation};
+use crate::types::hyperloglog::HyperLogLog;
+use crate::types::json::{JsonError, JsonPath};
+use crate::types::sorted_set::SortedSet;
+use crate::types::stream::{Stream, StreamError, XAddId};
+use crate::types::timeseries::TimeSeries;
+use crate::types::Value;
+use bytes::{Bytes, BytesMut};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::ops::Bound;
+use std::str;
 
 pub struct InMemoryLayer {
-    store: BTreeMap<String, String>,
+    store: BTreeMap<Bytes, Value>,
+    indexes: BTreeMap<Bytes, SecondaryIndex>,
+    // Full-text indexes by key prefix.
+    text_indexes: BTreeMap<Bytes, FullTextIndex>,
 }
 
 impl InMemoryLayer {
     pub fn new() -> Self {
         Self {
             store: BTreeMap::new(),
+            indexes: BTreeMap::new(),
+            text_indexes: BTreeMap::new(),
         }
     }
 
-    fn set(&mut self, key: String, value: String) {
-        self.store.insert(key, value);
+    fn set(&mut self, key: Bytes, value: Bytes) {
+        self.store.insert(key, Value::String(value));
     }
 
-    fn get(&self, key: String) -> Option<String> {
-        self.store.get(&key).cloned()
+    fn get(&self, key: Bytes) -> Result<Option<Bytes>, MemoryLayerErrors> {
+        match self.store.get(&key) {
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(MemoryLayerErrors::WrongType),
+            None => Ok(None),
+        }
     }
 
-    fn del(&mut self, key: String) -> Option<String> {
+    fn del(&mut self, key: Bytes) -> Option<Value> {
         self.store.remove(&key)
     }
 
-    pub fn eval(&mut self, op: Op) -> Option<String> {
+    fn list(&self, key: &Bytes) -> Result<Option<&VecDeque<Bytes>>, MemoryLayerErrors> {
+        match self.store.get(key) {
+            Some(Value::List(list)) => Ok(Some(list)),
+            Some(_) => Err(MemoryLayerErrors::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn list_or_default(&mut self, key: Bytes) -> Result<&mut VecDeque<Bytes>, MemoryLayerErrors> {
+        match self
+            .store
+            .entry(key)
+            .or_insert_with(|| Value::List(VecDeque::new()))
+        {
+            Value::List(list) => Ok(list),
+            _ => Err(MemoryLayerErrors::WrongType),
+        }
+    }
+
+    fn push(
+        &mut self,
+        key: Bytes,
+        values: Vec<Bytes>,
+        front: bool,
+    ) -> Result<i64, MemoryLayerErrors> {
+        let list = self.list_or_default(key)?;
+        for value in values {
+            if front {
+                list.push_front(value);
+            } else {
+                list.push_back(value);
+            }
+        }
+        Ok(list.len() as i64)
+    }
+
+    fn pop(&mut self, key: Bytes, front: bool) -> Result<Option<Bytes>, MemoryLayerErrors> {
+        let list = match self.store.get_mut(&key) {
+            Some(Value::List(list)) => list,
+            Some(_) => return Err(MemoryLayerErrors::WrongType),
+            None => return Ok(None),
+        };
+        let value = if front {
+            list.pop_front()
+        } else {
+            list.pop_back()
+        };
+        // Empty lists are not kept around, same as a key that was never pushed to.
+        if list.is_empty() {
+            self.store.remove(&key);
+        }
+        Ok(value)
+    }
+
+    // Pops from the first key holding a non-empty list, this is the non-blocking half of BLPOP/BRPOP.
+    fn blocking_pop(
+        &mut self,
+        keys: Vec<Bytes>,
+        front: bool,
+    ) -> Result<Option<(Bytes, Bytes)>, MemoryLayerErrors> {
+        for key in keys {
+            if self.list(&key)?.is_some() {
+                return Ok(self.pop(key.clone(), front)?.map(|value| (key, value)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn lrange(&self, key: Bytes, start: i64, stop: i64) -> Result<Vec<Bytes>, MemoryLayerErrors> {
+        let list = match self.list(&key)? {
+            Some(list) => list,
+            None => return Ok(vec![]),
+        };
+        let len = list.len() as i64;
+        let start = if start < 0 {
+            (len + start).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 {
+            len + stop
+        } else {
+            stop.min(len - 1)
+        };
+        if start > stop || start >= len {
+            return Ok(vec![]);
+        }
+        Ok(list
+            .range(start as usize..=stop as usize)
+            .cloned()
+            .collect())
+    }
+
+    pub fn llen(&self, key: Bytes) -> Result<i64, MemoryLayerErrors> {
+        Ok(self.list(&key)?.map_or(0, |list| list.len() as i64))
+    }
+
+    fn hash(&self, key: &Bytes) -> Result<Option<&BTreeMap<Bytes, Bytes>>, MemoryLayerErrors> {
+        match self.store.get(key) {
+            Some(Value::Hash(hash)) => Ok(Some(hash)),
+            Some(_) => Err(MemoryLayerErrors::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn hash_or_default(
+        &mut self,
+        key: Bytes,
+    ) -> Result<&mut BTreeMap<Bytes, Bytes>, MemoryLayerErrors> {
+        match self
+            .store
+            .entry(key)
+            .or_insert_with(|| Value::Hash(BTreeMap::new()))
+        {
+            Value::Hash(hash) => Ok(hash),
+            _ => Err(MemoryLayerErrors::WrongType),
+        }
+    }
+
+    fn hset(&mut self, key: Bytes, fields: Vec<(Bytes, Bytes)>) -> Result<i64, MemoryLayerErrors> {
+        let hash = self.hash_or_default(key)?;
+        let mut added = 0;
+        for (field, value) in fields {
+            if hash.insert(field, value).is_none() {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    fn hget(&self, key: Bytes, field: Bytes) -> Result<Option<Bytes>, MemoryLayerErrors> {
+        Ok(self.hash(&key)?.and_then(|hash| hash.get(&field).cloned()))
+    }
+
+    fn hdel(&mut self, key: Bytes, fields: Vec<Bytes>) -> Result<i64, MemoryLayerErrors> {
+        let hash = match self.store.get_mut(&key) {
+            Some(Value::Hash(hash)) => hash,
+            Some(_) => return Err(MemoryLayerErrors::WrongType),
+            None => return Ok(0),
+        };
+        let removed = fields
+            .iter()
+            .filter(|field| hash.remove(*field).is_some())
+            .count();
+        if hash.is_empty() {
+            self.store.remove(&key);
+        }
+        Ok(removed as i64)
+    }
+
+    fn hgetall(&self, key: Bytes) -> Result<Vec<(Bytes, Bytes)>, MemoryLayerErrors> {
+        Ok(self.hash(&key)?.map_or(vec![], |hash| {
+            hash.iter()
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect()
+        }))
+    }
+
+    fn hincrby(
+        &mut self,
+        key: Bytes,
+        field: Bytes,
+        increment: i64,
+    ) -> Result<i64, MemoryLayerErrors> {
+        let hash = self.hash_or_default(key)?;
+        let current = match hash.get(&field) {
+            Some(value) => str::from_utf8(value)
+                .ok()
+                .and_then(|value| value.parse::<i64>().ok())
+                .ok_or(MemoryLayerErrors::NotAnInteger)?,
+            None => 0,
+        };
+        let updated = current
+            .checked_add(increment)
+            .ok_or(MemoryLayerErrors::NotAnInteger)?;
+        hash.insert(field, Bytes::from(updated.to_string()));
+        Ok(updated)
+    }
+
+    fn hkeys(&self, key: Bytes) -> Result<Vec<Bytes>, MemoryLayerErrors> {
+        Ok(self
+            .hash(&key)?
+            .map_or(vec![], |hash| hash.keys().cloned().collect()))
+    }
+
+    fn sorted_set(&self, key: &Bytes) -> Result<Option<&SortedSet>, MemoryLayerErrors> {
+        match self.store.get(key) {
+            Some(Value::SortedSet(set)) => Ok(Some(set)),
+            Some(_) => Err(MemoryLayerErrors::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn zadd(&mut self, key: Bytes, members: Vec<(f64, Bytes)>) -> Result<i64, MemoryLayerErrors> {
+        let set = match self
+            .store
+            .entry(key)
+            .or_insert_with(|| Value::SortedSet(SortedSet::new()))
+        {
+            Value::SortedSet(set) => set,
+            _ => return Err(MemoryLayerErrors::WrongType),
+        };
+        let added = members
+            .into_iter()
+            .filter(|(score, member)| set.insert(member.clone(), *score))
+            .count();
+        Ok(added as i64)
+    }
+
+    fn zrem(&mut self, key: Bytes, members: Vec<Bytes>) -> Result<i64, MemoryLayerErrors> {
+        let set = match self.store.get_mut(&key) {
+            Some(Value::SortedSet(set)) => set,
+            Some(_) => return Err(MemoryLayerErrors::WrongType),
+            None => return Ok(0),
+        };
+        let removed = members.iter().filter(|member| set.remove(member)).count();
+        if set.is_empty() {
+            self.store.remove(&key);
+        }
+        Ok(removed as i64)
+    }
+
+    fn zscore(&self, key: Bytes, member: Bytes) -> Result<Option<f64>, MemoryLayerErrors> {
+        Ok(self.sorted_set(&key)?.and_then(|set| set.score(&member)))
+    }
+
+    fn zrange(&self, key: Bytes, start: i64, stop: i64) -> Result<Vec<Bytes>, MemoryLayerErrors> {
+        Ok(self.sorted_set(&key)?.map_or(vec![], |set| {
+            set.range_by_rank(start, stop)
+                .into_iter()
+                .map(|(member, _)| member)
+                .collect()
+        }))
+    }
+
+    fn zrangebyscore(
+        &self,
+        key: Bytes,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<Bytes>, MemoryLayerErrors> {
+        Ok(self.sorted_set(&key)?.map_or(vec![], |set| {
+            set.range_by_score(min, max)
+                .into_iter()
+                .map(|(member, _)| member)
+                .collect()
+        }))
+    }
+
+    fn zrank(&self, key: Bytes, member: Bytes) -> Result<Option<usize>, MemoryLayerErrors> {
+        Ok(self.sorted_set(&key)?.and_then(|set| set.rank(&member)))
+    }
+
+    fn set_members(&self, key: &Bytes) -> Result<Option<&BTreeSet<Bytes>>, MemoryLayerErrors> {
+        match self.store.get(key) {
+            Some(Value::Set(set)) => Ok(Some(set)),
+            Some(_) => Err(MemoryLayerErrors::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn sadd(&mut self, key: Bytes, members: Vec<Bytes>) -> Result<i64, MemoryLayerErrors> {
+        let set = match self
+            .store
+            .entry(key)
+            .or_insert_with(|| Value::Set(BTreeSet::new()))
+        {
+            Value::Set(set) => set,
+            _ => return Err(MemoryLayerErrors::WrongType),
+        };
+        let added = members
+            .into_iter()
+            .filter(|member| set.insert(member.clone()))
+            .count();
+        Ok(added as i64)
+    }
+
+    fn srem(&mut self, key: Bytes, members: Vec<Bytes>) -> Result<i64, MemoryLayerErrors> {
+        let set = match self.store.get_mut(&key) {
+            Some(Value::Set(set)) => set,
+            Some(_) => return Err(MemoryLayerErrors::WrongType),
+            None => return Ok(0),
+        };
+        let removed = members.iter().filter(|member| set.remove(*member)).count();
+        if set.is_empty() {
+            self.store.remove(&key);
+        }
+        Ok(removed as i64)
+    }
+
+    fn sismember(&self, key: Bytes, member: Bytes) -> Result<bool, MemoryLayerErrors> {
+        Ok(self
+            .set_members(&key)?
+            .is_some_and(|set| set.contains(&member)))
+    }
+
+    fn smembers(&self, key: Bytes) -> Result<Vec<Bytes>, MemoryLayerErrors> {
+        Ok(self
+            .set_members(&key)?
+            .map_or(vec![], |set| set.iter().cloned().collect()))
+    }
+
+    fn scard(&self, key: Bytes) -> Result<i64, MemoryLayerErrors> {
+        Ok(self.set_members(&key)?.map_or(0, |set| set.len() as i64))
+    }
+
+    // Missing keys behave as empty sets, as in every other set command.
+    fn combine_sets(
+        &self,
+        operation: SetOperation,
+        keys: &[Bytes],
+    ) -> Result<BTreeSet<Bytes>, MemoryLayerErrors> {
+        let mut sets = Vec::with_capacity(keys.len());
+        for key in keys {
+            sets.push(self.set_members(key)?);
+        }
+        let mut sets = sets.into_iter();
+        let mut result = match sets.next() {
+            Some(first) => first.cloned().unwrap_or_default(),
+            None => return Ok(BTreeSet::new()),
+        };
+        for set in sets {
+            let empty = BTreeSet::new();
+            let set = set.unwrap_or(&empty);
+            match operation {
+                SetOperation::Union => result.extend(set.iter().cloned()),
+                SetOperation::Intersection => result.retain(|member| set.contains(member)),
+                SetOperation::Difference => result.retain(|member| !set.contains(member)),
+            }
+        }
+        Ok(result)
+    }
+
+    fn combine_sets_store(
+        &mut self,
+        operation: SetOperation,
+        destination: Bytes,
+        keys: &[Bytes],
+    ) -> Result<i64, MemoryLayerErrors> {
+        let result = self.combine_sets(operation, keys)?;
+        let len = result.len() as i64;
+        if result.is_empty() {
+            self.store.remove(&destination);
+        } else {
+            self.store.insert(destination, Value::Set(result));
+        }
+        Ok(len)
+    }
+
+    fn string_or_empty(&self, key: &Bytes) -> Result<Bytes, MemoryLayerErrors> {
+        Ok(self.get(key.clone())?.unwrap_or_default())
+    }
+
+    fn setbit(&mut self, key: Bytes, offset: u64, bit: bool) -> Result<bool, MemoryLayerErrors> {
+        let value = match self
+            .store
+            .entry(key)
+            .or_insert_with(|| Value::String(Bytes::new()))
+        {
+            Value::String(value) => value,
+            _ => return Err(MemoryLayerErrors::WrongType),
+        };
+        // Mutates in place when nothing else holds the buffer, copies otherwise.
+        let mut buffer = std::mem::take(value)
+            .try_into_mut()
+            .unwrap_or_else(|shared| BytesMut::from(&shared[..]));
+        let previous = bitmap::set_bit(&mut buffer, offset, bit);
+        *value = buffer.freeze();
+        Ok(previous)
+    }
+
+    fn bitop(
+        &mut self,
+        operation: BitOperation,
+        destination: Bytes,
+        keys: &[Bytes],
+    ) -> Result<i64, MemoryLayerErrors> {
+        let mut sources = Vec::with_capacity(keys.len());
+        for key in keys {
+            sources.push(self.string_or_empty(key)?);
+        }
+        let sources: Vec<&[u8]> = sources.iter().map(|source| &source[..]).collect();
+        let result = bitmap::bit_op(operation, &sources);
+        let len = result.len() as i64;
+        if result.is_empty() {
+            self.store.remove(&destination);
+        } else {
+            self.store
+                .insert(destination, Value::String(Bytes::from(result)));
+        }
+        Ok(len)
+    }
+
+    // HyperLogLogs live in plain string values, anything else is a type error.
+    fn hyperloglog(&self, key: &Bytes) -> Result<Option<HyperLogLog>, MemoryLayerErrors> {
+        match self.get(key.clone())? {
+            Some(value) => HyperLogLog::from_bytes(&value)
+                .map(Some)
+                .ok_or(MemoryLayerErrors::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn pfadd(&mut self, key: Bytes, elements: Vec<Bytes>) -> Result<bool, MemoryLayerErrors> {
+        let (mut hll, mut changed) = match self.hyperloglog(&key)? {
+            Some(hll) => (hll, false),
+            None => (HyperLogLog::new(), true),
+        };
+        for element in elements {
+            changed |= hll.add(&element);
+        }
+        if changed {
+            self.set(key, Bytes::from(hll.to_bytes()));
+        }
+        Ok(changed)
+    }
+
+    fn merged_hyperloglog(&self, keys: &[Bytes]) -> Result<HyperLogLog, MemoryLayerErrors> {
+        let mut merged = HyperLogLog::new();
+        for key in keys {
+            if let Some(hll) = self.hyperloglog(key)? {
+                merged.merge(&hll);
+            }
+        }
+        Ok(merged)
+    }
+
+    fn stream(&self, key: &Bytes) -> Result<Option<&Stream>, MemoryLayerErrors> {
+        match self.store.get(key) {
+            Some(Value::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(MemoryLayerErrors::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn stream_or_default(&mut self, key: Bytes) -> Result<&mut Stream, MemoryLayerErrors> {
+        match self
+            .store
+            .entry(key)
+            .or_insert_with(|| Value::Stream(Stream::new()))
+        {
+            Value::Stream(stream) => Ok(stream),
+            _ => Err(MemoryLayerErrors::WrongType),
+        }
+    }
+
+    fn stream_mut(&mut self, key: &Bytes) -> Result<&mut Stream, MemoryLayerErrors> {
+        match self.store.get_mut(key) {
+            Some(Value::Stream(stream)) => Ok(stream),
+            Some(_) => Err(MemoryLayerErrors::WrongType),
+            None => Err(StreamError::NoGroup.into()),
+        }
+    }
+
+    fn timeseries(&self, key: &Bytes) -> Result<Option<&TimeSeries>, MemoryLayerErrors> {
+        match self.store.get(key) {
+            Some(Value::TimeSeries(series)) => Ok(Some(series)),
+            Some(_) => Err(MemoryLayerErrors::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn timeseries_or_default(&mut self, key: Bytes) -> Result<&mut TimeSeries, MemoryLayerErrors> {
+        match self
+            .store
+            .entry(key)
+            .or_insert_with(|| Value::TimeSeries(TimeSeries::default()))
+        {
+            Value::TimeSeries(series) => Ok(series),
+            _ => Err(MemoryLayerErrors::WrongType),
+        }
+    }
+
+    fn json(&self, key: &Bytes) -> Result<Option<&serde_json::Value>, MemoryLayerErrors> {
+        match self.store.get(key) {
+            Some(Value::Json(document)) => Ok(Some(document)),
+            Some(_) => Err(MemoryLayerErrors::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn json_mut(&mut self, key: &Bytes) -> Result<&mut serde_json::Value, MemoryLayerErrors> {
+        match self.store.get_mut(key) {
+            Some(Value::Json(document)) => Ok(document),
+            Some(_) => Err(MemoryLayerErrors::WrongType),
+            None => Err(JsonError::PathNotFound.into()),
+        }
+    }
+
+    fn json_set(
+        &mut self,
+        key: Bytes,
+        path: JsonPath,
+        value: serde_json::Value,
+    ) -> Result<(), MemoryLayerErrors> {
+        if self.json(&key)?.is_none() {
+            if !path.is_root() {
+                return Err(JsonError::NotAtRoot.into());
+            }
+            self.store.insert(key, Value::Json(value));
+            return Ok(());
+        }
+        Ok(path.set(self.json_mut(&key)?, value)?)
+    }
+
+    fn create_index(
+        &mut self,
+        name: Bytes,
+        prefix: Bytes,
+        extractor: Extractor,
+    ) -> Result<(), MemoryLayerErrors> {
+        if self.indexes.contains_key(&name) {
+            return Err(IndexError::Exists.into());
+        }
+        let mut index = SecondaryIndex::new(prefix.clone(), extractor);
+        for (key, value) in self.store.range(prefix..) {
+            if !index.covers(key) {
+                break;
+            }
+            index.update(key, Some(value));
+        }
+        self.indexes.insert(name, index);
+        Ok(())
+    }
+
+    fn create_text_index(&mut self, prefix: Bytes) -> Result<(), MemoryLayerErrors> {
+        if self.text_indexes.contains_key(&prefix) {
+            return Err(IndexError::Exists.into());
+        }
+        let mut index = FullTextIndex::new(prefix.clone());
+        for (key, value) in self.store.range(prefix.clone()..) {
+            if !index.covers(key) {
+                break;
+            }
+            index.update(key, Some(value));
+        }
+        self.text_indexes.insert(prefix, index);
+        Ok(())
+    }
+
+    fn search(&self, prefix: &Bytes, query: &[u8]) -> Result<Vec<Bytes>, MemoryLayerErrors> {
+        let index = self.text_indexes.get(prefix).ok_or(IndexError::NotFound)?;
+        Ok(index
+            .search(&String::from_utf8_lossy(query))
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect())
+    }
+
+    fn scan<'a>(&'a self, range: &'a KeyRange) -> impl Iterator<Item = (&'a Bytes, &'a Value)> {
+        self.store
+            .range((range.start(), Bound::Unbounded))
+            .take_while(move |(key, _)| range.before_end(key))
+    }
+
+    fn find_indexed(&self, name: &Bytes, value: &[u8]) -> Result<Vec<Bytes>, MemoryLayerErrors> {
+        let index = self.indexes.get(name).ok_or(IndexError::NotFound)?;
+        Ok(index.find(value))
+    }
+
+    /// Applies the operation and keeps every secondary index in step with it.
+    pub fn eval(&mut self, op: Op) -> Result<Reply, MemoryLayerErrors> {
+        let written = if self.indexes.is_empty() && self.text_indexes.is_empty() {
+            vec![]
+        } else {
+            op.written_keys()
+        };
+        let result = self.apply(op);
+        for key in written {
+            let value = self.store.get(&key);
+            for index in self.indexes.values_mut() {
+                index.update(&key, value);
+            }
+            for index in self.text_indexes.values_mut() {
+                index.update(&key, value);
+            }
+        }
+        result
+    }
+
+    fn apply(&mut self, op: Op) -> Result<Reply, MemoryLayerErrors> {
         let result = match op {
             Op::SET { key, value, .. } => {
                 self.set(key.clone(), value);
-                Some(key)
+                Reply::Bulk(key)
+            }
+            Op::GET { key, .. } => self.get(key)?.into(),
+            Op::DEL { key, .. } => self.del(key).map_or(Reply::Nil, Value::into_reply),
+            Op::LPUSH { key, values, .. } => Reply::Integer(self.push(key, values, true)?),
+            Op::RPUSH { key, values, .. } => Reply::Integer(self.push(key, values, false)?),
+            Op::LPOP { key, .. } => self.pop(key, true)?.into(),
+            Op::RPOP { key, .. } => self.pop(key, false)?.into(),
+            Op::LRANGE {
+                key, start, stop, ..
+            } => Reply::Array(
+                self.lrange(key, start, stop)?
+                    .into_iter()
+                    .map(Reply::Bulk)
+                    .collect(),
+            ),
+            Op::LLEN { key, .. } => Reply::Integer(self.llen(key)?),
+            Op::BLPOP { keys, .. } => {
+                self.blocking_pop(keys, true)?.map_or(Reply::Nil, |(k, v)| {
+                    Reply::Array(vec![Reply::Bulk(k), Reply::Bulk(v)])
+                })
+            }
+            Op::BRPOP { keys, .. } => self
+                .blocking_pop(keys, false)?
+                .map_or(Reply::Nil, |(k, v)| {
+                    Reply::Array(vec![Reply::Bulk(k), Reply::Bulk(v)])
+                }),
+            Op::HSET { key, fields, .. } => Reply::Integer(self.hset(key, fields)?),
+            Op::HGET { key, field, .. } => self.hget(key, field)?.into(),
+            Op::HDEL { key, fields, .. } => Reply::Integer(self.hdel(key, fields)?),
+            Op::HGETALL { key, .. } => Reply::Array(
+                self.hgetall(key)?
+                    .into_iter()
+                    .flat_map(|(field, value)| [Reply::Bulk(field), Reply::Bulk(value)])
+                    .collect(),
+            ),
+            Op::HINCRBY {
+                key,
+                field,
+                increment,
+                ..
+            } => Reply::Integer(self.hincrby(key, field, increment)?),
+            Op::HKEYS { key, .. } => {
+                Reply::Array(self.hkeys(key)?.into_iter().map(Reply::Bulk).collect())
+            }
+            Op::ZADD { key, members, .. } => Reply::Integer(self.zadd(key, members)?),
+            Op::ZREM { key, members, .. } => Reply::Integer(self.zrem(key, members)?),
+            Op::ZSCORE { key, member, .. } => {
+                self.zscore(key, member)?.map_or(Reply::Nil, Reply::score)
+            }
+            Op::ZRANGE {
+                key, start, stop, ..
+            } => Reply::Array(
+                self.zrange(key, start, stop)?
+                    .into_iter()
+                    .map(Reply::Bulk)
+                    .collect(),
+            ),
+            Op::ZRANGEBYSCORE { key, min, max, .. } => Reply::Array(
+                self.zrangebyscore(key, min, max)?
+                    .into_iter()
+                    .map(Reply::Bulk)
+                    .collect(),
+            ),
+            Op::ZRANK { key, member, .. } => self
+                .zrank(key, member)?
+                .map_or(Reply::Nil, |rank| Reply::Integer(rank as i64)),
+            Op::SADD { key, members, .. } => Reply::Integer(self.sadd(key, members)?),
+            Op::SREM { key, members, .. } => Reply::Integer(self.srem(key, members)?),
+            Op::SISMEMBER { key, member, .. } => {
+                Reply::Integer(self.sismember(key, member)? as i64)
+            }
+            Op::SMEMBERS { key, .. } => {
+                Reply::Array(self.smembers(key)?.into_iter().map(Reply::Bulk).collect())
+            }
+            Op::SCARD { key, .. } => Reply::Integer(self.scard(key)?),
+            Op::SETOP {
+                operation, keys, ..
+            } => Reply::Array(
+                self.combine_sets(operation, &keys)?
+                    .into_iter()
+                    .map(Reply::Bulk)
+                    .collect(),
+            ),
+            Op::SETOPSTORE {
+                operation,
+                destination,
+                keys,
+                ..
+            } => Reply::Integer(self.combine_sets_store(operation, destination, &keys)?),
+            Op::SETBIT {
+                key, offset, bit, ..
+            } => Reply::Integer(self.setbit(key, offset, bit)? as i64),
+            Op::GETBIT { key, offset, .. } => {
+                Reply::Integer(bitmap::get_bit(&self.string_or_empty(&key)?, offset) as i64)
+            }
+            Op::BITCOUNT { key, range, .. } => {
+                Reply::Integer(bitmap::bit_count(&self.string_or_empty(&key)?, range) as i64)
+            }
+            Op::BITPOS {
+                key,
+                bit,
+                start,
+                end,
+                ..
+            } => Reply::Integer(bitmap::bit_pos(
+                &self.string_or_empty(&key)?,
+                bit,
+                start,
+                end,
+            )),
+            Op::BITOP {
+                operation,
+                destination,
+                keys,
+                ..
+            } => Reply::Integer(self.bitop(operation, destination, &keys)?),
+            Op::PFADD { key, elements, .. } => Reply::Integer(self.pfadd(key, elements)? as i64),
+            Op::PFCOUNT { keys, .. } => {
+                Reply::Integer(self.merged_hyperloglog(&keys)?.count() as i64)
+            }
+            Op::PFMERGE {
+                destination, keys, ..
+            } => {
+                let mut sources = vec![destination.clone()];
+                sources.extend(keys);
+                let merged = self.merged_hyperloglog(&sources)?;
+                self.set(destination.clone(), Bytes::from(merged.to_bytes()));
+                Reply::Bulk(destination)
+            }
+            Op::XADD {
+                timestamp,
+                key,
+                id,
+                fields,
+            } => {
+                // Validate before creating the key, a rejected XADD leaves no empty stream.
+                let last_id = self
+                    .stream(&key)?
+                    .map_or(Stream::default().last_id(), Stream::last_id);
+                if id.is_some_and(|id| id <= last_id) {
+                    return Err(StreamError::IdTooSmall.into());
+                }
+                let id = match id {
+                    Some(id) => XAddId::Explicit(id),
+                    None => XAddId::Auto {
+                        ms: physical_millis(timestamp).max(0) as u64,
+                    },
+                };
+                let id = self.stream_or_default(key)?.add(id, fields)?;
+                Reply::Bulk(Bytes::from(id.to_string()))
+            }
+            Op::XRANGE {
+                key, start, end, ..
+            } => Reply::Array(self.stream(&key)?.map_or(vec![], |stream| {
+                stream
+                    .range(start, end)
+                    .into_iter()
+                    .map(Reply::stream_entry)
+                    .collect()
+            })),
+            Op::XREAD {
+                key, after, count, ..
+            } => Reply::Array(self.stream(&key)?.map_or(vec![], |stream| {
+                stream
+                    .read_after(after, count.map(|count| count as usize))
+                    .into_iter()
+                    .map(Reply::stream_entry)
+                    .collect()
+            })),
+            Op::XGROUPCREATE {
+                key, group, start, ..
+            } => {
+                self.stream_or_default(key)?
+                    .create_group(group.clone(), start)?;
+                Reply::Bulk(group)
+            }
+            Op::XREADGROUP {
+                timestamp,
+                key,
+                group,
+                consumer,
+                after,
+                count,
+            } => Reply::Array(
+                self.stream_mut(&key)?
+                    .read_group(
+                        &group,
+                        consumer,
+                        after,
+                        count.map(|count| count as usize),
+                        physical_millis(timestamp),
+                    )?
+                    .into_iter()
+                    .map(Reply::stream_entry)
+                    .collect(),
+            ),
+            Op::XACK {
+                key, group, ids, ..
+            } => Reply::Integer(self.stream_mut(&key)?.ack(&group, &ids)? as i64),
+            Op::XPENDING { key, group, .. } => {
+                let stream = self.stream(&key)?.ok_or(StreamError::NoGroup)?;
+                Reply::Array(
+                    stream
+                        .pending(&group)?
+                        .into_iter()
+                        .map(|(id, pending)| {
+                            Reply::Array(vec![
+                                Reply::Bulk(Bytes::from(id.to_string())),
+                                Reply::Bulk(pending.consumer),
+                                Reply::Integer(pending.delivery_count as i64),
+                            ])
+                        })
+                        .collect(),
+                )
+            }
+            Op::TSCREATE { key, retention, .. } => {
+                self.timeseries_or_default(key.clone())?
+                    .set_retention(retention);
+                Reply::Bulk(key)
             }
-            Op::GET { key, .. } => self.get(key),
-            Op::DEL { key, .. } => self.del(key),
+            Op::TSADD {
+                timestamp,
+                key,
+                sample_time,
+                value,
+            } => {
+                let sample_time = sample_time.unwrap_or(physical_millis(timestamp));
+                self.timeseries_or_default(key)?.add(sample_time, value);
+                Reply::Integer(sample_time)
+            }
+            Op::TSRANGE { key, from, to, .. } => {
+                Reply::Array(self.timeseries(&key)?.map_or(vec![], |series| {
+                    series
+                        .range(from, to)
+                        .into_iter()
+                        .map(Reply::sample)
+                        .collect()
+                }))
+            }
+            Op::TSAGGREGATE {
+                key,
+                from,
+                to,
+                aggregation,
+                bucket,
+                ..
+            } => Reply::Array(self.timeseries(&key)?.map_or(vec![], |series| {
+                series
+                    .aggregate(from, to, aggregation, bucket)
+                    .into_iter()
+                    .map(Reply::sample)
+                    .collect()
+            })),
+            Op::JSONSET {
+                key, path, value, ..
+            } => {
+                self.json_set(key.clone(), path, value)?;
+                Reply::Bulk(key)
+            }
+            Op::JSONGET { key, path, .. } => self
+                .json(&key)?
+                .and_then(|document| path.get(document))
+                .map_or(Reply::Nil, |value| {
+                    Reply::Bulk(Bytes::from(value.to_string()))
+                }),
+            Op::JSONARRAPPEND {
+                key, path, values, ..
+            } => Reply::Integer(path.append(self.json_mut(&key)?, values)? as i64),
+            Op::INDEXCREATE {
+                name,
+                prefix,
+                extractor,
+                ..
+            } => {
+                self.create_index(name.clone(), prefix, extractor)?;
+                Reply::Bulk(name)
+            }
+            Op::INDEXDROP { name, .. } => {
+                Reply::Integer(self.indexes.remove(&name).is_some() as i64)
+            }
+            Op::INDEXFIND { name, value, .. } => Reply::Array(
+                self.find_indexed(&name, &value)?
+                    .into_iter()
+                    .map(Reply::Bulk)
+                    .collect(),
+            ),
+            Op::SEARCHCREATE { prefix, .. } => {
+                self.create_text_index(prefix.clone())?;
+                Reply::Bulk(prefix)
+            }
+            Op::SEARCHDROP { prefix, .. } => {
+                Reply::Integer(self.text_indexes.remove(&prefix).is_some() as i64)
+            }
+            Op::SEARCH { prefix, query, .. } => Reply::Array(
+                self.search(&prefix, &query)?
+                    .into_iter()
+                    .map(Reply::Bulk)
+                    .collect(),
+            ),
+            Op::SCAN { range, filter, .. } => Reply::Array(
+                self.scan(&range)
+                    .filter(|(key, value)| filter.as_ref().is_none_or(|f| f.matches(key, value)))
+                    .map(|(key, value)| {
+                        Reply::Array(vec![Reply::Bulk(key.clone()), value.clone().into_reply()])
+                    })
+                    .collect(),
+            ),
+            Op::AGGREGATE {
+                function,
+                range,
+                filter,
+                ..
+            } => {
+                let mut accumulator = Accumulator::default();
+                self.scan(&range)
+                    .filter(|(key, value)| filter.as_ref().is_none_or(|f| f.matches(key, value)))
+                    .for_each(|(_, value)| accumulator.add(value));
+                match (function, accumulator.finish(function)) {
+                    (Aggregate::Count, Some(count)) => Reply::Integer(count as i64),
+                    (_, Some(result)) => Reply::score(result),
+                    (_, None) => Reply::Nil,
+                }
+            }
+            // The store adds what it knows about the WAL.
+            Op::STATS { .. } => Reply::Array(vec![
+                Reply::Bulk(Bytes::from("keys")),
+                Reply::Integer(self.store.len() as i64),
+            ]),
+            // Taken by the store, there is nothing to evaluate here.
+            Op::CHECKPOINT { .. } => Reply::Nil,
         };
-        result
+        Ok(result)
     }
 
-    pub fn get_snapshot(&self) -> Vec<(String, String)> {
+    pub fn get_snapshot(&self) -> Vec<(Bytes, Value)> {
         self.store.clone().into_iter().collect()
     }
+
+    /// The ops that create the current indexes, snapshots store these rather
+    /// than the indexes themselves.
+    pub fn index_definitions(&self) -> Vec<Op> {
+        let secondary = self.indexes.iter().map(|(name, index)| Op::INDEXCREATE {
+            timestamp: 0,
+            name: name.clone(),
+            prefix: index.prefix().clone(),
+            extractor: index.extractor().clone(),
+        });
+        let text = self.text_indexes.keys().map(|prefix| Op::SEARCHCREATE {
+            timestamp: 0,
+            prefix: prefix.clone(),
+        });
+        secondary.chain(text).collect()
+    }
+
+    /// Rebuilds a layer from snapshot entries and index definitions.
+    pub fn restore(
+        entries: Vec<(Bytes, Value)>,
+        indexes: Vec<Op>,
+    ) -> Result<Self, MemoryLayerErrors> {
+        let mut layer = Self::new();
+        layer.store = entries.into_iter().collect();
+        for op in indexes {
+            layer.eval(op)?;
+        }
+        Ok(layer)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::{HybridClock, ManualClock};
+    use crate::types::stream::StreamId;
+    use crate::types::timeseries::Aggregation;
 
     #[test]
     fn test_in_memory_layer() {
         let mut layer = InMemoryLayer::new();
-        let op = Op::new_set(0, "key".to_string(), "value".to_string());
-        assert_eq!(layer.eval(op), Some("key".to_string()));
+        let op = Op::new_set(0, "key", "value");
+        assert_eq!(layer.eval(op).unwrap(), Reply::Bulk(Bytes::from("key")));
+
+        let op = Op::new_get(0, "key");
+        assert_eq!(layer.eval(op).unwrap(), Reply::Bulk(Bytes::from("value")));
+
+        let op = Op::new_del(0, "key");
+        assert_eq!(layer.eval(op).unwrap(), Reply::Bulk(Bytes::from("value")));
+
+        let op = Op::new_get(0, "key");
+        assert_eq!(layer.eval(op).unwrap(), Reply::Nil);
+    }
+
+    #[test]
+    fn test_in_memory_layer_binary_values() {
+        let mut layer = InMemoryLayer::new();
+        let key = Bytes::from_static(&[0x00, 0xff, 0xfe]);
+        let value = Bytes::from_static(&[0xc3, 0x28, 0x00, 0x9f]);
+        layer
+            .eval(Op::new_set(0, key.clone(), value.clone()))
+            .unwrap();
+
+        assert_eq!(layer.eval(Op::new_get(0, key)).unwrap(), Reply::Bulk(value));
+    }
+
+    #[test]
+    fn test_list_operations() {
+        let mut layer = InMemoryLayer::new();
+        let key = Bytes::from("queue");
+        let push = Op::RPUSH {
+            timestamp: 0,
+            key: key.clone(),
+            values: vec![Bytes::from("a"), Bytes::from("b")],
+        };
+        assert_eq!(layer.eval(push).unwrap(), Reply::Integer(2));
+        let push = Op::LPUSH {
+            timestamp: 0,
+            key: key.clone(),
+            values: vec![Bytes::from("c")],
+        };
+        assert_eq!(layer.eval(push).unwrap(), Reply::Integer(3));
+
+        let range = Op::LRANGE {
+            timestamp: 0,
+            key: key.clone(),
+            start: 0,
+            stop: -1,
+        };
+        assert_eq!(
+            layer.eval(range).unwrap(),
+            Reply::Array(vec![
+                Reply::Bulk(Bytes::from("c")),
+                Reply::Bulk(Bytes::from("a")),
+                Reply::Bulk(Bytes::from("b")),
+            ])
+        );
+
+        let pop = Op::RPOP {
+            timestamp: 0,
+            key: key.clone(),
+        };
+        assert_eq!(layer.eval(pop).unwrap(), Reply::Bulk(Bytes::from("b")));
+        let len = Op::LLEN {
+            timestamp: 0,
+            key: key.clone(),
+        };
+        assert_eq!(layer.eval(len).unwrap(), Reply::Integer(2));
+    }
+
+    #[test]
+    fn test_list_wrong_type() {
+        let mut layer = InMemoryLayer::new();
+        layer.eval(Op::new_set(0, "key", "value")).unwrap();
+        let push = Op::LPUSH {
+            timestamp: 0,
+            key: Bytes::from("key"),
+            values: vec![Bytes::from("a")],
+        };
+        assert!(matches!(
+            layer.eval(push),
+            Err(MemoryLayerErrors::WrongType)
+        ));
+
+        layer
+            .eval(Op::RPUSH {
+                timestamp: 0,
+                key: Bytes::from("list"),
+                values: vec![Bytes::from("a")],
+            })
+            .unwrap();
+        assert!(matches!(
+            layer.eval(Op::new_get(0, "list")),
+            Err(MemoryLayerErrors::WrongType)
+        ));
+    }
+
+    #[test]
+    fn test_hash_operations() {
+        let mut layer = InMemoryLayer::new();
+        let key = Bytes::from("user:42");
+        let hset = Op::HSET {
+            timestamp: 0,
+            key: key.clone(),
+            fields: vec![
+                (Bytes::from("name"), Bytes::from("ada")),
+                (Bytes::from("visits"), Bytes::from("1")),
+            ],
+        };
+        assert_eq!(layer.eval(hset).unwrap(), Reply::Integer(2));
+
+        let hincrby = Op::HINCRBY {
+            timestamp: 0,
+            key: key.clone(),
+            field: Bytes::from("visits"),
+            increment: 5,
+        };
+        assert_eq!(layer.eval(hincrby).unwrap(), Reply::Integer(6));
+
+        let hkeys = Op::HKEYS {
+            timestamp: 0,
+            key: key.clone(),
+        };
+        assert_eq!(
+            layer.eval(hkeys).unwrap(),
+            Reply::Array(vec![
+                Reply::Bulk(Bytes::from("name")),
+                Reply::Bulk(Bytes::from("visits")),
+            ])
+        );
+
+        let hincrby = Op::HINCRBY {
+            timestamp: 0,
+            key: key.clone(),
+            field: Bytes::from("name"),
+            increment: 1,
+        };
+        assert!(matches!(
+            layer.eval(hincrby),
+            Err(MemoryLayerErrors::NotAnInteger)
+        ));
+
+        let hdel = Op::HDEL {
+            timestamp: 0,
+            key: key.clone(),
+            fields: vec![Bytes::from("name"), Bytes::from("visits")],
+        };
+        assert_eq!(layer.eval(hdel).unwrap(), Reply::Integer(2));
+        assert!(layer.get_snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_sorted_set_operations() {
+        let mut layer = InMemoryLayer::new();
+        let key = Bytes::from("board");
+        let zadd = Op::ZADD {
+            timestamp: 0,
+            key: key.clone(),
+            members: vec![
+                (30.0, Bytes::from("carol")),
+                (10.0, Bytes::from("alice")),
+                (20.0, Bytes::from("bob")),
+            ],
+        };
+        assert_eq!(layer.eval(zadd).unwrap(), Reply::Integer(3));
+
+        let zrank = Op::ZRANK {
+            timestamp: 0,
+            key: key.clone(),
+            member: Bytes::from("bob"),
+        };
+        assert_eq!(layer.eval(zrank).unwrap(), Reply::Integer(1));
+
+        let zrangebyscore = Op::ZRANGEBYSCORE {
+            timestamp: 0,
+            key: key.clone(),
+            min: 15.0,
+            max: f64::INFINITY,
+        };
+        assert_eq!(
+            layer.eval(zrangebyscore).unwrap(),
+            Reply::Array(vec![
+                Reply::Bulk(Bytes::from("bob")),
+                Reply::Bulk(Bytes::from("carol")),
+            ])
+        );
+
+        let zscore = Op::ZSCORE {
+            timestamp: 0,
+            key: key.clone(),
+            member: Bytes::from("alice"),
+        };
+        assert_eq!(layer.eval(zscore).unwrap(), Reply::Bulk(Bytes::from("10")));
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let mut layer = InMemoryLayer::new();
+        let sadd = |key: &str, members: &[&str]| Op::SADD {
+            timestamp: 0,
+            key: Bytes::from(key.to_string()),
+            members: members
+                .iter()
+                .map(|member| Bytes::from(member.to_string()))
+                .collect(),
+        };
+        assert_eq!(
+            layer.eval(sadd("a", &["x", "y", "z"])).unwrap(),
+            Reply::Integer(3)
+        );
+        assert_eq!(
+            layer.eval(sadd("b", &["y", "w"])).unwrap(),
+            Reply::Integer(2)
+        );
+
+        let keys = vec![Bytes::from("a"), Bytes::from("b")];
+        let inter = Op::SETOP {
+            timestamp: 0,
+            operation: SetOperation::Intersection,
+            keys: keys.clone(),
+        };
+        assert_eq!(
+            layer.eval(inter).unwrap(),
+            Reply::Array(vec![Reply::Bulk(Bytes::from("y"))])
+        );
+
+        let diff = Op::SETOPSTORE {
+            timestamp: 0,
+            operation: SetOperation::Difference,
+            destination: Bytes::from("c"),
+            keys: keys.clone(),
+        };
+        assert_eq!(layer.eval(diff).unwrap(), Reply::Integer(2));
+        let members = Op::SMEMBERS {
+            timestamp: 0,
+            key: Bytes::from("c"),
+        };
+        assert_eq!(
+            layer.eval(members).unwrap(),
+            Reply::Array(vec![
+                Reply::Bulk(Bytes::from("x")),
+                Reply::Bulk(Bytes::from("z")),
+            ])
+        );
+
+        let union = Op::SETOP {
+            timestamp: 0,
+            operation: SetOperation::Union,
+            keys: vec![Bytes::from("b"), Bytes::from("missing")],
+        };
+        assert_eq!(
+            layer.eval(union).unwrap(),
+            Reply::Array(vec![
+                Reply::Bulk(Bytes::from("w")),
+                Reply::Bulk(Bytes::from("y")),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_bitmap_operations() {
+        let mut layer = InMemoryLayer::new();
+        let setbit = |key: &str, offset: u64| Op::SETBIT {
+            timestamp: 0,
+            key: Bytes::from(key.to_string()),
+            offset,
+            bit: true,
+        };
+        assert_eq!(layer.eval(setbit("day1", 7)).unwrap(), Reply::Integer(0));
+        assert_eq!(layer.eval(setbit("day1", 7)).unwrap(), Reply::Integer(1));
+        layer.eval(setbit("day1", 20)).unwrap();
+        layer.eval(setbit("day2", 20)).unwrap();
 
-        let op = Op::new_get(0, "key".to_string());
-        assert_eq!(layer.eval(op), Some("value".to_string()));
+        let getbit = Op::GETBIT {
+            timestamp: 0,
+            key: Bytes::from("day1"),
+            offset: 20,
+        };
+        assert_eq!(layer.eval(getbit).unwrap(), Reply::Integer(1));
 
-        let op = Op::new_del(0, "key".to_string());
-        assert_eq!(layer.eval(op), Some("value".to_string()));
+        let bitop = Op::BITOP {
+            timestamp: 0,
+            operation: BitOperation::And,
+            destination: Bytes::from("both"),
+            keys: vec![Bytes::from("day1"), Bytes::from("day2")],
+        };
+        assert_eq!(layer.eval(bitop).unwrap(), Reply::Integer(3));
+        let bitcount = Op::BITCOUNT {
+            timestamp: 0,
+            key: Bytes::from("both"),
+            range: None,
+        };
+        assert_eq!(layer.eval(bitcount).unwrap(), Reply::Integer(1));
+        let bitpos = Op::BITPOS {
+            timestamp: 0,
+            key: Bytes::from("both"),
+            bit: true,
+            start: None,
+            end: None,
+        };
+        assert_eq!(layer.eval(bitpos).unwrap(), Reply::Integer(20));
+    }
 
-        let op = Op::new_get(0, "key".to_string());
-        assert_eq!(layer.eval(op), None);
+    #[test]
+    fn test_hyperloglog_operations() {
+        let mut layer = InMemoryLayer::new();
+        let pfadd = |key: &str, elements: &[&str]| Op::PFADD {
+            timestamp: 0,
+            key: Bytes::from(key.to_string()),
+            elements: elements
+                .iter()
+                .map(|element| Bytes::from(element.to_string()))
+                .collect(),
+        };
+        assert_eq!(
+            layer.eval(pfadd("mon", &["a", "b", "c"])).unwrap(),
+            Reply::Integer(1)
+        );
+        assert_eq!(layer.eval(pfadd("mon", &["a"])).unwrap(), Reply::Integer(0));
+        layer.eval(pfadd("tue", &["c", "d"])).unwrap();
+
+        let pfmerge = Op::PFMERGE {
+            timestamp: 0,
+            destination: Bytes::from("week"),
+            keys: vec![Bytes::from("mon"), Bytes::from("tue")],
+        };
+        layer.eval(pfmerge).unwrap();
+        let pfcount = Op::PFCOUNT {
+            timestamp: 0,
+            keys: vec![Bytes::from("week")],
+        };
+        assert_eq!(layer.eval(pfcount).unwrap(), Reply::Integer(4));
+
+        layer.eval(Op::new_set(0, "plain", "value")).unwrap();
+        assert!(matches!(
+            layer.eval(pfadd("plain", &["a"])),
+            Err(MemoryLayerErrors::WrongType)
+        ));
+    }
+
+    #[test]
+    fn test_stream_operations() {
+        let mut layer = InMemoryLayer::new();
+        let key = Bytes::from("events");
+        // `XADD events * n ms` stamped at `ms`.
+        let xadd = |ms: u64| {
+            let wall = ManualClock::default();
+            wall.set(ms as i64);
+            Op::XADD {
+                timestamp: HybridClock::new(Box::new(wall)).now(),
+                key: Bytes::from("events"),
+                id: None,
+                fields: vec![(Bytes::from("n"), Bytes::from(ms.to_string()))],
+            }
+        };
+        assert_eq!(
+            layer.eval(xadd(5)).unwrap(),
+            Reply::Bulk(Bytes::from("5-0"))
+        );
+
+        let group = Op::XGROUPCREATE {
+            timestamp: 0,
+            key: key.clone(),
+            group: Bytes::from("workers"),
+            start: None,
+        };
+        layer.eval(group).unwrap();
+        layer.eval(xadd(6)).unwrap();
+
+        let read = Op::XREADGROUP {
+            timestamp: 0,
+            key: key.clone(),
+            group: Bytes::from("workers"),
+            consumer: Bytes::from("w1"),
+            after: None,
+            count: None,
+        };
+        assert_eq!(
+            layer.eval(read).unwrap(),
+            Reply::Array(vec![Reply::Array(vec![
+                Reply::Bulk(Bytes::from("6-0")),
+                Reply::Array(vec![
+                    Reply::Bulk(Bytes::from("n")),
+                    Reply::Bulk(Bytes::from("6")),
+                ]),
+            ])])
+        );
+
+        let ack = Op::XACK {
+            timestamp: 0,
+            key: key.clone(),
+            group: Bytes::from("workers"),
+            ids: vec![StreamId::new(6, 0)],
+        };
+        assert_eq!(layer.eval(ack).unwrap(), Reply::Integer(1));
+
+        let missing_group = Op::XPENDING {
+            timestamp: 0,
+            key,
+            group: Bytes::from("nobody"),
+        };
+        assert!(matches!(
+            layer.eval(missing_group),
+            Err(MemoryLayerErrors::StreamError(StreamError::NoGroup))
+        ));
+    }
+
+    #[test]
+    fn test_rejected_xadd_creates_no_key() {
+        let mut layer = InMemoryLayer::new();
+        let xadd = Op::XADD {
+            timestamp: 0,
+            key: Bytes::from("s"),
+            id: Some(StreamId::new(0, 0)),
+            fields: vec![(Bytes::from("f"), Bytes::from("v"))],
+        };
+        assert!(matches!(
+            layer.eval(xadd),
+            Err(MemoryLayerErrors::StreamError(StreamError::IdTooSmall))
+        ));
+        assert!(layer.get_snapshot().is_empty());
+
+        let push = Op::LPUSH {
+            timestamp: 0,
+            key: Bytes::from("s"),
+            values: vec![Bytes::from("x")],
+        };
+        assert_eq!(layer.eval(push).unwrap(), Reply::Integer(1));
+    }
+
+    #[test]
+    fn test_timeseries_operations() {
+        let mut layer = InMemoryLayer::new();
+        let key = Bytes::from("cpu");
+        let create = Op::TSCREATE {
+            timestamp: 0,
+            key: key.clone(),
+            retention: 60_000,
+        };
+        layer.eval(create).unwrap();
+        for (sample_time, value) in [(0, 9.0), (60_000, 1.0), (90_000, 3.0)] {
+            let add = Op::TSADD {
+                timestamp: 0,
+                key: key.clone(),
+                sample_time: Some(sample_time),
+                value,
+            };
+            assert_eq!(layer.eval(add).unwrap(), Reply::Integer(sample_time));
+        }
+
+        // The sample at 0 fell out of the one minute retention window.
+        let aggregate = Op::TSAGGREGATE {
+            timestamp: 0,
+            key: key.clone(),
+            from: i64::MIN,
+            to: i64::MAX,
+            aggregation: Aggregation::Avg,
+            bucket: 60_000,
+        };
+        assert_eq!(
+            layer.eval(aggregate).unwrap(),
+            Reply::Array(vec![Reply::Array(vec![
+                Reply::Integer(60_000),
+                Reply::score(2.0)
+            ])])
+        );
+
+        let push = Op::LPUSH {
+            timestamp: 0,
+            key,
+            values: vec![Bytes::from("x")],
+        };
+        assert!(matches!(
+            layer.eval(push),
+            Err(MemoryLayerErrors::WrongType)
+        ));
+    }
+
+    #[test]
+    fn test_json_operations() {
+        let mut layer = InMemoryLayer::new();
+        let key = Bytes::from("config");
+        let json_set = |path: &str, value: serde_json::Value| Op::JSONSET {
+            timestamp: 0,
+            key: Bytes::from("config"),
+            path: path.parse().unwrap(),
+            value,
+        };
+        assert!(matches!(
+            layer.eval(json_set("$.db", serde_json::json!({}))),
+            Err(MemoryLayerErrors::JsonError(JsonError::NotAtRoot))
+        ));
+        layer
+            .eval(json_set(
+                "$",
+                serde_json::json!({"db": {"port": 5432}, "hosts": []}),
+            ))
+            .unwrap();
+        layer
+            .eval(json_set("$.db.port", serde_json::json!(6432)))
+            .unwrap();
+
+        let append = Op::JSONARRAPPEND {
+            timestamp: 0,
+            key: key.clone(),
+            path: "$.hosts".parse().unwrap(),
+            values: vec![serde_json::json!("a"), serde_json::json!("b")],
+        };
+        assert_eq!(layer.eval(append).unwrap(), Reply::Integer(2));
+
+        let get = Op::JSONGET {
+            timestamp: 0,
+            key,
+            path: JsonPath::root(),
+        };
+        assert_eq!(
+            layer.eval(get).unwrap(),
+            Reply::Bulk(Bytes::from(r#"{"db":{"port":6432},"hosts":["a","b"]}"#))
+        );
+    }
+
+    #[test]
+    fn test_secondary_index() {
+        let mut layer = InMemoryLayer::new();
+        layer.eval(Op::new_set(0, "user:1", "admin")).unwrap();
+        layer.eval(Op::new_set(0, "user:2", "guest")).unwrap();
+        layer.eval(Op::new_set(0, "vip:1", "admin")).unwrap();
+        let create = Op::INDEXCREATE {
+            timestamp: 0,
+            name: Bytes::from("roles"),
+            prefix: Bytes::from("user:"),
+            extractor: Extractor::Value,
+        };
+        layer.eval(create.clone()).unwrap();
+        assert!(matches!(
+            layer.eval(create),
+            Err(MemoryLayerErrors::IndexError(IndexError::Exists))
+        ));
+
+        let find = |value: &str| Op::INDEXFIND {
+            timestamp: 0,
+            name: Bytes::from("roles"),
+            value: Bytes::from(value.to_string()),
+        };
+        assert_eq!(
+            layer.eval(find("admin")).unwrap(),
+            Reply::Array(vec![Reply::Bulk(Bytes::from("user:1"))])
+        );
+
+        layer.eval(Op::new_set(0, "user:2", "admin")).unwrap();
+        layer.eval(Op::new_del(0, "user:1")).unwrap();
+        assert_eq!(
+            layer.eval(find("admin")).unwrap(),
+            Reply::Array(vec![Reply::Bulk(Bytes::from("user:2"))])
+        );
+        assert_eq!(layer.eval(find("guest")).unwrap(), Reply::Array(vec![]));
+    }
+
+    #[test]
+    fn test_restore_rebuilds_indexes() {
+        let mut layer = InMemoryLayer::new();
+        layer.eval(Op::new_set(0, "user:1", "admin")).unwrap();
+        layer.eval(Op::new_set(0, "doc:1", "red apples")).unwrap();
+        layer
+            .eval(Op::INDEXCREATE {
+                timestamp: 0,
+                name: Bytes::from("roles"),
+                prefix: Bytes::from("user:"),
+                extractor: Extractor::Value,
+            })
+            .unwrap();
+        layer
+            .eval(Op::SEARCHCREATE {
+                timestamp: 0,
+                prefix: Bytes::from("doc:"),
+            })
+            .unwrap();
+
+        let mut restored =
+            InMemoryLayer::restore(layer.get_snapshot(), layer.index_definitions()).unwrap();
+        assert_eq!(restored.get_snapshot(), layer.get_snapshot());
+        let find = Op::INDEXFIND {
+            timestamp: 0,
+            name: Bytes::from("roles"),
+            value: Bytes::from("admin"),
+        };
+        assert_eq!(
+            restored.eval(find).unwrap(),
+            Reply::Array(vec![Reply::Bulk(Bytes::from("user:1"))])
+        );
+        let search = Op::SEARCH {
+            timestamp: 0,
+            prefix: Bytes::from("doc:"),
+            query: Bytes::from("apples"),
+        };
+        assert_eq!(
+            restored.eval(search).unwrap(),
+            Reply::Array(vec![Reply::Bulk(Bytes::from("doc:1"))])
+        );
+    }
+
+    #[test]
+    fn test_full_text_search() {
+        let mut layer = InMemoryLayer::new();
+        layer
+            .eval(Op::new_set(0, "doc:1", "Red apples and red pears"))
+            .unwrap();
+        let create = Op::SEARCHCREATE {
+            timestamp: 0,
+            prefix: Bytes::from("doc:"),
+        };
+        layer.eval(create).unwrap();
+        layer.eval(Op::new_set(0, "doc:2", "green apples")).unwrap();
+
+        let search = |query: &str| Op::SEARCH {
+            timestamp: 0,
+            prefix: Bytes::from("doc:"),
+            query: Bytes::from(query.to_string()),
+        };
+        assert_eq!(
+            layer.eval(search("apples")).unwrap(),
+            Reply::Array(vec![
+                Reply::Bulk(Bytes::from("doc:2")),
+                Reply::Bulk(Bytes::from("doc:1")),
+            ])
+        );
+
+        layer.eval(Op::new_del(0, "doc:2")).unwrap();
+        assert_eq!(layer.eval(search("green")).unwrap(), Reply::Array(vec![]));
+    }
+
+    #[test]
+    fn test_scan_with_filter() {
+        use crate::query::{Comparison, Condition, Field, Filter, Predicate};
+
+        let mut layer = InMemoryLayer::new();
+        for (key, value) in [
+            ("a", "1"),
+            ("user:1", "5"),
+            ("user:2", "50"),
+            ("user:3", "x"),
+        ] {
+            layer.eval(Op::new_set(0, key, value)).unwrap();
+        }
+        let scan = |range, filter| Op::SCAN {
+            timestamp: 0,
+            range,
+            filter,
+        };
+        let entry = |key: &str, value: &str| {
+            Reply::Array(vec![
+                Reply::Bulk(Bytes::from(key.to_string())),
+                Reply::Bulk(Bytes::from(value.to_string())),
+            ])
+        };
+
+        let bounded = KeyRange::Between(Bound::Unbounded, Bound::Included(Bytes::from("user:1")));
+        assert_eq!(
+            layer.eval(scan(bounded, None)).unwrap(),
+            Reply::Array(vec![entry("a", "1"), entry("user:1", "5")])
+        );
+
+        let over_ten = Filter {
+            any_of: vec![vec![Condition {
+                field: Field::Value,
+                predicate: Predicate::Compare(Comparison::Greater, 10.0),
+            }]],
+        };
+        let prefix = KeyRange::Prefix(Bytes::from("user:"));
+        assert_eq!(
+            layer.eval(scan(prefix, Some(over_ten))).unwrap(),
+            Reply::Array(vec![entry("user:2", "50")])
+        );
+    }
+
+    #[test]
+    fn test_aggregate_over_range() {
+        use crate::query::{Condition, Field, Filter, Predicate};
+
+        let mut layer = InMemoryLayer::new();
+        for (key, value) in [
+            ("a", "100"),
+            ("user:1", "5"),
+            ("user:2", "50"),
+            ("user:3", "x"),
+        ] {
+            layer.eval(Op::new_set(0, key, value)).unwrap();
+        }
+        let aggregate = |function, filter| Op::AGGREGATE {
+            timestamp: 0,
+            function,
+            range: KeyRange::Prefix(Bytes::from("user:")),
+            filter,
+        };
+        assert_eq!(
+            layer.eval(aggregate(Aggregate::Count, None)).unwrap(),
+            Reply::Integer(3)
+        );
+        assert_eq!(
+            layer.eval(aggregate(Aggregate::Sum, None)).unwrap(),
+            Reply::score(55.0)
+        );
+        assert_eq!(
+            layer.eval(aggregate(Aggregate::Avg, None)).unwrap(),
+            Reply::score(27.5)
+        );
+
+        let no_numbers = Filter {
+            any_of: vec![vec![Condition {
+                field: Field::Key,
+                predicate: Predicate::Equals(Bytes::from("user:3")),
+            }]],
+        };
+        assert_eq!(
+            layer
+                .eval(aggregate(Aggregate::Max, Some(no_numbers)))
+                .unwrap(),
+            Reply::Nil
+        );
     }
 }
@@ -0,0 +1,136 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use bytes::Bytes;
+use thiserror::Error;
+
+use crate::types::json::JsonPath;
+use crate::types::Value;
+
+/// What part of a value an index is keyed by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Extractor {
+    // The raw bytes of a string value.
+    Value,
+    // A field of a JSON document, or of a string value holding JSON text.
+    JsonField(JsonPath),
+}
+
+impl Extractor {
+    pub fn extract(&self, value: &Value) -> Option<Bytes> {
+        match (self, value) {
+            (Extractor::Value, Value::String(bytes)) => Some(bytes.clone()),
+            (Extractor::JsonField(path), Value::Json(document)) => {
+                path.get(document).map(json_to_bytes)
+            }
+            (Extractor::JsonField(path), Value::String(bytes)) => {
+                let document = serde_json::from_slice(bytes).ok()?;
+                path.get(&document).map(json_to_bytes)
+            }
+            _ => None,
+        }
+    }
+}
+
+// Strings are indexed without their quotes so they can be looked up as typed.
+fn json_to_bytes(value: &serde_json::Value) -> Bytes {
+    match value {
+        serde_json::Value::String(text) => Bytes::from(text.clone()),
+        other => Bytes::from(other.to_string()),
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum IndexError {
+    #[error("Index already exists")]
+    Exists,
+
+    #[error("No such index")]
+    NotFound,
+}
+
+#[derive(Debug, Clone)]
+pub struct SecondaryIndex {
+    prefix: Bytes,
+    extractor: Extractor,
+    entries: BTreeMap<Bytes, BTreeSet<Bytes>>,
+    // Current indexed value of every key, needed to drop the old entry on change.
+    indexed: HashMap<Bytes, Bytes>,
+}
+
+impl SecondaryIndex {
+    pub fn new(prefix: Bytes, extractor: Extractor) -> Self {
+        Self {
+            prefix,
+            extractor,
+            entries: BTreeMap::new(),
+            indexed: HashMap::new(),
+        }
+    }
+
+    pub fn prefix(&self) -> &Bytes {
+        &self.prefix
+    }
+
+    pub fn extractor(&self) -> &Extractor {
+        &self.extractor
+    }
+
+    pub fn covers(&self, key: &[u8]) -> bool {
+        key.starts_with(&self.prefix)
+    }
+
+    /// Re-indexes `key` against its current value, `None` when the key is gone.
+    pub fn update(&mut self, key: &Bytes, value: Option<&Value>) {
+        if !self.covers(key) {
+            return;
+        }
+        if let Some(old) = self.indexed.remove(key) {
+            if let Some(keys) = self.entries.get_mut(&old) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.entries.remove(&old);
+                }
+            }
+        }
+        if let Some(new) = value.and_then(|value| self.extractor.extract(value)) {
+            self.entries
+                .entry(new.clone())
+                .or_default()
+                .insert(key.clone());
+            self.indexed.insert(key.clone(), new);
+        }
+    }
+
+    pub fn find(&self, value: &[u8]) -> Vec<Bytes> {
+        self.entries
+            .get(value)
+            .map_or(vec![], |keys| keys.iter().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_follows_updates() {
+        let path = "$.email".parse().unwrap();
+        let mut index = SecondaryIndex::new(Bytes::from("user:"), Extractor::JsonField(path));
+        let key = Bytes::from("user:1");
+        let alice = Value::Json(serde_json::json!({"email": "alice@example.com"}));
+        index.update(&key, Some(&alice));
+        assert_eq!(index.find(b"alice@example.com"), vec![key.clone()]);
+
+        // JSON stored as a plain string is indexed the same way.
+        let bob = Value::String(Bytes::from(r#"{"email":"bob@example.com"}"#));
+        index.update(&key, Some(&bob));
+        assert!(index.find(b"alice@example.com").is_empty());
+        assert_eq!(index.find(b"bob@example.com"), vec![key.clone()]);
+
+        index.update(&key, None);
+        assert!(index.find(b"bob@example.com").is_empty());
+
+        index.update(&Bytes::from("order:1"), Some(&alice));
+        assert!(index.find(b"alice@example.com").is_empty());
+    }
+}
@@ -1,29 +1,52 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::bytecode_serializer::BytecodeSerializer;
-use crate::errors::{BytecodeSerializerError, KVStoreError};
+use crate::clock::HybridClock;
+use crate::errors::{KVStoreError, MemoryLayerErrors, SnapshotError, WALError};
 use crate::filesystem::FileSystem;
 use crate::in_memory::InMemoryLayer;
-use crate::log::WAL;
+use crate::log::{Append, OnCorruption, WalRequest, WalStats, WAL};
 use crate::lru_cache::LruCacheLayer;
 use crate::operation::Op;
-use crate::parser::Parser;
+use crate::reply::Reply;
+use crate::session::{self, BlockedClients, ReplySender, Request};
+use crate::snapshot::{self, CheckpointPolicy, Snapshot, KEPT_SNAPSHOTS};
+use crate::tcp_adapter::TcpAdapter;
+use crate::wal_io::Durability;
+use bytes::Bytes;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
-use tokio::sync::Mutex;
-use tokio::task;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::{self, JoinHandle};
+use tokio::time::{self, Instant, MissedTickBehavior};
 
 pub struct KvStore {
     store: InMemoryLayer,
     wal: Arc<Mutex<WAL>>,
     file_system: FileSystem,
-    parser: Parser,
-    send_to_wal: tokio::sync::mpsc::Sender<Op>,
+    send_to_wal: tokio::sync::mpsc::Sender<WalRequest>,
     cache: LruCacheLayer,
+    blocked: BlockedClients,
+    wal_stats: Arc<WalStats>,
+    clock: HybridClock,
+    snapshot_dir: PathBuf,
+    checkpoints: CheckpointPolicy,
+    // Writes logged since the last checkpoint started.
+    unsnapshotted: u64,
+    checkpoint: Option<JoinHandle<()>>,
+    read_only: bool,
+    // Elements popped for sessions that gave up before the pop was durable.
+    give_back: mpsc::UnboundedSender<Op>,
+    returned: mpsc::UnboundedReceiver<Op>,
 }
 
 impl KvStore {
-    pub async fn new(root: PathBuf, cache_size: u32) -> Result<Self, KVStoreError> {
+    pub async fn new(
+        root: PathBuf,
+        cache_size: u32,
+        durability: Durability,
+        checkpoints: CheckpointPolicy,
+    ) -> Result<Self, KVStoreError> {
         let store = InMemoryLayer::new();
 
         let file_system = FileSystem::new(root)
@@ -36,81 +59,882 @@ impl KvStore {
             .map_err(|e| KVStoreError::FileSystemError(e))?;
 
         let wal_dir = file_system.get_wal_ref().await.clone();
+        let snapshot_dir = file_system.get_snapshot_ref().await.clone();
 
-        let (tx, rx) = tokio::sync::mpsc::channel::<Op>(100);
+        let (tx, rx) = tokio::sync::mpsc::channel::<WalRequest>(100);
         let wal_filesize_limit = 5 * 1024 * 1024;
-        let wal = WAL::new(rx, wal_dir, wal_filesize_limit)
+        let wal = WAL::new(rx, wal_dir, wal_filesize_limit, durability)
             .await
             .map_err(|e| KVStoreError::WALError(e))?;
 
+        let wal_stats = wal.stats();
         let wal = Arc::new(Mutex::new(wal));
 
-        let parser = Parser::new();
-
         let cache = LruCacheLayer::new(cache_size);
+        let (give_back, returned) = mpsc::unbounded_channel();
 
         Ok(Self {
             store,
             wal,
             file_system,
-            parser,
             send_to_wal: tx,
             cache,
+            blocked: BlockedClients::new(),
+            wal_stats,
+            clock: HybridClock::default(),
+            snapshot_dir,
+            checkpoints,
+            unsnapshotted: 0,
+            checkpoint: None,
+            read_only: false,
+            give_back,
+            returned,
         })
     }
 
-    pub async fn run(&mut self) {
+    /// Refuses writes and checkpoints, for serving a restored copy of the data.
+    /// A store whose WAL fails turns read-only by itself.
+    pub fn set_read_only(&mut self) {
+        self.read_only = true;
+    }
+
+    pub async fn run(&mut self, address: &str) {
+        self.spawn_wal();
+
+        let (requests, incoming) = mpsc::channel::<Request>(100);
+        match TcpAdapter::new(address, requests.clone()).await {
+            Ok(adapter) => {
+                task::spawn(adapter.run());
+            }
+            Err(e) => eprintln!("Error listening on {}: {:?}", address, e),
+        }
+        let std_in = tokio::io::BufReader::new(tokio::io::stdin());
+        task::spawn(session::serve(std_in, tokio::io::stdout(), requests));
+
+        self.execute(incoming).await;
+    }
+
+    fn spawn_wal(&self) {
         let wal = Arc::clone(&self.wal);
         task::spawn(async move {
             let mut wal = wal.lock().await;
             wal.run().await;
         });
+    }
 
-        let std_in = tokio::io::stdin();
-        let mut reader = tokio::io::BufReader::new(std_in);
-        let mut std_out = tokio::io::stdout();
+    /// Applies requests from all sessions one at a time, in the order they arrive.
+    async fn execute(&mut self, mut incoming: mpsc::Receiver<Request>) {
+        let period = self
+            .checkpoints
+            .interval
+            // Never fires, the tick is only awaited when there is an interval.
+            .unwrap_or(Duration::from_secs(3600));
+        let mut ticker = time::interval_at(Instant::now() + period, period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
         loop {
-            let op = self.parser.parse(&mut reader).await;
+            let request = tokio::select! {
+                request = incoming.recv() => request,
+                _ = ticker.tick(), if self.checkpoints.interval.is_some() => {
+                    self.checkpoint(None).await;
+                    continue;
+                }
+                Some(undo) = self.returned.recv() => {
+                    self.put_back(undo).await;
+                    continue;
+                }
+            };
+            let Some(Request { mut op, reply }) = request else {
+                break;
+            };
+            op.stamp(self.clock.now());
+            if !self.read_only && self.wal_stats.failed.load(Ordering::Acquire) {
+                eprintln!("The WAL failed, refusing writes from now on");
+                self.read_only = true;
+            }
+            if self.read_only && (op.is_write() || matches!(op, Op::CHECKPOINT { .. })) {
+                let _ = reply.send(Err(MemoryLayerErrors::ReadOnly));
+                continue;
+            }
             match op {
-                Ok(op) => {
-                    for op in op {
-                        self.send_to_wal.send(op.clone()).await.unwrap();
-                        let result = self.store.eval(op);
-                        match result {
-                            Some(str) => {
-                                if let Err(e) = std_out
-                                    .write_all(format!("Result: {}\n", str).as_bytes())
-                                    .await
-                                {
-                                    eprintln!("Error writing to stdout: {:?}", e);
-                                };
-                                std_out.flush().await.unwrap();
-                            }
-                            None => {
-                                if let Err(e) = std_out.write_all("Result: None\n".as_bytes()).await
-                                {
-                                    eprintln!("Error writing to stdout: {:?}\n", e)
-                                };
-                                std_out.flush().await.unwrap();
+                Op::BLPOP { ref keys, .. } | Op::BRPOP { ref keys, .. } => {
+                    let keys = keys.clone();
+                    let front = matches!(op, Op::BLPOP { .. });
+                    match self.store.eval(op.clone()) {
+                        // Nothing to pop yet, park the session until a push arrives.
+                        Ok(Reply::Nil) => self.blocked.block(keys, front, reply),
+                        Ok(popped) => match popped_element(&popped) {
+                            Some((key, value)) => {
+                                self.hand_over(op, key, value, front, reply).await
                             }
+                            None => self.commit(op, popped, reply).await,
+                        },
+                        Err(e) => {
+                            let _ = reply.send(Err(e));
+                        }
+                    }
+                }
+                Op::LPUSH { ref key, .. } | Op::RPUSH { ref key, .. } => {
+                    let key = key.clone();
+                    match self.store.eval(op.clone()) {
+                        Ok(len) => {
+                            self.commit(op, len, reply).await;
+                            self.wake_blocked(key).await;
+                        }
+                        Err(e) => {
+                            let _ = reply.send(Err(e));
                         }
                     }
                 }
+                Op::STATS { .. } => {
+                    let _ = reply.send(self.store.eval(op).map(|stats| self.stats(stats)));
+                }
+                Op::CHECKPOINT { .. } => self.checkpoint(Some(reply)).await,
+                op => match self.store.eval(op.clone()) {
+                    Ok(result) if op.is_write() => self.commit(op, result, reply).await,
+                    result => {
+                        let _ = reply.send(result);
+                    }
+                },
+            }
+        }
+    }
+
+    // Queues an applied write for the WAL and replies once it is durable. The
+    // wait happens off the executor so the writes behind it can join the same
+    // fsync; other sessions may read the new value before it is acknowledged.
+    async fn commit(&mut self, op: Op, result: Reply, reply: ReplySender) {
+        let (committed, durable) = oneshot::channel();
+        let append = Append {
+            op,
+            committed: Some(committed),
+        };
+        self.append(append).await;
+        task::spawn(async move {
+            let result = match durable.await {
+                Ok(lsn) => Ok(Reply::Written(Box::new(result), lsn)),
+                Err(_) => Err(MemoryLayerErrors::NotDurable),
+            };
+            let _ = reply.send(result);
+        });
+    }
+
+    fn stats(&self, store: Reply) -> Reply {
+        let Reply::Array(mut stats) = store else {
+            return store;
+        };
+        for (name, lsn) in [
+            ("last_lsn", &self.wal_stats.written),
+            ("durable_lsn", &self.wal_stats.durable),
+        ] {
+            stats.push(Reply::Bulk(Bytes::from(name)));
+            stats.push(Reply::Integer(lsn.load(Ordering::Acquire) as i64));
+        }
+        Reply::Array(stats)
+    }
+
+    async fn log(&mut self, op: Op) {
+        let append = Append {
+            op,
+            committed: None,
+        };
+        self.append(append).await;
+    }
+
+    async fn append(&mut self, append: Append) {
+        self.send_to_wal
+            .send(WalRequest::Append(append))
+            .await
+            .unwrap();
+        self.unsnapshotted += 1;
+        if self
+            .checkpoints
+            .after_writes
+            .is_some_and(|after| self.unsnapshotted >= after)
+        {
+            self.checkpoint(None).await;
+        }
+    }
+
+    // Snapshots the store as of the last write handed to the WAL. Only copying
+    // the data holds up the executor, the snapshot is written and the WAL
+    // cleaned up in the background. `reply` is for checkpoints asked for by a
+    // client, which are answered with the LSN the snapshot covers.
+    async fn checkpoint(&mut self, reply: Option<ReplySender>) {
+        if self
+            .checkpoint
+            .as_ref()
+            .is_some_and(|running| !running.is_finished())
+        {
+            if let Some(reply) = reply {
+                let _ = reply.send(Err(MemoryLayerErrors::CheckpointRunning));
+            }
+            return;
+        }
+        let (position, covered) = oneshot::channel();
+        self.send_to_wal
+            .send(WalRequest::Checkpoint(position))
+            .await
+            .unwrap();
+        // Nothing is applied while waiting, so the store matches the WAL at `lsn`.
+        let Ok(lsn) = covered.await else {
+            if let Some(reply) = reply {
+                let _ = reply.send(Err(MemoryLayerErrors::CheckpointFailed(
+                    "WAL sync failed".to_string(),
+                )));
+            }
+            return;
+        };
+        self.unsnapshotted = 0;
+        let snapshot = Snapshot {
+            lsn,
+            timestamp: self.clock.now(),
+            entries: self.store.get_snapshot(),
+            indexes: self.store.index_definitions(),
+        };
+        let dir = self.snapshot_dir.clone();
+        let wal = self.send_to_wal.clone();
+        self.checkpoint = Some(task::spawn(async move {
+            let result = match write_checkpoint(snapshot, &dir, wal).await {
+                Ok(()) => Ok(Reply::Integer(lsn as i64)),
                 Err(e) => {
-                    eprintln!("Error parsing input: {:?}", e);
+                    eprintln!("Error writing checkpoint at LSN {}: {:?}", lsn, e);
+                    Err(MemoryLayerErrors::CheckpointFailed(e.to_string()))
                 }
+            };
+            if let Some(reply) = reply {
+                let _ = reply.send(result);
             }
+        }));
+    }
+
+    // Hands elements of a freshly pushed list to blocked sessions, longest waiting first.
+    async fn wake_blocked(&mut self, key: Bytes) {
+        while self.store.llen(key.clone()).is_ok_and(|len| len > 0) {
+            let Some(waiter) = self.blocked.next_waiter(&key) else {
+                break;
+            };
+            let timestamp = self.clock.now();
+            let pop = if waiter.front {
+                Op::LPOP {
+                    timestamp,
+                    key: key.clone(),
+                }
+            } else {
+                Op::RPOP {
+                    timestamp,
+                    key: key.clone(),
+                }
+            };
+            let Ok(Reply::Bulk(value)) = self.store.eval(pop.clone()) else {
+                break;
+            };
+            self.hand_over(pop, key.clone(), value, waiter.front, waiter.reply)
+                .await;
         }
     }
 
-    pub async fn regenerate(&mut self) -> Result<(), BytecodeSerializerError> {
-        let recovered_file = self.wal.lock().await.recover().await;
-        let chunks = BytecodeSerializer::recover_from_bytes(&recovered_file).map_err(|_| {
-            BytecodeSerializerError::DeserializationError("Error deserializing".to_string())
-        })?;
-        for chunk in chunks {
-            self.store.eval(chunk);
+    // Answers a blocking pop once it is durable, like any other write. A session
+    // that timed out in the meantime gets nothing, and the element is given
+    // back to the list.
+    async fn hand_over(
+        &mut self,
+        pop: Op,
+        key: Bytes,
+        value: Bytes,
+        front: bool,
+        reply: ReplySender,
+    ) {
+        let (committed, durable) = oneshot::channel();
+        let append = Append {
+            op: pop,
+            committed: Some(committed),
+        };
+        self.append(append).await;
+        let give_back = self.give_back.clone();
+        task::spawn(async move {
+            let popped = Reply::Array(vec![Reply::Bulk(key.clone()), Reply::Bulk(value.clone())]);
+            let result = match durable.await {
+                Ok(lsn) => Ok(Reply::Written(Box::new(popped), lsn)),
+                Err(_) => Err(MemoryLayerErrors::NotDurable),
+            };
+            if reply.send(result).is_ok() {
+                return;
+            }
+            let undo = if front {
+                Op::LPUSH {
+                    timestamp: 0,
+                    key,
+                    values: vec![value],
+                }
+            } else {
+                Op::RPUSH {
+                    timestamp: 0,
+                    key,
+                    values: vec![value],
+                }
+            };
+            let _ = give_back.send(undo);
+        });
+    }
+
+    // Puts back an element whose session gave up before the pop was acknowledged.
+    async fn put_back(&mut self, mut undo: Op) {
+        undo.stamp(self.clock.now());
+        let key = match &undo {
+            Op::LPUSH { key, .. } | Op::RPUSH { key, .. } => key.clone(),
+            _ => return,
+        };
+        match self.store.eval(undo.clone()) {
+            Ok(_) => {
+                self.log(undo).await;
+                self.wake_blocked(key).await;
+            }
+            Err(e) => eprintln!("Error returning element to {:?}: {}", key, e),
+        }
+    }
+
+    /// Loads the newest snapshot and replays the WAL records after it. A snapshot
+    /// that fails to load is skipped for the one before it, whose records the
+    /// WAL still holds.
+    pub async fn regenerate(&mut self, on_corruption: OnCorruption) -> Result<(), KVStoreError> {
+        let started = Instant::now();
+        let snapshots = snapshot::list(&self.snapshot_dir)
+            .await
+            .map_err(KVStoreError::SnapshotError)?;
+        let mut covered = 0;
+        for (lsn, path) in snapshots.iter().rev() {
+            let restored = match Snapshot::load(path).await {
+                Ok(snapshot) => {
+                    let keys = snapshot.entries.len();
+                    InMemoryLayer::restore(snapshot.entries, snapshot.indexes)
+                        .map(|store| (store, keys, snapshot.timestamp))
+                        .map_err(|e| e.to_string())
+                }
+                Err(e) => Err(e.to_string()),
+            };
+            match restored {
+                Ok((store, keys, timestamp)) => {
+                    self.store = store;
+                    self.clock.observe(timestamp);
+                    covered = *lsn;
+                    eprintln!(
+                        "Loaded snapshot of {} keys at LSN {} in {:?}",
+                        keys,
+                        lsn,
+                        started.elapsed()
+                    );
+                    break;
+                }
+                Err(e) => eprintln!("Skipping snapshot at LSN {}: {}", lsn, e),
+            }
         }
+
+        let reading = Instant::now();
+        let recovery = self
+            .wal
+            .lock()
+            .await
+            .recover(on_corruption, covered)
+            .await
+            .map_err(KVStoreError::WALError)?;
+        if recovery.first_lsn > covered + 1 {
+            return Err(KVStoreError::WALError(WALError::MissingRecords {
+                from: covered + 1,
+                first: recovery.first_lsn,
+            }));
+        }
+        let last = recovery.last_lsn.unwrap_or(0);
+        if last < covered {
+            eprintln!(
+                "WAL ends at LSN {} before the snapshot at LSN {}, new records continue after the snapshot",
+                last, covered
+            );
+        }
+        eprintln!(
+            "Read {} WAL segments in {:?}, skipped {} the snapshot covers",
+            recovery.segments,
+            reading.elapsed(),
+            recovery.covered
+        );
+
+        let replaying = Instant::now();
+        let records = recovery.ops.len();
+        for (_, op) in recovery.ops {
+            // Later ops must sort after the replayed ones even if the wall clock went back.
+            self.clock.observe(op.timestamp());
+            if let Err(e) = self.store.eval(op) {
+                eprintln!("Error replaying WAL record: {}", e);
+            }
+        }
+        eprintln!(
+            "Replayed {} WAL records after LSN {} in {:?}",
+            records,
+            covered,
+            replaying.elapsed()
+        );
+        if recovery.truncated > 0 {
+            eprintln!(
+                "Truncated {} bytes of a torn write at the end of the WAL",
+                recovery.truncated
+            );
+        }
+        if !recovery.skipped.is_empty() {
+            eprintln!("Skipped {} corrupt WAL records", recovery.skipped.len());
+        }
+        for segment in &recovery.out_of_order {
+            eprintln!(
+                "WAL segment {} was created before the segment listed ahead of it",
+                segment.display()
+            );
+        }
+        eprintln!("Started up in {:?}", started.elapsed());
         Ok(())
     }
 }
+
+// Key and element in the reply to a blocking pop.
+fn popped_element(popped: &Reply) -> Option<(Bytes, Bytes)> {
+    let Reply::Array(pair) = popped else {
+        return None;
+    };
+    match pair.as_slice() {
+        [Reply::Bulk(key), Reply::Bulk(value)] => Some((key.clone(), value.clone())),
+        _ => None,
+    }
+}
+
+// Stores the snapshot, then drops the snapshots no longer kept and the WAL
+// segments that every kept snapshot covers.
+async fn write_checkpoint(
+    snapshot: Snapshot,
+    dir: &Path,
+    wal: mpsc::Sender<WalRequest>,
+) -> Result<(), SnapshotError> {
+    snapshot.store(dir).await?;
+    let Some(oldest) = snapshot::prune(dir, KEPT_SNAPSHOTS).await? else {
+        return Ok(());
+    };
+    let (done, deleted) = oneshot::channel();
+    if wal.send(WalRequest::Cleanup(oldest, done)).await.is_ok() {
+        if let Ok(deleted @ 1..) = deleted.await {
+            eprintln!("Deleted {} WAL segments covered by snapshots", deleted);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode_serializer::BytecodeSerializer;
+    use crate::clock::{physical_millis, ManualClock};
+    use crate::errors::FileSystemError;
+    use crate::parser::Parser;
+    use crate::restore::{self, RestorePoint};
+    use crate::types::Value;
+    use std::time::Duration;
+
+    fn test_root(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("kvstore_{}_{}", name, std::process::id()))
+    }
+
+    async fn spawn_store(name: &str) -> mpsc::Sender<Request> {
+        spawn_store_with_clock(name, HybridClock::default()).await
+    }
+
+    async fn spawn_store_with_clock(name: &str, clock: HybridClock) -> mpsc::Sender<Request> {
+        let root = test_root(name);
+        let _ = tokio::fs::remove_dir_all(&root).await;
+        let mut store = open_store(name, CheckpointPolicy::default()).await;
+        store.clock = clock;
+        spawn(store)
+    }
+
+    async fn open_store(name: &str, checkpoints: CheckpointPolicy) -> KvStore {
+        KvStore::new(test_root(name), 10, Durability::Always, checkpoints)
+            .await
+            .unwrap()
+    }
+
+    fn spawn(mut store: KvStore) -> mpsc::Sender<Request> {
+        store.spawn_wal();
+        let (requests, incoming) = mpsc::channel(100);
+        task::spawn(async move { store.execute(incoming).await });
+        requests
+    }
+
+    async fn op(line: &str) -> Op {
+        Parser::new()
+            .parse(line.as_bytes())
+            .await
+            .unwrap()
+            .remove(0)
+    }
+
+    fn popped(key: &str, value: &str) -> Reply {
+        Reply::Array(vec![
+            Reply::Bulk(Bytes::from(key.to_string())),
+            Reply::Bulk(Bytes::from(value.to_string())),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_blocked_pops_are_woken_in_fifo_order() {
+        let requests = spawn_store("blpop_fifo").await;
+        // Sent straight down the channel, the store sees them in this order.
+        let mut waiting = vec![];
+        for _ in 0..2 {
+            let (reply, response) = oneshot::channel();
+            let blpop = Request {
+                op: op("BLPOP jobs 0").await,
+                reply,
+            };
+            requests.send(blpop).await.unwrap();
+            waiting.push(response);
+        }
+
+        let push = session::execute(&requests, op("RPUSH jobs a b").await).await;
+        assert_eq!(
+            push.unwrap().unwrap(),
+            Reply::Written(Box::new(Reply::Integer(2)), 1)
+        );
+        let first = waiting.remove(0).await.unwrap().unwrap();
+        let second = waiting.remove(0).await.unwrap().unwrap();
+        assert_eq!(first, Reply::Written(Box::new(popped("jobs", "a")), 2));
+        assert_eq!(second, Reply::Written(Box::new(popped("jobs", "b")), 3));
+    }
+
+    #[tokio::test]
+    async fn test_blocked_pop_times_out() {
+        let requests = spawn_store("blpop_timeout").await;
+        let timed_out = session::execute(&requests, op("BRPOP jobs 0.05").await).await;
+        assert_eq!(timed_out.unwrap().unwrap(), Reply::Nil);
+
+        // The element is not handed to the session that already gave up.
+        session::execute(&requests, op("LPUSH jobs a").await).await;
+        let len = session::execute(&requests, op("LLEN jobs").await).await;
+        assert_eq!(len.unwrap().unwrap(), Reply::Integer(1));
+    }
+
+    #[tokio::test]
+    async fn test_pop_is_given_back_when_the_session_gives_up_before_the_sync() {
+        let root = test_root("blpop_give_back");
+        let _ = tokio::fs::remove_dir_all(&root).await;
+        let durability = Durability::Interval(Duration::from_millis(200));
+        let store = KvStore::new(root, 10, durability, CheckpointPolicy::default())
+            .await
+            .unwrap();
+        let requests = spawn(store);
+        let blpop = op("BLPOP jobs 0.05").await;
+        let waiting = {
+            let requests = requests.clone();
+            task::spawn(async move { session::execute(&requests, blpop).await })
+        };
+        let push = Request {
+            op: op("RPUSH jobs a").await,
+            reply: oneshot::channel().0,
+        };
+        requests.send(push).await.unwrap();
+        assert_eq!(waiting.await.unwrap().unwrap().unwrap(), Reply::Nil);
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let len = session::execute(&requests, op("LLEN jobs").await).await;
+        assert_eq!(len.unwrap().unwrap(), Reply::Integer(1));
+    }
+
+    #[tokio::test]
+    async fn test_store_turns_read_only_when_the_wal_fails() {
+        let requests = spawn_store("wal_failed").await;
+        session::execute(&requests, Op::new_set(0, "a", "v")).await;
+        let mut failed = open_store("wal_failed", CheckpointPolicy::default()).await;
+        failed.regenerate(OnCorruption::Fail).await.unwrap();
+        failed.wal_stats.failed.store(true, Ordering::Release);
+        let requests = spawn(failed);
+        let set = session::execute(&requests, Op::new_set(0, "b", "v")).await;
+        assert!(matches!(set.unwrap(), Err(MemoryLayerErrors::ReadOnly)));
+        let get = session::execute(&requests, op("GET a").await).await;
+        assert_eq!(get.unwrap().unwrap(), Reply::Bulk(Bytes::from("v")));
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_reports_a_failed_sync() {
+        let mut store = open_store("checkpoint_sync_failed", CheckpointPolicy::default()).await;
+        // A WAL whose sync always fails answers no checkpoint.
+        let (wal, mut wal_requests) = mpsc::channel(10);
+        store.send_to_wal = wal;
+        task::spawn(async move { while wal_requests.recv().await.is_some() {} });
+        let (requests, incoming) = mpsc::channel(10);
+        task::spawn(async move { store.execute(incoming).await });
+        let checkpoint = session::execute(&requests, op("CHECKPOINT").await).await;
+        assert!(matches!(
+            checkpoint.unwrap(),
+            Err(MemoryLayerErrors::CheckpointFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_acknowledged_once_in_the_wal() {
+        let requests = spawn_store("durable_acks").await;
+        let mut writers = vec![];
+        for i in 0..20 {
+            let (requests, set) = (requests.clone(), Op::new_set(0, format!("k{}", i), "v"));
+            writers.push(task::spawn(async move {
+                session::execute(&requests, set).await
+            }));
+        }
+        let mut lsns = vec![];
+        for writer in writers {
+            match writer.await.unwrap().unwrap().unwrap() {
+                Reply::Written(_, lsn) => lsns.push(lsn),
+                reply => panic!("Write acknowledged without an LSN: {:?}", reply),
+            }
+        }
+        lsns.sort();
+        assert_eq!(lsns, (1..=20).collect::<Vec<_>>());
+        let stats = session::execute(&requests, op("STATS").await).await;
+        let expected = ["keys", "last_lsn", "durable_lsn"]
+            .into_iter()
+            .flat_map(|name| [Reply::Bulk(Bytes::from(name)), Reply::Integer(20)])
+            .collect();
+        assert_eq!(stats.unwrap().unwrap(), Reply::Array(expected));
+
+        assert_eq!(logged_ops("durable_acks").await.len(), 20);
+    }
+
+    async fn logged_ops(name: &str) -> Vec<Op> {
+        let mut logged = vec![];
+        let mut segments = tokio::fs::read_dir(test_root(name).join("wal"))
+            .await
+            .unwrap();
+        while let Some(segment) = segments.next_entry().await.unwrap() {
+            if !segment.file_name().to_string_lossy().starts_with("wal_") {
+                continue;
+            }
+            let bytes = tokio::fs::read(segment.path()).await.unwrap();
+            logged.extend(BytecodeSerializer::recover_from_bytes(&bytes).unwrap());
+        }
+        logged
+    }
+
+    #[tokio::test]
+    async fn test_ops_are_stamped_when_accepted() {
+        let wall = ManualClock::default();
+        let clock = HybridClock::new(Box::new(wall.clone()));
+        let requests = spawn_store_with_clock("stamped", clock).await;
+        // The wall clock stalls, then jumps back before moving on.
+        for (millis, key) in [(1_000, "a"), (1_000, "b"), (400, "c"), (2_000, "d")] {
+            wall.set(millis);
+            let set = Op::new_set(0, key, "v");
+            session::execute(&requests, set).await.unwrap().unwrap();
+        }
+
+        let timestamps: Vec<i64> = logged_ops("stamped")
+            .await
+            .iter()
+            .map(Op::timestamp)
+            .collect();
+        assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+        let millis: Vec<i64> = timestamps.into_iter().map(physical_millis).collect();
+        assert_eq!(millis, vec![1_000, 1_000, 1_000, 2_000]);
+    }
+
+    #[tokio::test]
+    async fn test_star_times_come_from_the_stamp() {
+        let wall = ManualClock::default();
+        let clock = HybridClock::new(Box::new(wall.clone()));
+        let requests = spawn_store_with_clock("star_times", clock).await;
+        wall.set(5_000);
+        let add = session::execute(&requests, op("TS.ADD cpu * 1").await).await;
+        assert!(matches!(
+            add.unwrap().unwrap(),
+            Reply::Written(sample, _) if *sample == Reply::Integer(5_000)
+        ));
+        // The wall clock going back does not move stream IDs back.
+        wall.set(4_000);
+        let xadd = session::execute(&requests, op("XADD events * n 1").await).await;
+        assert!(matches!(
+            xadd.unwrap().unwrap(),
+            Reply::Written(id, _) if *id == Reply::Bulk(Bytes::from("5000-0"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_survives_restart() {
+        let requests = spawn_store("checkpoint").await;
+        for key in ["a", "b"] {
+            session::execute(&requests, Op::new_set(0, key, "v")).await;
+        }
+        let checkpoint = session::execute(&requests, op("CHECKPOINT").await).await;
+        assert_eq!(checkpoint.unwrap().unwrap(), Reply::Integer(2));
+        session::execute(&requests, Op::new_set(0, "c", "v")).await;
+
+        let snapshot_dir = test_root("checkpoint").join("snapshot");
+        let snapshots = snapshot::list(&snapshot_dir).await.unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].0, 2);
+
+        let mut restarted = open_store("checkpoint", CheckpointPolicy::default()).await;
+        restarted.regenerate(OnCorruption::Fail).await.unwrap();
+        let keys: Vec<Bytes> = restarted
+            .store
+            .get_snapshot()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec!["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_startup_falls_back_to_older_snapshot() {
+        let requests = spawn_store("snapshot_fallback").await;
+        for key in ["a", "b"] {
+            session::execute(&requests, Op::new_set(0, key, "v")).await;
+            session::execute(&requests, op("CHECKPOINT").await).await;
+        }
+        session::execute(&requests, Op::new_set(0, "c", "v")).await;
+        let snapshot_dir = test_root("snapshot_fallback").join("snapshot");
+        let (lsn, newest) = snapshot::list(&snapshot_dir).await.unwrap().pop().unwrap();
+        assert_eq!(lsn, 2);
+        tokio::fs::write(&newest, b"damaged").await.unwrap();
+
+        let mut restarted = open_store("snapshot_fallback", CheckpointPolicy::default()).await;
+        restarted.regenerate(OnCorruption::Fail).await.unwrap();
+        let keys: Vec<Bytes> = restarted
+            .store
+            .get_snapshot()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec!["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_past_the_end_of_the_wal() {
+        let requests = spawn_store("lost_tail").await;
+        for key in ["a", "b"] {
+            session::execute(&requests, Op::new_set(0, key, "v")).await;
+        }
+        session::execute(&requests, op("CHECKPOINT").await).await;
+        // The records were in the snapshot but never reached the disk.
+        let mut segments = tokio::fs::read_dir(test_root("lost_tail").join("wal"))
+            .await
+            .unwrap();
+        while let Some(segment) = segments.next_entry().await.unwrap() {
+            if segment.file_name().to_string_lossy().starts_with("wal_") {
+                tokio::fs::write(segment.path(), b"").await.unwrap();
+            }
+        }
+
+        let mut restarted = open_store("lost_tail", CheckpointPolicy::default()).await;
+        restarted.regenerate(OnCorruption::Fail).await.unwrap();
+        let requests = spawn(restarted);
+        let set = session::execute(&requests, Op::new_set(0, "c", "v")).await;
+        assert!(matches!(set.unwrap().unwrap(), Reply::Written(_, 3)));
+
+        let mut restarted = open_store("lost_tail", CheckpointPolicy::default()).await;
+        restarted.regenerate(OnCorruption::Fail).await.unwrap();
+        let keys: Vec<Bytes> = restarted
+            .store
+            .get_snapshot()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec!["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_after_write_threshold() {
+        let root = test_root("checkpoint_writes");
+        let _ = tokio::fs::remove_dir_all(&root).await;
+        let checkpoints = CheckpointPolicy {
+            interval: None,
+            after_writes: Some(3),
+        };
+        let requests = spawn(open_store("checkpoint_writes", checkpoints).await);
+        for key in ["a", "b", "c"] {
+            session::execute(&requests, Op::new_set(0, key, "v")).await;
+        }
+        let snapshot_dir = root.join("snapshot");
+        for _ in 0..100 {
+            if !snapshot::list(&snapshot_dir).await.unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let snapshots = snapshot::list(&snapshot_dir).await.unwrap();
+        assert_eq!(
+            snapshots.iter().map(|(lsn, _)| *lsn).collect::<Vec<_>>(),
+            vec![3]
+        );
+    }
+
+    fn strings(state: &Snapshot) -> Vec<(&str, &str)> {
+        state
+            .entries
+            .iter()
+            .map(|(key, value)| match value {
+                Value::String(value) => (
+                    std::str::from_utf8(key).unwrap(),
+                    std::str::from_utf8(value).unwrap(),
+                ),
+                value => panic!("Not a string: {:?}", value),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_restore_to_lsn_and_time() {
+        let wall = ManualClock::default();
+        let clock = HybridClock::new(Box::new(wall.clone()));
+        let requests = spawn_store_with_clock("restore", clock).await;
+        wall.set(1_000);
+        session::execute(&requests, Op::new_set(0, "a", "1")).await;
+        session::execute(&requests, op("CHECKPOINT").await).await;
+        for (millis, key, value) in [(2_000, "a", "2"), (3_000, "b", "3")] {
+            wall.set(millis);
+            session::execute(&requests, Op::new_set(0, key, value)).await;
+        }
+
+        let root = test_root("restore");
+        for (point, lsn, expected) in [
+            (RestorePoint::Lsn(2), 2, vec![("a", "2")]),
+            (RestorePoint::Time(2_500), 2, vec![("a", "2")]),
+            (RestorePoint::Time(1_500), 1, vec![("a", "1")]),
+            (RestorePoint::Time(500), 0, vec![]),
+            (RestorePoint::Lsn(10), 3, vec![("a", "2"), ("b", "3")]),
+        ] {
+            let state = restore::restore(&root, point, OnCorruption::Fail)
+                .await
+                .unwrap();
+            assert_eq!((state.lsn, strings(&state)), (lsn, expected), "{:?}", point);
+        }
+
+        // A restored directory starts up like any other and carries on the LSNs.
+        let state = restore::restore(&root, RestorePoint::Lsn(2), OnCorruption::Fail)
+            .await
+            .unwrap();
+        let _ = tokio::fs::remove_dir_all(test_root("restored")).await;
+        restore::write_data_dir(&test_root("restored"), &state)
+            .await
+            .unwrap();
+        let overwrite = restore::write_data_dir(&test_root("restored"), &state).await;
+        assert!(matches!(
+            overwrite,
+            Err(KVStoreError::FileSystemError(FileSystemError::NotEmpty(_)))
+        ));
+        let mut restored = open_store("restored", CheckpointPolicy::default()).await;
+        restored.regenerate(OnCorruption::Fail).await.unwrap();
+        restored.set_read_only();
+        let requests = spawn(restored);
+        let get = session::execute(&requests, op("GET a").await).await;
+        assert_eq!(get.unwrap().unwrap(), Reply::Bulk(Bytes::from("2")));
+        let set = session::execute(&requests, Op::new_set(0, "c", "v")).await;
+        assert!(matches!(set.unwrap(), Err(MemoryLayerErrors::ReadOnly)));
+
+        let mut restored = open_store("restored", CheckpointPolicy::default()).await;
+        restored.regenerate(OnCorruption::Fail).await.unwrap();
+        let requests = spawn(restored);
+        let set = session::execute(&requests, Op::new_set(0, "c", "v")).await;
+        assert!(matches!(set.unwrap().unwrap(), Reply::Written(_, 3)));
+    }
+}
@@ -1,197 +1,978 @@
+use crate::bytecode_serializer::BytecodeSerializer;
 use crate::errors::WALError;
+use crate::manifest::{Manifest, SegmentInfo};
 use crate::operation::Op;
-use crate::wal_io::WALio;
+use crate::wal_io::{Durability, WALio};
 
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use tokio::fs::{self, File};
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::oneshot;
+use tokio::time::{self, Instant, MissedTickBehavior};
 
 type DeletedFilesCount = usize;
 
+const BATCH_CAPACITY: usize = 64 * 1024;
+
+/// A record for the WAL. `committed` receives the record's LSN once it is as
+/// durable as the configured `Durability` makes it, and is dropped if writing it
+/// failed.
+pub struct Append {
+    pub op: Op,
+    pub committed: Option<oneshot::Sender<u64>>,
+}
+
+/// What the WAL task is asked to do, handled in the order sent.
+pub enum WalRequest {
+    Append(Append),
+    // Syncs the log and answers with the LSN of the last record queued before
+    // it, the position a snapshot taken by the sender at that moment covers.
+    Checkpoint(oneshot::Sender<u64>),
+    // Deletes the sealed segments holding only records up to the LSN and
+    // answers with how many were deleted.
+    Cleanup(u64, oneshot::Sender<usize>),
+}
+
+/// Position of the log, shared with the store for stats. Zero before the first record.
+#[derive(Debug, Default)]
+pub struct WalStats {
+    // LSN of the last record handed to the OS.
+    pub written: AtomicU64,
+    // LSN of the last record acknowledged as durable.
+    pub durable: AtomicU64,
+    // Set once a write or fsync failed. Later records are refused, the store
+    // already applied ops the log may not have.
+    pub failed: AtomicBool,
+}
+
 pub struct WAL {
-    rc: Receiver<Op>,
+    rc: Receiver<WalRequest>,
     io_controller: WALio,
     wal_file_manager: WALFileManager,
+    durability: Durability,
+    // Whether records were written since the last fsync.
+    unsynced: bool,
+    // Acknowledgements of written records waiting for the next fsync, with their LSNs.
+    uncommitted: Vec<(oneshot::Sender<u64>, u64)>,
+    stats: Arc<WalStats>,
 }
 
 impl WAL {
     pub async fn new(
-        rc: Receiver<Op>,
+        rc: Receiver<WalRequest>,
         wal_path: PathBuf,
         wal_filesize_limit: u64,
+        durability: Durability,
     ) -> Result<Self, WALError> {
         let file_manager = WALFileManager::new(wal_path, wal_filesize_limit).await?;
         let file_handle = file_manager.get_file_handler().await?;
-        let io_controller = WALio::new(file_handle, 3);
+        let io_controller = WALio::new(file_handle, BATCH_CAPACITY);
         Ok(Self {
             rc,
             io_controller,
             wal_file_manager: file_manager,
+            durability,
+            unsynced: false,
+            uncommitted: vec![],
+            stats: Arc::default(),
         })
     }
 
+    pub fn stats(&self) -> Arc<WalStats> {
+        Arc::clone(&self.stats)
+    }
+
     pub async fn run(&mut self) -> () {
-        while let Some(op) = self.rc.recv().await {
-            match op {
-                Op::GET {
-                    key: _,
-                    timestamp: __,
-                } => {
-                    continue;
+        let period = match self.durability {
+            Durability::Interval(period) => period,
+            // Never fires, `unsynced` stays false in the other modes.
+            _ => Duration::from_secs(3600),
+        };
+        let mut ticker = time::interval_at(Instant::now() + period, period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                request = self.rc.recv() => match request {
+                    Some(request) => self.handle(request).await,
+                    None => break,
+                },
+                _ = ticker.tick(), if self.unsynced => {
+                    self.sync().await;
                 }
-                _ => {
-                    let serialized = op.into_bytes();
-                    if let Err(e) = self.io_controller.write(serialized).await {
-                        eprintln!("Error writing to WAL: {:?}", e);
+            }
+        }
+        if self.unsynced {
+            self.sync().await;
+        }
+    }
+
+    async fn handle(&mut self, request: WalRequest) {
+        let mut next = Some(request);
+        while let Some(request) = next.take() {
+            match request {
+                WalRequest::Append(append) => next = self.write_batch(append).await,
+                WalRequest::Checkpoint(position) => {
+                    // A snapshot must not cover records a crash could still lose.
+                    if self.sync().await {
+                        let _ = position.send(self.stats.durable.load(Ordering::Acquire));
                     }
                 }
+                WalRequest::Cleanup(covered, done) => {
+                    match self.wal_file_manager.cleanup(covered).await {
+                        Ok(deleted) => {
+                            let _ = done.send(deleted);
+                        }
+                        Err(e) => eprintln!("Error deleting WAL segments: {:?}", e),
+                    }
+                }
+            }
+        }
+    }
+
+    // Writes `append` together with the appends already queued behind it, so
+    // writers that arrive while an fsync is running share the next one. Returns
+    // the first other request found in the queue, to be handled after the batch.
+    async fn write_batch(&mut self, append: Append) -> Option<WalRequest> {
+        let mut batch = vec![];
+        let mut next = Some(WalRequest::Append(append));
+        if self.stats.failed.load(Ordering::Acquire) {
+            // Dropping the acknowledgements tells the writers.
+            while let Some(WalRequest::Append(_)) = next {
+                next = self.rc.try_recv().ok();
+            }
+            return next;
+        }
+        // The segment only accounts for the records once they are written.
+        let mut segment = self.wal_file_manager.active().clone();
+        let mut written = Ok(());
+        while let Some(WalRequest::Append(Append { op, committed })) = next {
+            if op.is_write() && written.is_ok() {
+                let lsn = segment.next_lsn();
+                let record = BytecodeSerializer::record_to_bytes(&op, lsn);
+                segment.append(lsn, &record);
+                written = self.io_controller.write(record).await;
+                batch.extend(committed.map(|committed| (committed, lsn)));
+            }
+            next = self.rc.try_recv().ok();
+        }
+        if let Err(e) = written.and(self.io_controller.flush().await) {
+            // Dropping the acknowledgements tells the writers.
+            eprintln!("Error writing to WAL: {:?}", e);
+            self.stats.failed.store(true, Ordering::Release);
+            return next;
+        }
+        *self.wal_file_manager.active_mut() = segment;
+        let last_lsn = self.wal_file_manager.active().next_lsn() - 1;
+        self.stats.written.store(last_lsn, Ordering::Release);
+        self.uncommitted.extend(batch);
+        match self.durability {
+            Durability::Always => {
+                self.sync().await;
+            }
+            Durability::Interval(_) => self.unsynced = true,
+            Durability::Os => self.commit(),
+        }
+        match self.wal_file_manager.is_full().await {
+            Ok(true) => {
+                // The sealed segment is synced before the manifest describes it.
+                if self.unsynced {
+                    self.sync().await;
+                }
+                if let Err(e) = self.rotate().await {
+                    eprintln!("Error rotating WAL file: {:?}", e);
+                }
             }
-            let new_file = self.wal_file_manager.size_rotate().await;
-            match new_file {
-                Ok(opt) => {
-                    if let Some(file) = opt {
-                        self.io_controller.set_new_file_handle(file)
+            Ok(false) => {}
+            Err(e) => eprintln!("Error rotating WAL file: {:?}", e),
+        }
+        next
+    }
+
+    async fn rotate(&mut self) -> Result<(), WALError> {
+        self.wal_file_manager.rotate().await?;
+        let file = self.wal_file_manager.get_file_handler().await?;
+        self.io_controller.set_new_file_handle(file);
+        Ok(())
+    }
+
+    async fn sync(&mut self) -> bool {
+        match self.io_controller.sync().await {
+            Ok(()) => {
+                self.unsynced = false;
+                self.commit();
+                true
+            }
+            Err(e) => {
+                eprintln!("Error syncing WAL: {:?}", e);
+                self.stats.failed.store(true, Ordering::Release);
+                self.uncommitted.clear();
+                false
+            }
+        }
+    }
+
+    fn commit(&mut self) {
+        let written = self.stats.written.load(Ordering::Acquire);
+        self.stats.durable.store(written, Ordering::Release);
+        for (committed, lsn) in self.uncommitted.drain(..) {
+            // The writer may have gone away, the record is written regardless.
+            let _ = committed.send(lsn);
+        }
+    }
+
+    /// Reads back every segment, oldest first. Each segment is expected to be
+    /// created after the one listed before it, one that is not means files were
+    /// restored out of order or the clock went back, and is reported in
+    /// `Recovery::out_of_order` but still replayed.
+    ///
+    /// Damaged records followed by nothing valid at the end of the newest segment
+    /// are a write torn by a crash and are cut off the file. Damage anywhere else
+    /// fails recovery unless `on_corruption` says to skip it.
+    ///
+    /// Only records after LSN `after`, the one a snapshot covers, are returned.
+    /// Sealed segments the manifest shows to hold nothing later are not read.
+    pub async fn recover(
+        &mut self,
+        on_corruption: OnCorruption,
+        after: u64,
+    ) -> Result<Recovery, WALError> {
+        let mut recovery = Recovery::default();
+        let manifest = self.wal_file_manager.segment_infos().to_vec();
+        let newest = manifest.len() - 1;
+        let paths = self.wal_file_manager.segments();
+        let mut read: Vec<SegmentInfo> = Vec::with_capacity(manifest.len());
+        recovery.first_lsn = manifest[0].first_lsn;
+        for (index, (listed, segment)) in manifest.iter().zip(paths).enumerate() {
+            if index > 0 && listed.created <= manifest[index - 1].created {
+                recovery.out_of_order.push(segment.clone());
+            }
+            // Segments listed without records may just not have been read yet.
+            let covered = listed.last_lsn.is_some_and(|last| last <= after);
+            if self.wal_file_manager.has_manifest() && index < newest && covered {
+                recovery.covered += 1;
+                read.push(listed.clone());
+                continue;
+            }
+            let bytes = fs::read(&segment).await?;
+            // A segment carries on from the one before unless it starts past LSNs
+            // a snapshot claimed.
+            let first_lsn = read.last().map_or(listed.first_lsn, |previous| {
+                previous.next_lsn().max(listed.first_lsn)
+            });
+            let mut info = SegmentInfo::new(listed.created, first_lsn);
+            let mut ops = vec![];
+            let mut offset = 0;
+            while offset < bytes.len() {
+                let error = match BytecodeSerializer::read_record(&bytes, offset) {
+                    Ok((record, next)) => {
+                        // Records from before LSNs were written are numbered in order.
+                        let lsn = record.lsn.unwrap_or(info.next_lsn());
+                        if lsn != info.next_lsn() {
+                            eprintln!(
+                                "WAL record in {} at byte {} has LSN {} where {} was expected",
+                                segment.display(),
+                                offset,
+                                lsn,
+                                info.next_lsn()
+                            );
+                        }
+                        info.append(lsn, &bytes[offset..next]);
+                        if lsn > after {
+                            ops.push((lsn, record.op));
+                        }
+                        offset = next;
+                        continue;
                     }
+                    Err(e) => e,
+                };
+                let next = BytecodeSerializer::next_valid_record(&bytes, offset);
+                if next.is_none() && index == newest {
+                    truncate(&segment, offset as u64).await?;
+                    recovery.truncated = (bytes.len() - offset) as u64;
+                    break;
                 }
-                Err(e) => eprintln!("Error rotating WAL file: {:?}", e),
+                if on_corruption == OnCorruption::Fail {
+                    return Err(WALError::Corrupted {
+                        segment: segment.display().to_string(),
+                        offset,
+                        reason: format!("{:?}", error),
+                    });
+                }
+                eprintln!(
+                    "Skipping corrupt WAL record in {} at byte {}: {:?}",
+                    segment.display(),
+                    offset,
+                    error
+                );
+                recovery.skipped.push((segment.clone(), offset));
+                offset = next.unwrap_or(bytes.len());
+            }
+            // Only sealed segments are described in full, the active one grows
+            // after the manifest was last written.
+            let sealed = index < newest;
+            if self.wal_file_manager.has_manifest() && sealed && info != *listed {
+                if on_corruption == OnCorruption::Fail {
+                    return Err(WALError::ManifestMismatch(segment.display().to_string()));
+                }
+                eprintln!(
+                    "WAL segment {} does not match the manifest, replaying what is there",
+                    segment.display()
+                );
+            }
+            recovery.segments += 1;
+            recovery.ops.extend(ops);
+            read.push(info);
+        }
+        // The active segment may still be empty, its first LSN follows the last record.
+        recovery.last_lsn = read
+            .last()
+            .map(|segment| segment.next_lsn() - 1)
+            .filter(|lsn| *lsn > 0);
+        self.wal_file_manager.reconcile(read).await?;
+        // The snapshot may cover records that were never synced and are gone
+        // after a crash, their LSNs must not be handed out again.
+        if self.wal_file_manager.active().next_lsn() <= after {
+            if self.wal_file_manager.active().last_lsn.is_some() {
+                self.rotate().await?;
             }
+            self.wal_file_manager.start_at(after + 1).await?;
         }
+        let last_lsn = self.wal_file_manager.active().next_lsn() - 1;
+        self.stats.written.store(last_lsn, Ordering::Release);
+        self.stats.durable.store(last_lsn, Ordering::Release);
+        Ok(recovery)
     }
-    // Update them ethod so it recovers from all files in the WAL directory
-    pub async fn recover(&mut self) -> Vec<u8> {
-        if let Ok(buffer) = self.io_controller.recover().await {
-            buffer
-        } else {
-            Vec::new()
+}
+
+/// What recovery does with damaged records before the end of the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnCorruption {
+    Fail,
+    // Replays around them, losing the damaged records.
+    Skip,
+}
+
+#[derive(Debug, Default)]
+pub struct Recovery {
+    // Records to replay with their LSNs.
+    pub ops: Vec<(u64, Op)>,
+    // LSN the oldest segment starts at, records before it were cleaned up.
+    pub first_lsn: u64,
+    pub last_lsn: Option<u64>,
+    // Segments read, and segments skipped as covered by the snapshot.
+    pub segments: usize,
+    pub covered: usize,
+    // Bytes of a torn write cut off the newest segment.
+    pub truncated: u64,
+    // Segment and offset of every damaged record that was skipped.
+    pub skipped: Vec<(PathBuf, usize)>,
+    // Segments created no later than the segment listed before them.
+    pub out_of_order: Vec<PathBuf>,
+}
+
+async fn truncate(segment: &Path, len: u64) -> Result<(), WALError> {
+    let file = File::options().write(true).open(segment).await?;
+    file.set_len(len).await?;
+    file.sync_all().await?;
+    Ok(())
+}
+
+/// Reads the records after LSN `after` without opening the WAL for writing, so
+/// nothing in the directory changes and a server may keep running on it. Damage
+/// at the end of the newest segment is taken for a write in progress.
+pub async fn read_log(
+    wal_path: &Path,
+    after: u64,
+    on_corruption: OnCorruption,
+) -> Result<Recovery, WALError> {
+    let manifest = match Manifest::load(wal_path).await? {
+        Some(manifest) => manifest,
+        None => WALFileManager::scan(wal_path).await?,
+    };
+    let mut recovery = Recovery::default();
+    let Some(oldest) = manifest.segments.first() else {
+        return Ok(recovery);
+    };
+    recovery.first_lsn = oldest.first_lsn;
+    let mut next_lsn = oldest.first_lsn;
+    let newest = manifest.segments.len() - 1;
+    for (index, listed) in manifest.segments.iter().enumerate() {
+        let segment = wal_path.join(&listed.name);
+        let bytes = fs::read(&segment).await?;
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let error = match BytecodeSerializer::read_record(&bytes, offset) {
+                Ok((record, next)) => {
+                    let lsn = record.lsn.unwrap_or(next_lsn);
+                    if lsn > after {
+                        recovery.ops.push((lsn, record.op));
+                    }
+                    next_lsn = lsn + 1;
+                    offset = next;
+                    continue;
+                }
+                Err(e) => e,
+            };
+            let next = BytecodeSerializer::next_valid_record(&bytes, offset);
+            if next.is_none() && index == newest {
+                break;
+            }
+            if on_corruption == OnCorruption::Fail {
+                return Err(WALError::Corrupted {
+                    segment: segment.display().to_string(),
+                    offset,
+                    reason: format!("{:?}", error),
+                });
+            }
+            recovery.skipped.push((segment.clone(), offset));
+            offset = next.unwrap_or(bytes.len());
         }
+        recovery.segments += 1;
     }
+    recovery.last_lsn = Some(next_lsn - 1).filter(|lsn| *lsn > 0);
+    Ok(recovery)
+}
+
+fn segment_timestamp(path: &Path) -> Option<i64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("wal_")?
+        .parse()
+        .ok()
 }
 
-// file names are in the format wal_{timestamp} where timestamp is the time the file was created
+// File names are in the format wal_{timestamp} where timestamp is the time the file
+// was created. The manifest lists them in order, the names are only read when a
+// directory without a manifest is opened.
 pub struct WALFileManager {
     wal_path: PathBuf,
-    wal_dir_files: Vec<PathBuf>,
+    manifest: Manifest,
+    // Whether the segments were listed by a manifest rather than a directory scan.
+    has_manifest: bool,
     file_size: u64,
-    latest_file: PathBuf,
 }
 
 impl WALFileManager {
     pub async fn new(wal_path: PathBuf, file_size: u64) -> Result<Self, WALError> {
-        let mut dir_contents = fs::read_dir(&wal_path).await?;
-        let mut wal_dir_files = Vec::new();
-        while let Some(entry) = dir_contents.next_entry().await? {
-            wal_dir_files.push(entry.path());
-        }
-
-        let latest_file = {
-            if wal_dir_files.is_empty() {
-                let timestamp = chrono::Utc::now().timestamp();
-                let file_name = wal_path.join(format!("wal_{}", timestamp));
-                let _file = fs::File::create(&file_name).await?;
-                wal_dir_files.push(file_name.clone());
-                file_name
-            } else {
-                wal_dir_files.sort();
-                wal_dir_files
-                    .last()
-                    .expect("At least 1 file should be present")
-                    .clone()
-            }
+        let (manifest, has_manifest) = match Manifest::load(&wal_path).await? {
+            Some(manifest) => (manifest, true),
+            None => (Self::scan(&wal_path).await?, false),
         };
-
-        Ok(Self {
+        let mut manager = Self {
             wal_path,
-            wal_dir_files,
-            latest_file,
+            manifest,
+            has_manifest,
             file_size,
+        };
+        if manager.manifest.segments.is_empty() {
+            manager.rotate().await?;
+        } else {
+            manager.manifest.store(&manager.wal_path).await?;
+        }
+        Ok(manager)
+    }
+
+    // Builds a manifest for a directory written before there was one. Record
+    // counts and checksums are filled in by recovery.
+    async fn scan(wal_path: &Path) -> Result<Manifest, WALError> {
+        let mut dir_contents = fs::read_dir(wal_path).await?;
+        let mut created = Vec::new();
+        while let Some(entry) = dir_contents.next_entry().await? {
+            created.extend(segment_timestamp(&entry.path()));
+        }
+        created.sort();
+        Ok(Manifest {
+            segments: created
+                .into_iter()
+                .map(|created| SegmentInfo::new(created, 1))
+                .collect(),
         })
     }
 
-    pub fn get_latest_file(&self) -> &Path {
-        &self.latest_file
+    /// Starts an empty log whose first record gets `first_lsn`, for a data
+    /// directory restored from a snapshot at the LSN before it.
+    pub async fn create(wal_path: &Path, first_lsn: u64) -> Result<(), WALError> {
+        let segment = SegmentInfo::new(chrono::Utc::now().timestamp(), first_lsn);
+        fs::File::create(wal_path.join(&segment.name)).await?;
+        Manifest {
+            segments: vec![segment],
+        }
+        .store(wal_path)
+        .await
+    }
+
+    /// Numbers the next record `next_lsn`, the active segment must still be empty.
+    pub async fn start_at(&mut self, next_lsn: u64) -> Result<(), WALError> {
+        self.active_mut().first_lsn = next_lsn;
+        self.manifest.store(&self.wal_path).await
     }
 
+    pub fn get_latest_file(&self) -> PathBuf {
+        self.wal_path.join(&self.active().name)
+    }
+
+    /// Segment files, oldest first.
+    pub fn segments(&self) -> Vec<PathBuf> {
+        self.manifest
+            .segments
+            .iter()
+            .map(|segment| self.wal_path.join(&segment.name))
+            .collect()
+    }
+
+    pub fn has_manifest(&self) -> bool {
+        self.has_manifest
+    }
+
+    pub fn segment_infos(&self) -> &[SegmentInfo] {
+        &self.manifest.segments
+    }
+
+    /// The segment being written to.
+    pub fn active(&self) -> &SegmentInfo {
+        self.manifest
+            .segments
+            .last()
+            .expect("There is always an active segment")
+    }
+
+    pub fn active_mut(&mut self) -> &mut SegmentInfo {
+        self.manifest
+            .segments
+            .last_mut()
+            .expect("There is always an active segment")
+    }
+
+    /// Replaces what the manifest says about the segments with what was read back.
+    pub async fn reconcile(&mut self, segments: Vec<SegmentInfo>) -> Result<(), WALError> {
+        if segments != self.manifest.segments {
+            self.manifest.segments = segments;
+            self.manifest.store(&self.wal_path).await?;
+        }
+        Ok(())
+    }
+
+    /// Starts a new segment. The manifest records the sealed one as it is now,
+    /// so it has to be synced before.
     pub async fn rotate(&mut self) -> Result<(), WALError> {
-        let timestamp = chrono::Utc::now().timestamp();
-        let file_name = self.wal_path.join(format!("wal_{}", timestamp));
-        let _file = fs::File::create(&file_name).await?;
-        self.wal_dir_files.push(file_name.clone());
+        let now = chrono::Utc::now().timestamp();
+        let segment = match self.manifest.segments.last() {
+            // Two rotations within a second must not reuse, and truncate, a name.
+            Some(active) => SegmentInfo::new(now.max(active.created + 1), active.next_lsn()),
+            None => SegmentInfo::new(now, 1),
+        };
+        let _file = fs::File::create(self.wal_path.join(&segment.name)).await?;
+        self.manifest.segments.push(segment);
+        self.manifest.store(&self.wal_path).await?;
         Ok(())
     }
 
-    pub async fn timed_cleanup(
-        &mut self,
-        to_point_in_time: i64,
-    ) -> Result<DeletedFilesCount, WALError> {
-        let files_to_delete: Vec<&PathBuf> = self
-            .wal_dir_files
+    /// Deletes the oldest segments as long as every record in them has an LSN up
+    /// to `covered`, never the active one. The manifest drops them first, so a
+    /// crash in between leaves unlisted files behind rather than missing ones.
+    pub async fn cleanup(&mut self, covered: u64) -> Result<DeletedFilesCount, WALError> {
+        let sealed = self.manifest.segments.len() - 1;
+        let obsolete = self.manifest.segments[..sealed]
             .iter()
-            .filter(|file_path| {
-                file_path
-                    .file_name()
-                    .and_then(|f| f.to_str())
-                    .and_then(|file_name| file_name.split_once('_'))
-                    .and_then(|(_, ts_str)| ts_str.parse::<i64>().ok())
-                    .map_or(false, |ts| ts < to_point_in_time)
-            })
-            .collect();
-
-        if files_to_delete.len() == 0 {
+            .take_while(|segment| segment.next_lsn() <= covered + 1)
+            .count();
+        if obsolete == 0 {
             return Ok(0);
         }
+        let files_to_delete: Vec<PathBuf> = self
+            .manifest
+            .segments
+            .drain(..obsolete)
+            .map(|segment| self.wal_path.join(&segment.name))
+            .collect();
+        self.manifest.store(&self.wal_path).await?;
+
         let mut delete_futures = FuturesUnordered::new();
         let mut removed_files_counter: DeletedFilesCount = 0;
-        let mut success_deleted_files = vec![];
 
         for file in files_to_delete {
-            let filepath_clone = file.to_owned();
-            delete_futures.push(async move {
-                fs::remove_file(&filepath_clone)
-                    .await
-                    .map(|_| filepath_clone)
-            })
+            delete_futures.push(async move { fs::remove_file(&file).await.map(|_| file) })
         }
 
         while let Some(result) = delete_futures.next().await {
             match result {
-                Ok(file) => {
-                    removed_files_counter += 1;
-                    success_deleted_files.push(file);
-                }
-                Err(e) => println!("Err deleting file: {}", e),
+                Ok(_) => removed_files_counter += 1,
+                Err(e) => eprintln!("Error deleting WAL segment: {}", e),
             }
         }
 
-        self.wal_dir_files
-            .retain(|file_path| !success_deleted_files.contains(file_path));
-
-        if self.wal_dir_files.len() == 0 {
-            self.rotate().await?;
-        }
-
         Ok(removed_files_counter)
     }
 
-    pub async fn size_rotate(&mut self) -> Result<Option<File>, WALError> {
-        let file_size = fs::metadata(&self.latest_file).await?.len();
-        if file_size >= self.file_size {
-            self.rotate().await?;
-            return Ok(Some(fs::File::open(&self.latest_file).await?));
-        }
-
-        Ok(None)
+    pub async fn is_full(&self) -> Result<bool, WALError> {
+        let file_size = fs::metadata(self.get_latest_file()).await?.len();
+        Ok(file_size >= self.file_size)
     }
 
     async fn get_file_handler(&self) -> Result<File, WALError> {
         let file = File::options()
-            .read(true)
-            .write(true)
-            .open(&self.latest_file)
+            .append(true)
+            .open(self.get_latest_file())
             .await?;
 
         Ok(file)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_recover_replays_every_segment_in_order() {
+        let dir = std::env::temp_dir().join(format!("wal_segments_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir).await;
+        fs::create_dir_all(&dir).await.unwrap();
+        // wal_99 sorts after wal_100 by name but was created first.
+        let segments = [
+            (
+                "wal_99",
+                vec![Op::new_set(1, "a", "1"), Op::new_set(2, "b", "2")],
+            ),
+            ("wal_100", vec![Op::new_del(3, "a")]),
+        ];
+        for (name, ops) in &segments {
+            let bytes: Vec<u8> = ops.iter().flat_map(|op| op.into_bytes()).collect();
+            fs::write(dir.join(name), bytes).await.unwrap();
+        }
+
+        let (_tx, rx) = tokio::sync::mpsc::channel(1);
+        let mut wal = WAL::new(rx, dir.clone(), 1024, Durability::Os)
+            .await
+            .unwrap();
+        let recovery = wal.recover(OnCorruption::Fail, 0).await.unwrap();
+        assert_eq!(recovery.segments, 2);
+        assert!(recovery.out_of_order.is_empty());
+        assert_eq!(
+            recovery.ops,
+            vec![
+                (1, Op::new_set(1, "a", "1")),
+                (2, Op::new_set(2, "b", "2")),
+                (3, Op::new_del(3, "a")),
+            ]
+        );
+
+        // New segments keep sorting after the existing ones.
+        wal.wal_file_manager.rotate().await.unwrap();
+        let latest = wal.wal_file_manager.get_latest_file().to_path_buf();
+        assert_eq!(wal.wal_file_manager.segments().last(), Some(&latest));
+        assert!(segment_timestamp(&latest).unwrap() > 100);
+    }
+
+    #[tokio::test]
+    async fn test_run_writes_every_record() {
+        let dir = std::env::temp_dir().join(format!("wal_run_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir).await;
+        fs::create_dir_all(&dir).await.unwrap();
+        let interval = Durability::Interval(Duration::from_millis(5));
+        for durability in [Durability::Always, interval, Durability::Os] {
+            let (tx, rx) = tokio::sync::mpsc::channel(8);
+            let mut wal = WAL::new(rx, dir.clone(), 1024 * 1024, durability)
+                .await
+                .unwrap();
+            let writer = tokio::spawn(async move {
+                wal.run().await;
+                wal
+            });
+            let (committed, durable) = oneshot::channel();
+            let set = Append {
+                op: Op::new_set(0, "a", "1"),
+                committed: Some(committed),
+            };
+            tx.send(WalRequest::Append(set)).await.unwrap();
+            let get = Append {
+                op: Op::new_get(0, "a"),
+                committed: None,
+            };
+            tx.send(WalRequest::Append(get)).await.unwrap();
+            durable.await.unwrap();
+            drop(tx);
+            let mut wal = writer.await.unwrap();
+            let recovery = wal.recover(OnCorruption::Fail, 0).await.unwrap();
+            let (lsn, op) = recovery.ops.last().unwrap();
+            assert_eq!(op, &Op::new_set(0, "a", "1"));
+            assert_eq!(Some(*lsn), recovery.last_lsn);
+        }
+    }
+
+    async fn write_segments(name: &str, segments: &[(&str, Vec<u8>)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir).await;
+        fs::create_dir_all(&dir).await.unwrap();
+        for (segment, bytes) in segments {
+            fs::write(dir.join(segment), bytes).await.unwrap();
+        }
+        dir
+    }
+
+    async fn recover(dir: &Path, on_corruption: OnCorruption) -> Result<Recovery, WALError> {
+        let (_tx, rx) = tokio::sync::mpsc::channel(1);
+        let mut wal = WAL::new(rx, dir.to_path_buf(), 1024, Durability::Os)
+            .await
+            .unwrap();
+        wal.recover(on_corruption, 0).await
+    }
+
+    #[tokio::test]
+    async fn test_torn_tail_is_truncated() {
+        let mut bytes = Op::new_set(1, "a", "1").into_bytes();
+        let good = bytes.len();
+        let torn = Op::new_set(2, "b", "2").into_bytes();
+        bytes.extend(&torn[..torn.len() - 3]);
+        let dir = write_segments("wal_torn", &[("wal_1", bytes)]).await;
+
+        let recovery = recover(&dir, OnCorruption::Fail).await.unwrap();
+        assert_eq!(recovery.ops, vec![(1, Op::new_set(1, "a", "1"))]);
+        assert_eq!(recovery.truncated, (torn.len() - 3) as u64);
+        let len = fs::metadata(dir.join("wal_1")).await.unwrap().len();
+        assert_eq!(len, good as u64);
+    }
+
+    #[tokio::test]
+    async fn test_corruption_mid_log_fails_unless_skipped() {
+        let mut damaged = Op::new_set(1, "a", "1").into_bytes();
+        damaged[6] ^= 0xff;
+        damaged.extend(Op::new_set(2, "b", "2").into_bytes());
+        let segments = [
+            ("wal_1", damaged),
+            ("wal_2", Op::new_del(3, "b").into_bytes()),
+        ];
+        let dir = write_segments("wal_corrupt", &segments).await;
+
+        let error = recover(&dir, OnCorruption::Fail).await.unwrap_err();
+        assert!(matches!(error, WALError::Corrupted { offset: 0, .. }));
+
+        let recovery = recover(&dir, OnCorruption::Skip).await.unwrap();
+        assert_eq!(
+            recovery.ops,
+            vec![(1, Op::new_set(2, "b", "2")), (2, Op::new_del(3, "b"))]
+        );
+        assert_eq!(recovery.skipped, vec![(dir.join("wal_1"), 0)]);
+    }
+
+    #[tokio::test]
+    async fn test_manifest_follows_rotation() {
+        let dir = write_segments("wal_manifest", &[]).await;
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        // Small enough that every batch fills a segment.
+        let mut wal = WAL::new(rx, dir.clone(), 1, Durability::Always)
+            .await
+            .unwrap();
+        let writer = tokio::spawn(async move { wal.run().await });
+        for key in ["a", "b"] {
+            let (committed, durable) = oneshot::channel();
+            let append = Append {
+                op: Op::new_set(0, key, "1"),
+                committed: Some(committed),
+            };
+            tx.send(WalRequest::Append(append)).await.unwrap();
+            durable.await.unwrap();
+        }
+        drop(tx);
+        writer.await.unwrap();
+
+        let manifest = Manifest::load(&dir).await.unwrap().unwrap();
+        let lsns: Vec<_> = manifest
+            .segments
+            .iter()
+            .map(|segment| (segment.first_lsn, segment.last_lsn, segment.records))
+            .collect();
+        assert_eq!(lsns, vec![(1, Some(1), 1), (2, Some(2), 1), (3, None, 0)]);
+        let recovery = recover(&dir, OnCorruption::Fail).await.unwrap();
+        assert_eq!(recovery.ops.len(), 2);
+
+        // A sealed segment that changed after it was described is not trusted.
+        let first = dir.join(&manifest.segments[0].name);
+        let mut bytes = fs::read(&first).await.unwrap();
+        bytes.extend(Op::new_del(0, "a").into_bytes());
+        fs::write(&first, bytes).await.unwrap();
+        let error = recover(&dir, OnCorruption::Fail).await.unwrap_err();
+        assert!(matches!(error, WALError::ManifestMismatch(_)));
+    }
+
+    // Starts a WAL where every record fills a segment of its own and writes a
+    // record for each key.
+    async fn run_one_record_per_segment(
+        dir: &Path,
+        keys: &[&str],
+    ) -> (mpsc::Sender<WalRequest>, tokio::task::JoinHandle<()>) {
+        let (tx, rx) = mpsc::channel(8);
+        let mut wal = WAL::new(rx, dir.to_path_buf(), 1, Durability::Always)
+            .await
+            .unwrap();
+        let writer = tokio::spawn(async move { wal.run().await });
+        for key in keys {
+            let (committed, durable) = oneshot::channel();
+            let append = Append {
+                op: Op::new_set(0, key.to_string(), "1"),
+                committed: Some(committed),
+            };
+            tx.send(WalRequest::Append(append)).await.unwrap();
+            durable.await.unwrap();
+        }
+        (tx, writer)
+    }
+
+    #[tokio::test]
+    async fn test_recover_skips_segments_the_snapshot_covers() {
+        let dir = write_segments("wal_covered", &[]).await;
+        let (tx, writer) = run_one_record_per_segment(&dir, &["a", "b", "c"]).await;
+        drop(tx);
+        writer.await.unwrap();
+        // Covered segments are not even read.
+        let manifest = Manifest::load(&dir).await.unwrap().unwrap();
+        fs::write(dir.join(&manifest.segments[0].name), b"garbage")
+            .await
+            .unwrap();
+
+        let (_tx, rx) = mpsc::channel(1);
+        let mut wal = WAL::new(rx, dir.clone(), 1, Durability::Os).await.unwrap();
+        let recovery = wal.recover(OnCorruption::Fail, 2).await.unwrap();
+        assert_eq!(recovery.ops, vec![(3, Op::new_set(0, "c", "1"))]);
+        assert_eq!((recovery.covered, recovery.segments), (2, 2));
+        assert_eq!(recovery.last_lsn, Some(3));
+        assert_eq!(Manifest::load(&dir).await.unwrap().unwrap(), manifest);
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_deletes_covered_segments() {
+        let dir = write_segments("wal_cleanup", &[]).await;
+        let (tx, writer) = run_one_record_per_segment(&dir, &["a", "b", "c"]).await;
+        let (position, covered) = oneshot::channel();
+        tx.send(WalRequest::Checkpoint(position)).await.unwrap();
+        assert_eq!(covered.await.unwrap(), 3);
+
+        for expected in [2, 0] {
+            let (done, deleted) = oneshot::channel();
+            tx.send(WalRequest::Cleanup(2, done)).await.unwrap();
+            assert_eq!(deleted.await.unwrap(), expected);
+        }
+        drop(tx);
+        writer.await.unwrap();
+
+        let recovery = recover(&dir, OnCorruption::Fail).await.unwrap();
+        assert_eq!(recovery.ops, vec![(3, Op::new_set(0, "c", "1"))]);
+        let manifest = Manifest::load(&dir).await.unwrap().unwrap();
+        assert_eq!(manifest.segments.len(), 2);
+        assert_eq!(manifest.segments[1].first_lsn, 4);
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_is_not_counted_and_stops_the_log() {
+        let dir = write_segments("wal_failed_write", &[]).await;
+        let (tx, rx) = mpsc::channel(8);
+        let mut wal = WAL::new(rx, dir.clone(), 1024 * 1024, Durability::Always)
+            .await
+            .unwrap();
+        // Writes through a read-only handle fail.
+        let read_only = File::open(wal.wal_file_manager.get_latest_file())
+            .await
+            .unwrap();
+        wal.io_controller.set_new_file_handle(read_only);
+        let stats = wal.stats();
+        let writer = tokio::spawn(async move {
+            wal.run().await;
+            wal
+        });
+        for key in ["a", "b"] {
+            let (committed, durable) = oneshot::channel();
+            let set = Append {
+                op: Op::new_set(0, key, "1"),
+                committed: Some(committed),
+            };
+            tx.send(WalRequest::Append(set)).await.unwrap();
+            assert!(durable.await.is_err());
+            assert!(stats.failed.load(Ordering::Acquire));
+        }
+        drop(tx);
+        let wal = writer.await.unwrap();
+        assert_eq!(wal.wal_file_manager.active().records, 0);
+        assert_eq!(wal.wal_file_manager.active().next_lsn(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_segments_created_out_of_order_are_reported() {
+        let dir = write_segments(
+            "wal_out_of_order",
+            &[("wal_200", vec![]), ("wal_100", vec![])],
+        )
+        .await;
+        let manifest = Manifest {
+            segments: vec![SegmentInfo::new(200, 1), SegmentInfo::new(100, 1)],
+        };
+        manifest.store(&dir).await.unwrap();
+
+        let recovery = recover(&dir, OnCorruption::Fail).await.unwrap();
+        assert_eq!(recovery.segments, 2);
+        assert_eq!(recovery.out_of_order, vec![dir.join("wal_100")]);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_waits_for_sync() {
+        let dir = write_segments("wal_checkpoint_sync", &[]).await;
+        let (tx, rx) = mpsc::channel(8);
+        let never = Durability::Interval(Duration::from_secs(3600));
+        let mut wal = WAL::new(rx, dir.clone(), 1024 * 1024, never).await.unwrap();
+        let stats = wal.stats();
+        let writer = tokio::spawn(async move { wal.run().await });
+        for key in ["a", "b"] {
+            let set = Append {
+                op: Op::new_set(0, key, "1"),
+                committed: None,
+            };
+            tx.send(WalRequest::Append(set)).await.unwrap();
+        }
+        let (position, covered) = oneshot::channel();
+        tx.send(WalRequest::Checkpoint(position)).await.unwrap();
+        assert_eq!(covered.await.unwrap(), 2);
+        assert_eq!(stats.durable.load(Ordering::Acquire), 2);
+        drop(tx);
+        writer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recovery_skips_lsns_the_snapshot_claims() {
+        let dir = write_segments(
+            "wal_lost_tail",
+            &[("wal_1", Op::new_set(0, "a", "1").into_bytes())],
+        )
+        .await;
+        let (tx, rx) = mpsc::channel(8);
+        let mut wal = WAL::new(rx, dir.clone(), 1024 * 1024, Durability::Always)
+            .await
+            .unwrap();
+        // The snapshot covers up to 5, records 2 to 5 were lost in a crash.
+        let recovery = wal.recover(OnCorruption::Fail, 5).await.unwrap();
+        assert_eq!(recovery.last_lsn, Some(1));
+        let writer = tokio::spawn(async move { wal.run().await });
+        let (committed, durable) = oneshot::channel();
+        let set = Append {
+            op: Op::new_set(0, "b", "1"),
+            committed: Some(committed),
+        };
+        tx.send(WalRequest::Append(set)).await.unwrap();
+        assert_eq!(durable.await.unwrap(), 6);
+        drop(tx);
+        writer.await.unwrap();
+
+        let (_tx, rx) = mpsc::channel(1);
+        let mut wal = WAL::new(rx, dir.clone(), 1024, Durability::Os)
+            .await
+            .unwrap();
+        let recovery = wal.recover(OnCorruption::Fail, 5).await.unwrap();
+        assert_eq!(recovery.ops, vec![(6, Op::new_set(0, "b", "1"))]);
+        assert_eq!(recovery.last_lsn, Some(6));
+    }
+}
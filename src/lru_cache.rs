@@ -1,7 +1,8 @@
+use bytes::Bytes;
 use hashlink::{linked_hash_map::RawEntryMut, LinkedHashMap};
 
 pub struct LruCacheLayer {
-    cache: LinkedHashMap<String, String>,
+    cache: LinkedHashMap<Bytes, Bytes>,
 }
 
 impl LruCacheLayer {
@@ -11,20 +12,24 @@ impl LruCacheLayer {
         Self { cache }
     }
 
-    fn put(&mut self, key: &str, value: &str) -> () {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> () {
         let _cached_val = match self.cache.raw_entry_mut().from_key(key) {
             RawEntryMut::Occupied(mut occupied) => {
                 occupied.to_back();
                 occupied.into_mut()
             }
-            RawEntryMut::Vacant(vacant) => vacant.insert(key.to_owned(), value.to_owned()).1,
+            RawEntryMut::Vacant(vacant) => {
+                vacant
+                    .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value))
+                    .1
+            }
         };
         if self.cache.capacity() == self.cache.len() {
             self.cache.pop_front();
         }
     }
 
-    fn get(&mut self, key: &str) -> Option<String> {
+    fn get(&mut self, key: &[u8]) -> Option<Bytes> {
         if let RawEntryMut::Occupied(mut entry) = self.cache.raw_entry_mut().from_key(key) {
             entry.to_back();
             return Some(entry.get().clone());
@@ -32,7 +37,7 @@ impl LruCacheLayer {
         None
     }
 
-    fn del(&mut self, key: &str) -> () {
+    fn del(&mut self, key: &[u8]) -> () {
         if let RawEntryMut::Occupied(entry) = self.cache.raw_entry_mut().from_key(key) {
             entry.remove_entry();
         };
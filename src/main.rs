@@ -1,22 +1,171 @@
 mod bytecode_serializer;
+mod clock;
 mod errors;
 mod filesystem;
 mod in_memory;
+mod index;
 mod kvstore;
 mod log;
 mod lru_cache;
+mod manifest;
 mod operation;
 mod parser;
 mod persistent;
+mod query;
+mod reply;
+mod restore;
+mod search;
+mod session;
+mod snapshot;
 mod tcp_adapter;
+mod types;
 mod wal_io;
 use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Parser, ValueEnum};
+use filesystem::FileSystem;
+use log::OnCorruption;
+use restore::RestorePoint;
+use snapshot::CheckpointPolicy;
+use wal_io::Durability;
+
+#[derive(Parser)]
+struct Config {
+    #[arg(long, default_value = "data")]
+    data_dir: PathBuf,
+
+    #[arg(long, default_value = "127.0.0.1:6380")]
+    address: String,
+
+    #[arg(long, default_value_t = 100)]
+    cache_size: u32,
+
+    /// When WAL writes are forced to disk. A crash of the process alone loses
+    /// nothing in any mode, they differ on power failure.
+    #[arg(long, value_enum, default_value_t = DurabilityMode::Always)]
+    durability: DurabilityMode,
+
+    /// How often `--durability interval` syncs the WAL.
+    #[arg(long, default_value_t = 100)]
+    fsync_interval_ms: u64,
+
+    /// Replay around corrupt records in the middle of the WAL instead of refusing
+    /// to start. The damaged records are lost.
+    #[arg(long)]
+    skip_corrupt_records: bool,
+
+    /// Seconds between checkpoints, which snapshot the data and delete the WAL
+    /// segments it covers. 0 turns timed checkpoints off.
+    #[arg(long, default_value_t = 300)]
+    checkpoint_interval_secs: u64,
+
+    /// Take a checkpoint after this many writes. 0 turns it off.
+    #[arg(long, default_value_t = 100_000)]
+    checkpoint_writes: u64,
+
+    /// Rebuild the data as it was after the WAL record with this LSN.
+    #[arg(long, group = "restore_point")]
+    restore_to_lsn: Option<u64>,
+
+    /// Rebuild the data as it was at this time, RFC 3339 or milliseconds since
+    /// the epoch.
+    #[arg(long, group = "restore_point", value_parser = parse_time)]
+    restore_to_time: Option<i64>,
+
+    /// Write the rebuilt data to this new data directory and exit. Without it
+    /// the rebuilt data is served read-only and the data directory is left as is.
+    #[arg(long, requires = "restore_point")]
+    restore_into: Option<PathBuf>,
+}
+
+fn parse_time(value: &str) -> Result<i64, String> {
+    value.parse().or_else(|_| {
+        chrono::DateTime::parse_from_rfc3339(value)
+            .map(|time| time.timestamp_millis())
+            .map_err(|e| e.to_string())
+    })
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DurabilityMode {
+    /// fsync every batch, loses nothing
+    Always,
+    /// fsync every --fsync-interval-ms, loses up to one interval of writes
+    Interval,
+    /// leave it to the OS, loses what was not written back yet (~30s on Linux)
+    Os,
+}
+
+impl Config {
+    fn durability(&self) -> Durability {
+        match self.durability {
+            DurabilityMode::Always => Durability::Always,
+            DurabilityMode::Interval => {
+                Durability::Interval(Duration::from_millis(self.fsync_interval_ms.max(1)))
+            }
+            DurabilityMode::Os => Durability::Os,
+        }
+    }
+
+    fn checkpoints(&self) -> CheckpointPolicy {
+        CheckpointPolicy {
+            interval: (self.checkpoint_interval_secs > 0)
+                .then(|| Duration::from_secs(self.checkpoint_interval_secs)),
+            after_writes: (self.checkpoint_writes > 0).then_some(self.checkpoint_writes),
+        }
+    }
+
+    fn restore_point(&self) -> Option<RestorePoint> {
+        self.restore_to_lsn
+            .map(RestorePoint::Lsn)
+            .or(self.restore_to_time.map(RestorePoint::Time))
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    let root = PathBuf::from("data");
-    let cache_size = 100u32;
-    let mut kvstore = kvstore::KvStore::new(root, cache_size).await.unwrap();
-    kvstore.regenerate().await.unwrap();
-    kvstore.run().await;
+    let config = Config::parse();
+    let on_corruption = if config.skip_corrupt_records {
+        OnCorruption::Skip
+    } else {
+        OnCorruption::Fail
+    };
+    let mut data_dir = config.data_dir.clone();
+    let mut checkpoints = config.checkpoints();
+    let restore_point = config.restore_point();
+    if let Some(point) = restore_point {
+        let state = restore::restore(&config.data_dir, point, on_corruption)
+            .await
+            .unwrap();
+        eprintln!(
+            "Restored {} keys as of LSN {}",
+            state.entries.len(),
+            state.lsn
+        );
+        if let Some(dir) = &config.restore_into {
+            restore::write_data_dir(dir, &state).await.unwrap();
+            eprintln!("Wrote the restored data to {}", dir.display());
+            return;
+        }
+        // Served from a scratch directory so the data directory stays as it was.
+        let file_system = FileSystem::new(config.data_dir.clone()).await.unwrap();
+        data_dir = file_system.get_temp_ref().await.join("restore");
+        let _ = tokio::fs::remove_dir_all(&data_dir).await;
+        restore::write_data_dir(&data_dir, &state).await.unwrap();
+        checkpoints = CheckpointPolicy::default();
+    }
+    let mut kvstore = kvstore::KvStore::new(
+        data_dir,
+        config.cache_size,
+        config.durability(),
+        checkpoints,
+    )
+    .await
+    .unwrap();
+    if restore_point.is_some() {
+        kvstore.set_read_only();
+    }
+    kvstore.regenerate(on_corruption).await.unwrap();
+    kvstore.run(&config.address).await;
 }
@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File};
+
+use crate::bytecode_serializer::BytecodeSerializer;
+use crate::errors::WALError;
+
+pub const MANIFEST_FILE: &str = "MANIFEST";
+
+/// A WAL segment as listed in the manifest. Log sequence numbers count records
+/// from the start of the log, so they keep going across segments.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentInfo {
+    pub name: String,
+    // Seconds since the epoch, also the suffix of the file name.
+    pub created: i64,
+    pub first_lsn: u64,
+    // `None` while the segment holds no records.
+    pub last_lsn: Option<u64>,
+    pub records: u64,
+    // CRC32 of the whole file.
+    pub checksum: u32,
+}
+
+impl SegmentInfo {
+    pub fn new(created: i64, first_lsn: u64) -> Self {
+        Self {
+            name: format!("wal_{}", created),
+            created,
+            first_lsn,
+            last_lsn: None,
+            records: 0,
+            checksum: 0,
+        }
+    }
+
+    pub fn next_lsn(&self) -> u64 {
+        self.last_lsn.map_or(self.first_lsn, |lsn| lsn + 1)
+    }
+
+    /// Accounts for the record with `lsn` written at the end of the segment.
+    pub fn append(&mut self, lsn: u64, record: &[u8]) {
+        self.last_lsn = Some(lsn);
+        self.records += 1;
+        self.checksum = BytecodeSerializer::update_crc32(self.checksum, record);
+    }
+}
+
+/// Segments of the WAL, oldest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub segments: Vec<SegmentInfo>,
+}
+
+impl Manifest {
+    /// `None` when the directory has no manifest yet.
+    pub async fn load(dir: &Path) -> Result<Option<Self>, WALError> {
+        let bytes = match fs::read(dir.join(MANIFEST_FILE)).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| WALError::Manifest(e.to_string()))
+    }
+
+    /// Replaces the manifest through a synced temporary file and a rename, so a
+    /// crash leaves either the old or the new one.
+    pub async fn store(&self, dir: &Path) -> Result<(), WALError> {
+        let bytes =
+            serde_json::to_vec_pretty(self).map_err(|e| WALError::Manifest(e.to_string()))?;
+        let temp = dir.join(format!("{}.tmp", MANIFEST_FILE));
+        fs::write(&temp, bytes).await?;
+        File::open(&temp).await?.sync_all().await?;
+        fs::rename(&temp, dir.join(MANIFEST_FILE)).await?;
+        File::open(dir).await?.sync_all().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_manifest_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("manifest_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir).await;
+        fs::create_dir_all(&dir).await.unwrap();
+        assert_eq!(Manifest::load(&dir).await.unwrap(), None);
+
+        let mut sealed = SegmentInfo::new(100, 1);
+        sealed.append(1, b"one");
+        sealed.append(2, b"two");
+        assert_eq!(sealed.next_lsn(), 3);
+        assert_eq!(
+            sealed.checksum,
+            BytecodeSerializer::update_crc32(0, b"onetwo")
+        );
+        let manifest = Manifest {
+            segments: vec![sealed.clone(), SegmentInfo::new(200, sealed.next_lsn())],
+        };
+        manifest.store(&dir).await.unwrap();
+        assert_eq!(Manifest::load(&dir).await.unwrap(), Some(manifest));
+    }
+}
@@ -1,58 +1,886 @@
+use std::ops::Bound;
+use std::str::FromStr;
+
+use bytes::Bytes;
+
 use crate::bytecode_serializer::BytecodeSerializer;
+use crate::index::Extractor;
+use crate::query::{Aggregate, Comparison, Condition, Field, Filter, KeyRange, Predicate};
+use crate::types::bitmap::BitOperation;
+use crate::types::json::JsonPath;
+use crate::types::stream::StreamId;
+use crate::types::timeseries::Aggregation;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Op {
     SET {
         timestamp: i64,
-        key: String,
-        value: String,
+        key: Bytes,
+        value: Bytes,
     },
     GET {
         timestamp: i64,
-        key: String,
+        key: Bytes,
     },
     DEL {
         timestamp: i64,
-        key: String,
+        key: Bytes,
+    },
+    LPUSH {
+        timestamp: i64,
+        key: Bytes,
+        values: Vec<Bytes>,
+    },
+    RPUSH {
+        timestamp: i64,
+        key: Bytes,
+        values: Vec<Bytes>,
+    },
+    LPOP {
+        timestamp: i64,
+        key: Bytes,
+    },
+    RPOP {
+        timestamp: i64,
+        key: Bytes,
+    },
+    LRANGE {
+        timestamp: i64,
+        key: Bytes,
+        start: i64,
+        stop: i64,
+    },
+    LLEN {
+        timestamp: i64,
+        key: Bytes,
+    },
+    // Timeout is in seconds, zero blocks forever. Waiting is up to the session layer,
+    // the memory layer only ever pops from the first non-empty list.
+    BLPOP {
+        timestamp: i64,
+        keys: Vec<Bytes>,
+        timeout: f64,
+    },
+    BRPOP {
+        timestamp: i64,
+        keys: Vec<Bytes>,
+        timeout: f64,
+    },
+    HSET {
+        timestamp: i64,
+        key: Bytes,
+        fields: Vec<(Bytes, Bytes)>,
+    },
+    HGET {
+        timestamp: i64,
+        key: Bytes,
+        field: Bytes,
+    },
+    HDEL {
+        timestamp: i64,
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    HGETALL {
+        timestamp: i64,
+        key: Bytes,
+    },
+    HINCRBY {
+        timestamp: i64,
+        key: Bytes,
+        field: Bytes,
+        increment: i64,
+    },
+    HKEYS {
+        timestamp: i64,
+        key: Bytes,
+    },
+    ZADD {
+        timestamp: i64,
+        key: Bytes,
+        members: Vec<(f64, Bytes)>,
+    },
+    ZREM {
+        timestamp: i64,
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    ZSCORE {
+        timestamp: i64,
+        key: Bytes,
+        member: Bytes,
+    },
+    ZRANGE {
+        timestamp: i64,
+        key: Bytes,
+        start: i64,
+        stop: i64,
+    },
+    ZRANGEBYSCORE {
+        timestamp: i64,
+        key: Bytes,
+        min: f64,
+        max: f64,
+    },
+    ZRANK {
+        timestamp: i64,
+        key: Bytes,
+        member: Bytes,
+    },
+    SADD {
+        timestamp: i64,
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    SREM {
+        timestamp: i64,
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    SISMEMBER {
+        timestamp: i64,
+        key: Bytes,
+        member: Bytes,
+    },
+    SMEMBERS {
+        timestamp: i64,
+        key: Bytes,
+    },
+    SCARD {
+        timestamp: i64,
+        key: Bytes,
+    },
+    SETOP {
+        timestamp: i64,
+        operation: SetOperation,
+        keys: Vec<Bytes>,
+    },
+    SETOPSTORE {
+        timestamp: i64,
+        operation: SetOperation,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+    },
+    SETBIT {
+        timestamp: i64,
+        key: Bytes,
+        offset: u64,
+        bit: bool,
+    },
+    GETBIT {
+        timestamp: i64,
+        key: Bytes,
+        offset: u64,
+    },
+    BITCOUNT {
+        timestamp: i64,
+        key: Bytes,
+        range: Option<(i64, i64)>,
+    },
+    BITPOS {
+        timestamp: i64,
+        key: Bytes,
+        bit: bool,
+        start: Option<i64>,
+        end: Option<i64>,
+    },
+    BITOP {
+        timestamp: i64,
+        operation: BitOperation,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+    },
+    PFADD {
+        timestamp: i64,
+        key: Bytes,
+        elements: Vec<Bytes>,
+    },
+    PFCOUNT {
+        timestamp: i64,
+        keys: Vec<Bytes>,
+    },
+    PFMERGE {
+        timestamp: i64,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+    },
+    XADD {
+        timestamp: i64,
+        key: Bytes,
+        // `None` for `*`, an ID at the time the op was stamped.
+        id: Option<StreamId>,
+        fields: Vec<(Bytes, Bytes)>,
+    },
+    XRANGE {
+        timestamp: i64,
+        key: Bytes,
+        start: StreamId,
+        end: StreamId,
+    },
+    XREAD {
+        timestamp: i64,
+        key: Bytes,
+        after: StreamId,
+        count: Option<u64>,
+    },
+    XGROUPCREATE {
+        timestamp: i64,
+        key: Bytes,
+        group: Bytes,
+        start: Option<StreamId>,
+    },
+    XREADGROUP {
+        timestamp: i64,
+        key: Bytes,
+        group: Bytes,
+        consumer: Bytes,
+        after: Option<StreamId>,
+        count: Option<u64>,
+    },
+    XACK {
+        timestamp: i64,
+        key: Bytes,
+        group: Bytes,
+        ids: Vec<StreamId>,
+    },
+    XPENDING {
+        timestamp: i64,
+        key: Bytes,
+        group: Bytes,
+    },
+    // Sets the retention in milliseconds of a series, creating it if needed. Zero keeps everything.
+    TSCREATE {
+        timestamp: i64,
+        key: Bytes,
+        retention: u64,
+    },
+    TSADD {
+        timestamp: i64,
+        key: Bytes,
+        // `None` for `*`, the time the op was stamped.
+        sample_time: Option<i64>,
+        value: f64,
+    },
+    TSRANGE {
+        timestamp: i64,
+        key: Bytes,
+        from: i64,
+        to: i64,
+    },
+    TSAGGREGATE {
+        timestamp: i64,
+        key: Bytes,
+        from: i64,
+        to: i64,
+        aggregation: Aggregation,
+        bucket: u64,
+    },
+    // Only the path and the new value are logged, never the whole document.
+    JSONSET {
+        timestamp: i64,
+        key: Bytes,
+        path: JsonPath,
+        value: serde_json::Value,
+    },
+    JSONGET {
+        timestamp: i64,
+        key: Bytes,
+        path: JsonPath,
+    },
+    JSONARRAPPEND {
+        timestamp: i64,
+        key: Bytes,
+        path: JsonPath,
+        values: Vec<serde_json::Value>,
+    },
+    INDEXCREATE {
+        timestamp: i64,
+        name: Bytes,
+        prefix: Bytes,
+        extractor: Extractor,
+    },
+    INDEXDROP {
+        timestamp: i64,
+        name: Bytes,
+    },
+    INDEXFIND {
+        timestamp: i64,
+        name: Bytes,
+        value: Bytes,
+    },
+    SEARCHCREATE {
+        timestamp: i64,
+        prefix: Bytes,
+    },
+    SEARCHDROP {
+        timestamp: i64,
+        prefix: Bytes,
+    },
+    SEARCH {
+        timestamp: i64,
+        prefix: Bytes,
+        query: Bytes,
+    },
+    SCAN {
+        timestamp: i64,
+        range: KeyRange,
+        filter: Option<Filter>,
     },
+    AGGREGATE {
+        timestamp: i64,
+        function: Aggregate,
+        range: KeyRange,
+        filter: Option<Filter>,
+    },
+    STATS {
+        timestamp: i64,
+    },
+    CHECKPOINT {
+        timestamp: i64,
+    },
+}
+
+/// Set algebra shared by SUNION/SINTER/SDIFF and their STORE variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SetOperation {
+    Union,
+    Intersection,
+    Difference,
 }
 
+// Every variant carries a timestamp. Both accessors are generated from the one
+// list below, so a variant missing from it fails to compile.
+macro_rules! timestamped {
+    ($($variant:ident),* $(,)?) => {
+        impl Op {
+            /// Hybrid logical clock reading of when the operation was accepted, see
+            /// `clock::HybridClock`. Point-in-time restores stop on these.
+            pub fn timestamp(&self) -> i64 {
+                match self {
+                    $(Op::$variant { timestamp, .. })|* => *timestamp,
+                }
+            }
+
+            /// Sets the timestamp, ops leave the parser unstamped.
+            pub fn stamp(&mut self, now: i64) {
+                match self {
+                    $(Op::$variant { timestamp, .. })|* => *timestamp = now,
+                }
+            }
+        }
+    };
+}
+
+timestamped!(
+    SET,
+    GET,
+    DEL,
+    LPUSH,
+    RPUSH,
+    LPOP,
+    RPOP,
+    LRANGE,
+    LLEN,
+    BLPOP,
+    BRPOP,
+    HSET,
+    HGET,
+    HDEL,
+    HGETALL,
+    HINCRBY,
+    HKEYS,
+    ZADD,
+    ZREM,
+    ZSCORE,
+    ZRANGE,
+    ZRANGEBYSCORE,
+    ZRANK,
+    SADD,
+    SREM,
+    SISMEMBER,
+    SMEMBERS,
+    SCARD,
+    SETOP,
+    SETOPSTORE,
+    SETBIT,
+    GETBIT,
+    BITCOUNT,
+    BITPOS,
+    BITOP,
+    PFADD,
+    PFCOUNT,
+    PFMERGE,
+    XADD,
+    XRANGE,
+    XREAD,
+    XGROUPCREATE,
+    XREADGROUP,
+    XACK,
+    XPENDING,
+    TSCREATE,
+    TSADD,
+    TSRANGE,
+    TSAGGREGATE,
+    JSONSET,
+    JSONGET,
+    JSONARRAPPEND,
+    INDEXCREATE,
+    INDEXDROP,
+    INDEXFIND,
+    SEARCHCREATE,
+    SEARCHDROP,
+    SEARCH,
+    SCAN,
+    AGGREGATE,
+    STATS,
+    CHECKPOINT,
+);
+
 impl Op {
-    pub fn new_set(timestamp: i64, key: String, value: String) -> Self {
+    pub fn new_set<K: Into<Bytes>, V: Into<Bytes>>(timestamp: i64, key: K, value: V) -> Self {
         Op::SET {
             timestamp,
-            key,
-            value,
+            key: key.into(),
+            value: value.into(),
         }
     }
-    pub fn new_get(timestamp: i64, key: String) -> Self {
-        Op::GET { timestamp, key }
+    pub fn new_get<K: Into<Bytes>>(timestamp: i64, key: K) -> Self {
+        Op::GET {
+            timestamp,
+            key: key.into(),
+        }
     }
-    pub fn new_del(timestamp: i64, key: String) -> Self {
-        Op::DEL { timestamp, key }
+    pub fn new_del<K: Into<Bytes>>(timestamp: i64, key: K) -> Self {
+        Op::DEL {
+            timestamp,
+            key: key.into(),
+        }
     }
 
     pub fn into_bytes(&self) -> Vec<u8> {
         BytecodeSerializer::op_to_bytes(&self)
     }
+
+    /// Timeout in seconds of a blocking pop, `None` for ops that never block.
+    pub fn block_timeout(&self) -> Option<f64> {
+        match self {
+            Op::BLPOP { timeout, .. } | Op::BRPOP { timeout, .. } => Some(*timeout),
+            _ => None,
+        }
+    }
+
+    /// Whether the operation changes state and therefore has to be written to the WAL.
+    pub fn is_write(&self) -> bool {
+        match self {
+            Op::GET { .. }
+            | Op::LRANGE { .. }
+            | Op::LLEN { .. }
+            | Op::HGET { .. }
+            | Op::HGETALL { .. }
+            | Op::HKEYS { .. }
+            | Op::ZSCORE { .. }
+            | Op::ZRANGE { .. }
+            | Op::ZRANGEBYSCORE { .. }
+            | Op::ZRANK { .. }
+            | Op::SISMEMBER { .. }
+            | Op::SMEMBERS { .. }
+            | Op::SCARD { .. }
+            | Op::SETOP { .. }
+            | Op::GETBIT { .. }
+            | Op::BITCOUNT { .. }
+            | Op::BITPOS { .. }
+            | Op::PFCOUNT { .. }
+            | Op::XRANGE { .. }
+            | Op::XREAD { .. }
+            | Op::XPENDING { .. }
+            | Op::TSRANGE { .. }
+            | Op::TSAGGREGATE { .. }
+            | Op::JSONGET { .. }
+            | Op::INDEXFIND { .. }
+            | Op::SEARCH { .. }
+            | Op::SCAN { .. }
+            | Op::AGGREGATE { .. }
+            | Op::STATS { .. }
+            | Op::CHECKPOINT { .. } => false,
+            Op::SET { .. }
+            | Op::DEL { .. }
+            | Op::LPUSH { .. }
+            | Op::RPUSH { .. }
+            | Op::LPOP { .. }
+            | Op::RPOP { .. }
+            | Op::BLPOP { .. }
+            | Op::BRPOP { .. }
+            | Op::HSET { .. }
+            | Op::HDEL { .. }
+            | Op::HINCRBY { .. }
+            | Op::ZADD { .. }
+            | Op::ZREM { .. }
+            | Op::SADD { .. }
+            | Op::SREM { .. }
+            | Op::SETOPSTORE { .. }
+            | Op::SETBIT { .. }
+            | Op::BITOP { .. }
+            | Op::PFADD { .. }
+            | Op::PFMERGE { .. }
+            | Op::XADD { .. }
+            | Op::XGROUPCREATE { .. }
+            | Op::XREADGROUP { .. }
+            | Op::XACK { .. }
+            | Op::TSCREATE { .. }
+            | Op::TSADD { .. }
+            | Op::JSONSET { .. }
+            | Op::JSONARRAPPEND { .. }
+            | Op::INDEXCREATE { .. }
+            | Op::INDEXDROP { .. }
+            | Op::SEARCHCREATE { .. }
+            | Op::SEARCHDROP { .. } => true,
+        }
+    }
+
+    /// Keys whose value may be changed by the operation.
+    pub fn written_keys(&self) -> Vec<Bytes> {
+        match self {
+            Op::SET { key, .. }
+            | Op::DEL { key, .. }
+            | Op::LPUSH { key, .. }
+            | Op::RPUSH { key, .. }
+            | Op::LPOP { key, .. }
+            | Op::RPOP { key, .. }
+            | Op::HSET { key, .. }
+            | Op::HDEL { key, .. }
+            | Op::HINCRBY { key, .. }
+            | Op::ZADD { key, .. }
+            | Op::ZREM { key, .. }
+            | Op::SADD { key, .. }
+            | Op::SREM { key, .. }
+            | Op::SETBIT { key, .. }
+            | Op::PFADD { key, .. }
+            | Op::XADD { key, .. }
+            | Op::XGROUPCREATE { key, .. }
+            | Op::XREADGROUP { key, .. }
+            | Op::XACK { key, .. }
+            | Op::TSCREATE { key, .. }
+            | Op::TSADD { key, .. }
+            | Op::JSONSET { key, .. }
+            | Op::JSONARRAPPEND { key, .. } => vec![key.clone()],
+            Op::SETOPSTORE { destination, .. }
+            | Op::BITOP { destination, .. }
+            | Op::PFMERGE { destination, .. } => vec![destination.clone()],
+            Op::BLPOP { keys, .. } | Op::BRPOP { keys, .. } => keys.clone(),
+            _ => vec![],
+        }
+    }
+}
+
+/// Lossy UTF-8 view of raw key/value bytes, used when echoing data back
+/// through the human readable grammar.
+pub fn as_text(bytes: &[u8]) -> std::borrow::Cow<'_, str> {
+    String::from_utf8_lossy(bytes)
+}
+
+fn parse_arg<T: FromStr>(arg: &Bytes) -> Option<T> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
+
+// Same limit as Redis, bitmaps can address up to 512MB.
+const MAX_BIT_OFFSET: u64 = u32::MAX as u64;
+
+fn parse_bit(arg: &Bytes) -> Option<bool> {
+    match &arg[..] {
+        b"0" => Some(false),
+        b"1" => Some(true),
+        _ => None,
+    }
+}
+
+fn parse_bit_offset(arg: &Bytes) -> Option<u64> {
+    parse_arg::<u64>(arg).filter(|offset| *offset <= MAX_BIT_OFFSET)
+}
+
+fn parse_bit_operation(arg: &Bytes) -> Option<BitOperation> {
+    match &arg[..] {
+        b"AND" => Some(BitOperation::And),
+        b"OR" => Some(BitOperation::Or),
+        b"XOR" => Some(BitOperation::Xor),
+        b"NOT" => Some(BitOperation::Not),
+        _ => None,
+    }
+}
+
+fn parse_aggregation(arg: &Bytes) -> Option<Aggregation> {
+    match &arg[..] {
+        b"AVG" => Some(Aggregation::Avg),
+        b"MIN" => Some(Aggregation::Min),
+        b"MAX" => Some(Aggregation::Max),
+        b"SUM" => Some(Aggregation::Sum),
+        _ => None,
+    }
+}
+
+// `-` and `+` stand for the oldest and newest sample.
+fn parse_time_bound(arg: &Bytes) -> Option<i64> {
+    match &arg[..] {
+        b"-" => Some(i64::MIN),
+        b"+" => Some(i64::MAX),
+        _ => parse_arg(arg),
+    }
+}
+
+fn parse_sample_time(arg: &Bytes) -> Option<Option<i64>> {
+    match &arg[..] {
+        b"*" => Some(None),
+        _ => Some(Some(parse_arg(arg)?)),
+    }
+}
+
+fn parse_json(arg: &[u8]) -> Option<serde_json::Value> {
+    serde_json::from_slice(arg).ok()
+}
+
+// `VALUE` indexes the raw value, a `$.path` indexes a field of JSON values.
+fn parse_extractor(arg: &Bytes) -> Option<Extractor> {
+    match &arg[..] {
+        b"VALUE" => Some(Extractor::Value),
+        _ => Some(Extractor::JsonField(parse_arg(arg)?)),
+    }
+}
+
+// `field op operand` conditions joined by AND and OR, e.g. `KEY GLOB user:* AND VALUE > 10`.
+fn parse_filter(words: &[Bytes]) -> Option<Filter> {
+    let mut any_of = vec![vec![]];
+    let mut words = words.iter();
+    loop {
+        let field = match &words.next()?[..] {
+            b"KEY" => Field::Key,
+            b"VALUE" => Field::Value,
+            _ => return None,
+        };
+        let operator = words.next()?;
+        let operand = words.next()?.clone();
+        let compare = |comparison| Some(Predicate::Compare(comparison, parse_score(&operand)?));
+        let predicate = match &operator[..] {
+            b"=" => Predicate::Equals(operand.clone()),
+            b"!=" => Predicate::NotEquals(operand.clone()),
+            b"PREFIX" => Predicate::Prefix(operand.clone()),
+            b"CONTAINS" => Predicate::Contains(operand.clone()),
+            b"GLOB" => Predicate::Glob(operand.clone()),
+            b"<" => compare(Comparison::Less)?,
+            b"<=" => compare(Comparison::LessOrEqual)?,
+            b">" => compare(Comparison::Greater)?,
+            b">=" => compare(Comparison::GreaterOrEqual)?,
+            _ => return None,
+        };
+        any_of.last_mut()?.push(Condition { field, predicate });
+        match words.next().map(|word| &word[..]) {
+            None => return Some(Filter { any_of }),
+            Some(b"AND") => {}
+            Some(b"OR") => any_of.push(vec![]),
+            Some(_) => return None,
+        }
+    }
+}
+
+fn parse_stream_id(arg: &Bytes) -> Option<StreamId> {
+    parse_arg(arg)
+}
+
+fn parse_stream_bound(arg: &Bytes, is_end: bool) -> Option<StreamId> {
+    StreamId::parse_bound(std::str::from_utf8(arg).ok()?, is_end)
+}
+
+fn parse_xadd_id(arg: &Bytes) -> Option<Option<StreamId>> {
+    match &arg[..] {
+        b"*" => Some(None),
+        _ => Some(Some(parse_stream_id(arg)?)),
+    }
+}
+
+// NaN would break score ordering, infinities are fine and used for open ranges.
+fn parse_score(arg: &Bytes) -> Option<f64> {
+    parse_arg::<f64>(arg).filter(|score| !score.is_nan())
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum OpType {
     SET,
     GET,
     DEL,
+    LPUSH,
+    RPUSH,
+    LPOP,
+    RPOP,
+    LRANGE,
+    LLEN,
+    BLPOP,
+    BRPOP,
+    HSET,
+    HGET,
+    HDEL,
+    HGETALL,
+    HINCRBY,
+    HKEYS,
+    ZADD,
+    ZREM,
+    ZSCORE,
+    ZRANGE,
+    ZRANGEBYSCORE,
+    ZRANK,
+    SADD,
+    SREM,
+    SISMEMBER,
+    SMEMBERS,
+    SCARD,
+    SUNION,
+    SINTER,
+    SDIFF,
+    SUNIONSTORE,
+    SINTERSTORE,
+    SDIFFSTORE,
+    SETBIT,
+    GETBIT,
+    BITCOUNT,
+    BITPOS,
+    BITOP,
+    PFADD,
+    PFCOUNT,
+    PFMERGE,
+    XADD,
+    XRANGE,
+    XREAD,
+    XGROUP,
+    XREADGROUP,
+    XACK,
+    XPENDING,
+    TSCREATE,
+    TSADD,
+    TSRANGE,
+    TSAGGREGATE,
+    JSONSET,
+    JSONGET,
+    JSONARRAPPEND,
+    INDEXCREATE,
+    INDEXDROP,
+    INDEXFIND,
+    SEARCHCREATE,
+    SEARCHDROP,
+    SEARCH,
+    SCAN,
+    COUNT,
+    SUM,
+    MIN,
+    MAX,
+    AVG,
+    STATS,
+    CHECKPOINT,
+}
+
+// Commands that take a key followed by positional arguments.
+const KEYWORDS: &[(&str, OpType)] = &[
+    ("LPUSH", OpType::LPUSH),
+    ("RPUSH", OpType::RPUSH),
+    ("LPOP", OpType::LPOP),
+    ("RPOP", OpType::RPOP),
+    ("LRANGE", OpType::LRANGE),
+    ("LLEN", OpType::LLEN),
+    ("BLPOP", OpType::BLPOP),
+    ("BRPOP", OpType::BRPOP),
+    ("HSET", OpType::HSET),
+    ("HGET", OpType::HGET),
+    ("HDEL", OpType::HDEL),
+    ("HGETALL", OpType::HGETALL),
+    ("HINCRBY", OpType::HINCRBY),
+    ("HKEYS", OpType::HKEYS),
+    ("ZADD", OpType::ZADD),
+    ("ZREM", OpType::ZREM),
+    ("ZSCORE", OpType::ZSCORE),
+    ("ZRANGE", OpType::ZRANGE),
+    ("ZRANGEBYSCORE", OpType::ZRANGEBYSCORE),
+    ("ZRANK", OpType::ZRANK),
+    ("SADD", OpType::SADD),
+    ("SREM", OpType::SREM),
+    ("SISMEMBER", OpType::SISMEMBER),
+    ("SMEMBERS", OpType::SMEMBERS),
+    ("SCARD", OpType::SCARD),
+    ("SUNION", OpType::SUNION),
+    ("SINTER", OpType::SINTER),
+    ("SDIFF", OpType::SDIFF),
+    ("SUNIONSTORE", OpType::SUNIONSTORE),
+    ("SINTERSTORE", OpType::SINTERSTORE),
+    ("SDIFFSTORE", OpType::SDIFFSTORE),
+    ("SETBIT", OpType::SETBIT),
+    ("GETBIT", OpType::GETBIT),
+    ("BITCOUNT", OpType::BITCOUNT),
+    ("BITPOS", OpType::BITPOS),
+    ("BITOP", OpType::BITOP),
+    ("PFADD", OpType::PFADD),
+    ("PFCOUNT", OpType::PFCOUNT),
+    ("PFMERGE", OpType::PFMERGE),
+    ("XADD", OpType::XADD),
+    ("XRANGE", OpType::XRANGE),
+    ("XREAD", OpType::XREAD),
+    ("XGROUP", OpType::XGROUP),
+    ("XREADGROUP", OpType::XREADGROUP),
+    ("XACK", OpType::XACK),
+    ("XPENDING", OpType::XPENDING),
+    ("TS.CREATE", OpType::TSCREATE),
+    ("TS.ADD", OpType::TSADD),
+    ("TS.RANGE", OpType::TSRANGE),
+    ("TS.AGGREGATE", OpType::TSAGGREGATE),
+    ("JSON.SET", OpType::JSONSET),
+    ("JSON.GET", OpType::JSONGET),
+    ("JSON.ARRAPPEND", OpType::JSONARRAPPEND),
+    ("INDEX.CREATE", OpType::INDEXCREATE),
+    ("INDEX.DROP", OpType::INDEXDROP),
+    ("INDEX.FIND", OpType::INDEXFIND),
+    ("SEARCH.CREATE", OpType::SEARCHCREATE),
+    ("SEARCH.DROP", OpType::SEARCHDROP),
+    ("SEARCH", OpType::SEARCH),
+    ("SCAN", OpType::SCAN),
+    ("COUNT", OpType::COUNT),
+    ("SUM", OpType::SUM),
+    ("MIN", OpType::MIN),
+    ("MAX", OpType::MAX),
+    ("AVG", OpType::AVG),
+    ("STATS", OpType::STATS),
+    ("CHECKPOINT", OpType::CHECKPOINT),
+];
+
+impl OpType {
+    pub fn from_keyword(word: &str) -> Option<Self> {
+        KEYWORDS
+            .iter()
+            .find(|(keyword, _)| *keyword == word)
+            .map(|(_, op_type)| op_type.clone())
+    }
+
+    /// Whether the keyword is followed by a key, the few that are not end right there.
+    pub fn takes_key(&self) -> bool {
+        !matches!(self, OpType::STATS | OpType::CHECKPOINT)
+    }
+
+    pub fn keyword(&self) -> &'static str {
+        KEYWORDS
+            .iter()
+            .find(|(_, op_type)| op_type == self)
+            .map_or("", |(keyword, _)| keyword)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct OpBuilder {
     timestamp: i64,
     op_type: Option<OpType>,
-    key: Option<String>,
-    value: Option<String>,
+    key: Option<Bytes>,
+    value: Option<Bytes>,
+    args: Vec<Bytes>,
+    // Words of a WHERE clause, if the command has one.
+    filter: Option<Vec<Bytes>>,
 }
 
 impl OpBuilder {
+    // The executor stamps ops when it accepts them, until then they carry zero.
     pub fn new() -> Self {
         let timestamp = 0i64;
         Self {
@@ -60,6 +888,8 @@ impl OpBuilder {
             op_type: None,
             key: None,
             value: None,
+            args: Vec::new(),
+            filter: None,
         }
     }
 
@@ -68,30 +898,665 @@ impl OpBuilder {
         self
     }
 
-    pub fn set_key<T: Into<String>>(&mut self, key: T) -> &mut Self {
+    pub fn set_key<T: Into<Bytes>>(&mut self, key: T) -> &mut Self {
         self.key = Some(key.into());
         self
     }
 
-    pub fn set_value<T: Into<String>>(&mut self, value: T) -> &mut Self {
+    pub fn set_value<T: Into<Bytes>>(&mut self, value: T) -> &mut Self {
         self.value = Some(value.into());
         self
     }
 
+    pub fn push_arg<T: Into<Bytes>>(&mut self, arg: T) -> &mut Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    // A quoted word, already unquoted by the lexer. Inside a JSON document it
+    // stands for a JSON string, so it is quoted again as one.
+    pub fn push_quoted_arg(&mut self, text: String) -> &mut Self {
+        let in_document = matches!(self.op_type, Some(OpType::JSONSET | OpType::JSONARRAPPEND))
+            && !self.args.is_empty();
+        if in_document {
+            self.args
+                .push(Bytes::from(serde_json::Value::String(text).to_string()));
+        } else {
+            self.args.push(Bytes::from(text));
+        }
+        self
+    }
+
+    pub fn accepts_filter(&self) -> bool {
+        matches!(
+            self.op_type,
+            Some(
+                OpType::SCAN
+                    | OpType::COUNT
+                    | OpType::SUM
+                    | OpType::MIN
+                    | OpType::MAX
+                    | OpType::AVG
+            )
+        )
+    }
+
+    pub fn push_filter_word<T: Into<Bytes>>(&mut self, word: T) -> &mut Self {
+        self.filter.get_or_insert_with(Vec::new).push(word.into());
+        self
+    }
+
+    fn key(&self) -> Option<Bytes> {
+        self.key.clone()
+    }
+
+    fn filter(&self) -> Option<Option<Filter>> {
+        match &self.filter {
+            Some(words) => Some(Some(parse_filter(words)?)),
+            None => Some(None),
+        }
+    }
+
+    fn aggregate(&self, function: Aggregate) -> Option<Op> {
+        Some(Op::AGGREGATE {
+            timestamp: self.timestamp,
+            function,
+            range: self.key_range()?,
+            filter: self.filter()?,
+        })
+    }
+
+    // `PREFIX p` or `start end`, with `-` and `+` for open ends.
+    fn key_range(&self) -> Option<KeyRange> {
+        let key = self.key()?;
+        let [arg] = self.args_exact()?;
+        if key == "PREFIX" {
+            return Some(KeyRange::Prefix(arg.clone()));
+        }
+        let start = match &key[..] {
+            b"-" => Bound::Unbounded,
+            _ => Bound::Included(key),
+        };
+        let end = match &arg[..] {
+            b"+" => Bound::Unbounded,
+            _ => Bound::Included(arg.clone()),
+        };
+        Some(KeyRange::Between(start, end))
+    }
+
+    fn args_exact<const N: usize>(&self) -> Option<&[Bytes; N]> {
+        self.args.as_slice().try_into().ok()
+    }
+
+    fn args_at_least(&self, n: usize) -> Option<Vec<Bytes>> {
+        if self.args.len() < n {
+            return None;
+        }
+        Some(self.args.clone())
+    }
+
+    // Blocking pops take `key [key ...] timeout`.
+    fn blocking_args(&self) -> Option<(Vec<Bytes>, f64)> {
+        let (timeout, rest) = self.args.split_last()?;
+        let timeout = parse_arg::<f64>(timeout).filter(|t| t.is_finite() && *t >= 0.0)?;
+        let mut keys = vec![self.key()?];
+        keys.extend(rest.iter().cloned());
+        Some((keys, timeout))
+    }
+
+    fn args_pairs(&self) -> Option<Vec<(Bytes, Bytes)>> {
+        if self.args.is_empty() || !self.args.len().is_multiple_of(2) {
+            return None;
+        }
+        Some(
+            self.args
+                .chunks_exact(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect(),
+        )
+    }
+
+    fn set_operation(&self) -> Option<SetOperation> {
+        match self.op_type {
+            Some(OpType::SUNION) | Some(OpType::SUNIONSTORE) => Some(SetOperation::Union),
+            Some(OpType::SINTER) | Some(OpType::SINTERSTORE) => Some(SetOperation::Intersection),
+            Some(OpType::SDIFF) | Some(OpType::SDIFFSTORE) => Some(SetOperation::Difference),
+            _ => None,
+        }
+    }
+
     pub fn build(&self) -> Option<Op> {
+        let timestamp = self.timestamp;
         match self.op_type {
             Some(OpType::SET) => match (&self.key, &self.value) {
-                (Some(k), Some(v)) => Some(Op::new_set(self.timestamp, k.clone(), v.clone())),
+                (Some(k), Some(v)) => Some(Op::new_set(timestamp, k.clone(), v.clone())),
                 _ => None,
             },
             Some(OpType::GET) => match &self.key {
-                Some(k) => Some(Op::new_get(self.timestamp, k.clone())),
+                Some(k) => Some(Op::new_get(timestamp, k.clone())),
                 _ => None,
             },
             Some(OpType::DEL) => match &self.key {
-                Some(k) => Some(Op::new_del(self.timestamp, k.clone())),
+                Some(k) => Some(Op::new_del(timestamp, k.clone())),
                 _ => None,
             },
+            Some(OpType::LPUSH) => Some(Op::LPUSH {
+                timestamp,
+                key: self.key()?,
+                values: self.args_at_least(1)?,
+            }),
+            Some(OpType::RPUSH) => Some(Op::RPUSH {
+                timestamp,
+                key: self.key()?,
+                values: self.args_at_least(1)?,
+            }),
+            Some(OpType::LPOP) => {
+                let [] = self.args_exact()?;
+                Some(Op::LPOP {
+                    timestamp,
+                    key: self.key()?,
+                })
+            }
+            Some(OpType::RPOP) => {
+                let [] = self.args_exact()?;
+                Some(Op::RPOP {
+                    timestamp,
+                    key: self.key()?,
+                })
+            }
+            Some(OpType::LRANGE) => {
+                let [start, stop] = self.args_exact()?;
+                Some(Op::LRANGE {
+                    timestamp,
+                    key: self.key()?,
+                    start: parse_arg(start)?,
+                    stop: parse_arg(stop)?,
+                })
+            }
+            Some(OpType::LLEN) => {
+                let [] = self.args_exact()?;
+                Some(Op::LLEN {
+                    timestamp,
+                    key: self.key()?,
+                })
+            }
+            Some(OpType::BLPOP) => {
+                let (keys, timeout) = self.blocking_args()?;
+                Some(Op::BLPOP {
+                    timestamp,
+                    keys,
+                    timeout,
+                })
+            }
+            Some(OpType::BRPOP) => {
+                let (keys, timeout) = self.blocking_args()?;
+                Some(Op::BRPOP {
+                    timestamp,
+                    keys,
+                    timeout,
+                })
+            }
+            Some(OpType::HSET) => Some(Op::HSET {
+                timestamp,
+                key: self.key()?,
+                fields: self.args_pairs()?,
+            }),
+            Some(OpType::HGET) => {
+                let [field] = self.args_exact()?;
+                Some(Op::HGET {
+                    timestamp,
+                    key: self.key()?,
+                    field: field.clone(),
+                })
+            }
+            Some(OpType::HDEL) => Some(Op::HDEL {
+                timestamp,
+                key: self.key()?,
+                fields: self.args_at_least(1)?,
+            }),
+            Some(OpType::HGETALL) => {
+                let [] = self.args_exact()?;
+                Some(Op::HGETALL {
+                    timestamp,
+                    key: self.key()?,
+                })
+            }
+            Some(OpType::HINCRBY) => {
+                let [field, increment] = self.args_exact()?;
+                Some(Op::HINCRBY {
+                    timestamp,
+                    key: self.key()?,
+                    field: field.clone(),
+                    increment: parse_arg(increment)?,
+                })
+            }
+            Some(OpType::HKEYS) => {
+                let [] = self.args_exact()?;
+                Some(Op::HKEYS {
+                    timestamp,
+                    key: self.key()?,
+                })
+            }
+            Some(OpType::ZADD) => {
+                let members = self
+                    .args_pairs()?
+                    .into_iter()
+                    .map(|(score, member)| Some((parse_score(&score)?, member)))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(Op::ZADD {
+                    timestamp,
+                    key: self.key()?,
+                    members,
+                })
+            }
+            Some(OpType::ZREM) => Some(Op::ZREM {
+                timestamp,
+                key: self.key()?,
+                members: self.args_at_least(1)?,
+            }),
+            Some(OpType::ZSCORE) => {
+                let [member] = self.args_exact()?;
+                Some(Op::ZSCORE {
+                    timestamp,
+                    key: self.key()?,
+                    member: member.clone(),
+                })
+            }
+            Some(OpType::ZRANGE) => {
+                let [start, stop] = self.args_exact()?;
+                Some(Op::ZRANGE {
+                    timestamp,
+                    key: self.key()?,
+                    start: parse_arg(start)?,
+                    stop: parse_arg(stop)?,
+                })
+            }
+            Some(OpType::ZRANGEBYSCORE) => {
+                let [min, max] = self.args_exact()?;
+                Some(Op::ZRANGEBYSCORE {
+                    timestamp,
+                    key: self.key()?,
+                    min: parse_score(min)?,
+                    max: parse_score(max)?,
+                })
+            }
+            Some(OpType::ZRANK) => {
+                let [member] = self.args_exact()?;
+                Some(Op::ZRANK {
+                    timestamp,
+                    key: self.key()?,
+                    member: member.clone(),
+                })
+            }
+            Some(OpType::SADD) => Some(Op::SADD {
+                timestamp,
+                key: self.key()?,
+                members: self.args_at_least(1)?,
+            }),
+            Some(OpType::SREM) => Some(Op::SREM {
+                timestamp,
+                key: self.key()?,
+                members: self.args_at_least(1)?,
+            }),
+            Some(OpType::SISMEMBER) => {
+                let [member] = self.args_exact()?;
+                Some(Op::SISMEMBER {
+                    timestamp,
+                    key: self.key()?,
+                    member: member.clone(),
+                })
+            }
+            Some(OpType::SMEMBERS) => {
+                let [] = self.args_exact()?;
+                Some(Op::SMEMBERS {
+                    timestamp,
+                    key: self.key()?,
+                })
+            }
+            Some(OpType::SCARD) => {
+                let [] = self.args_exact()?;
+                Some(Op::SCARD {
+                    timestamp,
+                    key: self.key()?,
+                })
+            }
+            Some(OpType::SUNION) | Some(OpType::SINTER) | Some(OpType::SDIFF) => {
+                let mut keys = vec![self.key()?];
+                keys.extend(self.args.iter().cloned());
+                Some(Op::SETOP {
+                    timestamp,
+                    operation: self.set_operation()?,
+                    keys,
+                })
+            }
+            Some(OpType::SUNIONSTORE) | Some(OpType::SINTERSTORE) | Some(OpType::SDIFFSTORE) => {
+                Some(Op::SETOPSTORE {
+                    timestamp,
+                    operation: self.set_operation()?,
+                    destination: self.key()?,
+                    keys: self.args_at_least(1)?,
+                })
+            }
+            Some(OpType::SETBIT) => {
+                let [offset, bit] = self.args_exact()?;
+                Some(Op::SETBIT {
+                    timestamp,
+                    key: self.key()?,
+                    offset: parse_bit_offset(offset)?,
+                    bit: parse_bit(bit)?,
+                })
+            }
+            Some(OpType::GETBIT) => {
+                let [offset] = self.args_exact()?;
+                Some(Op::GETBIT {
+                    timestamp,
+                    key: self.key()?,
+                    offset: parse_bit_offset(offset)?,
+                })
+            }
+            Some(OpType::BITCOUNT) => {
+                let range = match self.args.as_slice() {
+                    [] => None,
+                    [start, end] => Some((parse_arg(start)?, parse_arg(end)?)),
+                    _ => return None,
+                };
+                Some(Op::BITCOUNT {
+                    timestamp,
+                    key: self.key()?,
+                    range,
+                })
+            }
+            Some(OpType::BITPOS) => {
+                let (bit, start, end) = match self.args.as_slice() {
+                    [bit] => (parse_bit(bit)?, None, None),
+                    [bit, start] => (parse_bit(bit)?, Some(parse_arg(start)?), None),
+                    [bit, start, end] => (
+                        parse_bit(bit)?,
+                        Some(parse_arg(start)?),
+                        Some(parse_arg(end)?),
+                    ),
+                    _ => return None,
+                };
+                Some(Op::BITPOS {
+                    timestamp,
+                    key: self.key()?,
+                    bit,
+                    start,
+                    end,
+                })
+            }
+            // BITOP has the operation in the key slot: BITOP AND dest key [key ...]
+            Some(OpType::BITOP) => {
+                let operation = parse_bit_operation(&self.key()?)?;
+                let (destination, keys) = self.args.split_first()?;
+                if keys.is_empty() || (operation == BitOperation::Not && keys.len() != 1) {
+                    return None;
+                }
+                Some(Op::BITOP {
+                    timestamp,
+                    operation,
+                    destination: destination.clone(),
+                    keys: keys.to_vec(),
+                })
+            }
+            Some(OpType::PFADD) => Some(Op::PFADD {
+                timestamp,
+                key: self.key()?,
+                elements: self.args.clone(),
+            }),
+            Some(OpType::PFCOUNT) => {
+                let mut keys = vec![self.key()?];
+                keys.extend(self.args.iter().cloned());
+                Some(Op::PFCOUNT { timestamp, keys })
+            }
+            Some(OpType::PFMERGE) => Some(Op::PFMERGE {
+                timestamp,
+                destination: self.key()?,
+                keys: self.args_at_least(1)?,
+            }),
+            Some(OpType::XADD) => {
+                let (id, fields) = self.args.split_first()?;
+                if fields.is_empty() || !fields.len().is_multiple_of(2) {
+                    return None;
+                }
+                Some(Op::XADD {
+                    timestamp,
+                    key: self.key()?,
+                    id: parse_xadd_id(id)?,
+                    fields: fields
+                        .chunks_exact(2)
+                        .map(|pair| (pair[0].clone(), pair[1].clone()))
+                        .collect(),
+                })
+            }
+            Some(OpType::XRANGE) => {
+                let [start, end] = self.args_exact()?;
+                Some(Op::XRANGE {
+                    timestamp,
+                    key: self.key()?,
+                    start: parse_stream_bound(start, false)?,
+                    end: parse_stream_bound(end, true)?,
+                })
+            }
+            Some(OpType::XREAD) => {
+                let (after, count) = match self.args.as_slice() {
+                    [after] => (after, None),
+                    [after, count] => (after, Some(parse_arg(count)?)),
+                    _ => return None,
+                };
+                Some(Op::XREAD {
+                    timestamp,
+                    key: self.key()?,
+                    after: parse_stream_id(after)?,
+                    count,
+                })
+            }
+            // XGROUP has the subcommand in the key slot: XGROUP CREATE key group id|$
+            Some(OpType::XGROUP) => {
+                if self.key()? != "CREATE" {
+                    return None;
+                }
+                let [key, group, start] = self.args_exact()?;
+                let start = match &start[..] {
+                    b"$" => None,
+                    _ => Some(parse_stream_id(start)?),
+                };
+                Some(Op::XGROUPCREATE {
+                    timestamp,
+                    key: key.clone(),
+                    group: group.clone(),
+                    start,
+                })
+            }
+            Some(OpType::XREADGROUP) => {
+                let (group, consumer, after, count) = match self.args.as_slice() {
+                    [group, consumer, after] => (group, consumer, after, None),
+                    [group, consumer, after, count] => {
+                        (group, consumer, after, Some(parse_arg(count)?))
+                    }
+                    _ => return None,
+                };
+                let after = match &after[..] {
+                    b">" => None,
+                    _ => Some(parse_stream_id(after)?),
+                };
+                Some(Op::XREADGROUP {
+                    timestamp,
+                    key: self.key()?,
+                    group: group.clone(),
+                    consumer: consumer.clone(),
+                    after,
+                    count,
+                })
+            }
+            Some(OpType::XACK) => {
+                let (group, ids) = self.args.split_first()?;
+                if ids.is_empty() {
+                    return None;
+                }
+                Some(Op::XACK {
+                    timestamp,
+                    key: self.key()?,
+                    group: group.clone(),
+                    ids: ids
+                        .iter()
+                        .map(parse_stream_id)
+                        .collect::<Option<Vec<_>>>()?,
+                })
+            }
+            Some(OpType::XPENDING) => {
+                let [group] = self.args_exact()?;
+                Some(Op::XPENDING {
+                    timestamp,
+                    key: self.key()?,
+                    group: group.clone(),
+                })
+            }
+            Some(OpType::TSCREATE) => {
+                let [retention] = self.args_exact()?;
+                Some(Op::TSCREATE {
+                    timestamp,
+                    key: self.key()?,
+                    retention: parse_arg(retention)?,
+                })
+            }
+            Some(OpType::TSADD) => {
+                let [sample_time, value] = self.args_exact()?;
+                Some(Op::TSADD {
+                    timestamp,
+                    key: self.key()?,
+                    sample_time: parse_sample_time(sample_time)?,
+                    value: parse_score(value)?,
+                })
+            }
+            Some(OpType::TSRANGE) => {
+                let [from, to] = self.args_exact()?;
+                Some(Op::TSRANGE {
+                    timestamp,
+                    key: self.key()?,
+                    from: parse_time_bound(from)?,
+                    to: parse_time_bound(to)?,
+                })
+            }
+            Some(OpType::TSAGGREGATE) => {
+                let [from, to, aggregation, bucket] = self.args_exact()?;
+                Some(Op::TSAGGREGATE {
+                    timestamp,
+                    key: self.key()?,
+                    from: parse_time_bound(from)?,
+                    to: parse_time_bound(to)?,
+                    aggregation: parse_aggregation(aggregation)?,
+                    bucket: parse_arg::<u64>(bucket).filter(|bucket| *bucket > 0)?,
+                })
+            }
+            Some(OpType::JSONSET) => {
+                let (path, value) = self.args.split_first()?;
+                // The lexer splits on whitespace, glue the document back together.
+                let value = value
+                    .iter()
+                    .map(|part| &part[..])
+                    .collect::<Vec<_>>()
+                    .join(&b' ');
+                Some(Op::JSONSET {
+                    timestamp,
+                    key: self.key()?,
+                    path: parse_arg(path)?,
+                    value: parse_json(&value)?,
+                })
+            }
+            Some(OpType::JSONGET) => {
+                let path = match self.args.as_slice() {
+                    [] => JsonPath::root(),
+                    [path] => parse_arg(path)?,
+                    _ => return None,
+                };
+                Some(Op::JSONGET {
+                    timestamp,
+                    key: self.key()?,
+                    path,
+                })
+            }
+            Some(OpType::JSONARRAPPEND) => {
+                let (path, values) = self.args.split_first()?;
+                if values.is_empty() {
+                    return None;
+                }
+                Some(Op::JSONARRAPPEND {
+                    timestamp,
+                    key: self.key()?,
+                    path: parse_arg(path)?,
+                    values: values
+                        .iter()
+                        .map(|value| parse_json(value))
+                        .collect::<Option<Vec<_>>>()?,
+                })
+            }
+            Some(OpType::INDEXCREATE) => {
+                let [prefix, extractor] = self.args_exact()?;
+                Some(Op::INDEXCREATE {
+                    timestamp,
+                    name: self.key()?,
+                    prefix: prefix.clone(),
+                    extractor: parse_extractor(extractor)?,
+                })
+            }
+            Some(OpType::INDEXDROP) => {
+                let [] = self.args_exact()?;
+                Some(Op::INDEXDROP {
+                    timestamp,
+                    name: self.key()?,
+                })
+            }
+            Some(OpType::INDEXFIND) => {
+                let [value] = self.args_exact()?;
+                Some(Op::INDEXFIND {
+                    timestamp,
+                    name: self.key()?,
+                    value: value.clone(),
+                })
+            }
+            Some(OpType::SEARCHCREATE) => {
+                let [] = self.args_exact()?;
+                Some(Op::SEARCHCREATE {
+                    timestamp,
+                    prefix: self.key()?,
+                })
+            }
+            Some(OpType::SEARCHDROP) => {
+                let [] = self.args_exact()?;
+                Some(Op::SEARCHDROP {
+                    timestamp,
+                    prefix: self.key()?,
+                })
+            }
+            Some(OpType::SEARCH) => {
+                if self.args.is_empty() {
+                    return None;
+                }
+                let query = self
+                    .args
+                    .iter()
+                    .map(|term| &term[..])
+                    .collect::<Vec<_>>()
+                    .join(&b' ');
+                Some(Op::SEARCH {
+                    timestamp,
+                    prefix: self.key()?,
+                    query: Bytes::from(query),
+                })
+            }
+            Some(OpType::SCAN) => Some(Op::SCAN {
+                timestamp,
+                range: self.key_range()?,
+                filter: self.filter()?,
+            }),
+            Some(OpType::COUNT) => self.aggregate(Aggregate::Count),
+            Some(OpType::SUM) => self.aggregate(Aggregate::Sum),
+            Some(OpType::MIN) => self.aggregate(Aggregate::Min),
+            Some(OpType::MAX) => self.aggregate(Aggregate::Max),
+            Some(OpType::AVG) => self.aggregate(Aggregate::Avg),
+            Some(OpType::STATS) => Some(Op::STATS { timestamp }),
+            Some(OpType::CHECKPOINT) => Some(Op::CHECKPOINT { timestamp }),
             _ => None,
         }
     }
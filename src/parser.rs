@@ -7,16 +7,36 @@ use core::str;
 use tokio::io::AsyncBufReadExt;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Token {
     SET,
     GET,
     DEL,
     AND,
     TO,
+    COMMAND(OpType),
     LITERAL(String),
+    // A double quoted word, always data. Holds the text between the quotes.
+    QUOTED(String),
     EOF,
 }
 
+impl Token {
+    /// The word a token was read from, for positions where only data is expected.
+    fn as_literal(&self) -> Option<String> {
+        match self {
+            Token::SET => Some("SET".to_string()),
+            Token::GET => Some("GET".to_string()),
+            Token::DEL => Some("DEL".to_string()),
+            Token::AND => Some("AND".to_string()),
+            Token::TO => Some("TO".to_string()),
+            Token::COMMAND(op_type) => Some(op_type.keyword().to_string()),
+            Token::LITERAL(word) | Token::QUOTED(word) => Some(word.clone()),
+            Token::EOF => None,
+        }
+    }
+}
+
 struct Lexer;
 
 impl Lexer {
@@ -32,17 +52,79 @@ impl Lexer {
         let mut tokens: Vec<Token> = vec![];
 
         // Read data until newline
-        buffer.read_until(b'\n', &mut bytes).await?;
+        if buffer.read_until(b'\n', &mut bytes).await? == 0 {
+            return Err(ParserError::EndOfInput);
+        }
         let input = str::from_utf8(&bytes)?;
 
-        for word in input.split_whitespace() {
-            let token = self.eval_word(word);
+        for word in Self::split_words(input) {
+            // Quoted text is always data, even if it spells a keyword.
+            let token = match Self::unquote(word) {
+                Some(text) => Token::QUOTED(text),
+                None if word.starts_with('"') => Token::LITERAL(word.to_string()),
+                None => self.eval_word(word),
+            };
             tokens.push(token);
         }
 
         Ok(tokens)
     }
 
+    // Splits on whitespace, except inside a double quoted run that starts a word.
+    // A word that is more than one quoted run, such as the `"b"}` ending a JSON
+    // document, keeps its quotes.
+    fn split_words(input: &str) -> Vec<&str> {
+        let mut words = vec![];
+        let mut chars = input.char_indices().peekable();
+        while let Some(&(start, first)) = chars.peek() {
+            if first.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            let mut quoted = first == '"';
+            let mut escaped = false;
+            chars.next();
+            let mut end = input.len();
+            while let Some(&(index, c)) = chars.peek() {
+                if quoted {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => quoted = false,
+                        _ => {}
+                    }
+                } else if c.is_whitespace() {
+                    end = index;
+                    break;
+                }
+                chars.next();
+            }
+            words.push(&input[start..end]);
+        }
+        words
+    }
+
+    // The text of a word that is a single double quoted run, with `\"` and `\\`
+    // unescaped. Other backslashes are kept as they are.
+    fn unquote(word: &str) -> Option<String> {
+        let mut chars = word.strip_prefix('"')?.chars();
+        let mut text = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next()? {
+                    escaped @ ('"' | '\\') => text.push(escaped),
+                    other => {
+                        text.push('\\');
+                        text.push(other);
+                    }
+                },
+                '"' => return chars.as_str().is_empty().then_some(text),
+                c => text.push(c),
+            }
+        }
+        None
+    }
+
     fn eval_word(&self, word: &str) -> Token {
         // Use get_or_insert_with to avoid unnecessary clones
         match word {
@@ -51,7 +133,10 @@ impl Lexer {
             "DEL" => Token::DEL,
             "AND" => Token::AND,
             "TO" => Token::TO,
-            _ => Token::LITERAL(word.to_string()),
+            _ => match OpType::from_keyword(word) {
+                Some(op_type) => Token::COMMAND(op_type),
+                None => Token::LITERAL(word.to_string()),
+            },
         }
     }
 }
@@ -65,11 +150,15 @@ pub enum ParserStates {
     To,
     Key,
     Value,
+    Command,
+    Args,
+    Where,
 }
 
 pub struct StateMachine {
     state: ParserStates,
     op_builder: OpBuilder,
+    ready: Option<Op>,
 }
 
 impl StateMachine {
@@ -77,9 +166,23 @@ impl StateMachine {
         Self {
             state: ParserStates::Start,
             op_builder: OpBuilder::new(),
+            ready: None,
         }
     }
 
+    // Operations are only emitted once the whole command is read, on AND or
+    // end of input, since commands with variadic arguments have no other end marker.
+    fn finish_operation(&mut self) -> Result<(), MemoryLayerErrors> {
+        let op = self
+            .op_builder
+            .build()
+            .ok_or_else(|| MemoryLayerErrors::GenericError("Invalid arguments".to_string()))?;
+        self.ready = Some(op);
+        self.op_builder = OpBuilder::new();
+        self.state = ParserStates::Start;
+        Ok(())
+    }
+
     pub fn process(&mut self, token: &Token) -> Result<(), MemoryLayerErrors> {
         match &self.state {
             ParserStates::Start => match token {
@@ -98,6 +201,16 @@ impl StateMachine {
                     self.state = ParserStates::Del;
                     Ok(())
                 }
+                Token::COMMAND(op_type) => {
+                    self.op_builder.set_op_type(op_type.clone());
+                    self.state = if op_type.takes_key() {
+                        ParserStates::Command
+                    } else {
+                        ParserStates::Args
+                    };
+                    Ok(())
+                }
+                Token::EOF => Ok(()),
                 _ => Err(MemoryLayerErrors::GenericError(
                     "Invalid operation".to_string(),
                 )),
@@ -108,55 +221,76 @@ impl StateMachine {
                     self.state = ParserStates::To;
                     Ok(())
                 }
-                Token::AND => {
-                    self.op_builder = OpBuilder::new();
-                    self.state = ParserStates::Start;
-                    Ok(())
-                }
-                Token::EOF => {
-                    self.state = ParserStates::Start;
-                    Ok(())
-                }
+                Token::AND | Token::EOF => self.finish_operation(),
                 _ => Err(MemoryLayerErrors::GenericError(
                     "Invalid operation".to_string(),
                 )),
             },
-            ParserStates::To => match token {
-                Token::LITERAL(value) => {
-                    self.op_builder.set_value(value.clone());
+            // Keywords are plain values here, as in SET k TO COUNT.
+            ParserStates::To => match token.as_literal() {
+                Some(value) => {
+                    self.op_builder.set_value(value);
                     self.state = ParserStates::Value;
                     Ok(())
                 }
-                _ => Err(MemoryLayerErrors::GenericError("Invalid value".to_string())),
+                None => Err(MemoryLayerErrors::GenericError("Invalid value".to_string())),
             },
             ParserStates::Value => match token {
-                Token::AND => {
-                    self.op_builder = OpBuilder::new();
-                    self.state = ParserStates::Start;
+                Token::AND | Token::EOF => self.finish_operation(),
+                _ => Err(MemoryLayerErrors::GenericError(
+                    "Invalid operation".to_string(),
+                )),
+            },
+            ParserStates::Args => match token {
+                Token::LITERAL(word) if word == "WHERE" && self.op_builder.accepts_filter() => {
+                    self.state = ParserStates::Where;
                     Ok(())
                 }
-                Token::EOF => {
-                    self.state = ParserStates::Start;
+                Token::AND | Token::EOF => self.finish_operation(),
+                Token::QUOTED(text) => {
+                    self.op_builder.push_quoted_arg(text.clone());
                     Ok(())
                 }
-                _ => Err(MemoryLayerErrors::GenericError(
-                    "Invalid operation".to_string(),
-                )),
+                // Keywords are plain arguments here, as in TS.AGGREGATE cpu - + SUM 60.
+                _ => {
+                    let arg = token.as_literal().ok_or_else(|| {
+                        MemoryLayerErrors::GenericError("Invalid argument".to_string())
+                    })?;
+                    self.op_builder.push_arg(arg);
+                    Ok(())
+                }
+            },
+            // AND joins conditions inside a WHERE clause, so the clause runs to the end of the line.
+            ParserStates::Where => match token.as_literal() {
+                Some(word) => {
+                    self.op_builder.push_filter_word(word);
+                    Ok(())
+                }
+                None => self.finish_operation(),
+            },
+            // A command can not end before its key, so AND or a keyword here is a
+            // literal argument, as in BITOP AND dest key.
+            ParserStates::Command => match token.as_literal() {
+                Some(key) => {
+                    self.op_builder.set_key(key);
+                    self.state = ParserStates::Args;
+                    Ok(())
+                }
+                None => Err(MemoryLayerErrors::GenericError("Invalid key".to_string())),
             },
-            ParserStates::Set | ParserStates::Get | ParserStates::Del => {
-                if let Token::LITERAL(ref key) = token {
-                    self.op_builder.set_key(key.clone());
+            ParserStates::Set | ParserStates::Get | ParserStates::Del => match token.as_literal() {
+                Some(key) => {
+                    self.op_builder.set_key(key);
                     self.state = ParserStates::Key;
                     Ok(())
-                } else {
-                    return Err(MemoryLayerErrors::GenericError("Invalid key".to_string()));
                 }
-            }
+                None => Err(MemoryLayerErrors::GenericError("Invalid key".to_string())),
+            },
         }
     }
 
     pub fn get_operation(&mut self) -> Option<Op> {
-        self.op_builder.build()
+        self.ready.take()
     }
 }
 
@@ -179,7 +313,8 @@ impl Parser {
         self.token_stream = lexer.tokenize(buffer).await?;
         let mut operations: Vec<Op> = vec![];
         let mut state_machine = StateMachine::new();
-        let mut token_iter = self.token_stream.iter().peekable();
+        let eof = Token::EOF;
+        let mut token_iter = self.token_stream.iter().chain(std::iter::once(&eof));
         while let Some(token) = token_iter.next() {
             state_machine
                 .process(token)
@@ -198,6 +333,11 @@ impl Parser {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::query::{Aggregate, Comparison, Condition, Field, Filter, KeyRange, Predicate};
+    use crate::types::bitmap::BitOperation;
+    use crate::types::timeseries::Aggregation;
+    use bytes::Bytes;
+    use std::ops::Bound;
     use tokio::io::BufReader;
 
     #[tokio::test]
@@ -232,6 +372,136 @@ mod tests {
         assert_eq!(tokens, expected);
     }
 
+    #[tokio::test]
+    async fn test_lexer_quoted_literals() {
+        let lexer = Lexer::new();
+        let buffer = r#"SEARCH doc: "quick  brown fox" AND JSON.SET cfg $ {"name": "a \" b"} AND GET "AND" "a \\ \" b""#;
+        let tokens = lexer.tokenize(buffer.as_bytes()).await.unwrap();
+        let expected = vec![
+            Token::COMMAND(OpType::SEARCH),
+            Token::LITERAL("doc:".to_string()),
+            Token::QUOTED("quick  brown fox".to_string()),
+            Token::AND,
+            Token::COMMAND(OpType::JSONSET),
+            Token::LITERAL("cfg".to_string()),
+            Token::LITERAL("$".to_string()),
+            Token::LITERAL(r#"{"name":"#.to_string()),
+            Token::LITERAL(r#""a \" b"}"#.to_string()),
+            Token::AND,
+            Token::GET,
+            Token::QUOTED("AND".to_string()),
+            Token::QUOTED(r#"a \ " b"#.to_string()),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[tokio::test]
+    async fn test_parser_quoted_values() {
+        let mut parser = Parser::new();
+        let buffer = r#"SET "my key" TO "a b" AND GET "my key" AND JSON.ARRAPPEND doc $.tags "x y" 2 AND JSON.SET doc $ { "name": "bob" }"#;
+        let operations = parser.parse(buffer.as_bytes()).await.unwrap();
+        let expected = vec![
+            Op::new_set(0, "my key", "a b"),
+            Op::new_get(0, "my key"),
+            Op::JSONARRAPPEND {
+                timestamp: 0,
+                key: Bytes::from("doc"),
+                path: "$.tags".parse().unwrap(),
+                values: vec![serde_json::json!("x y"), serde_json::json!(2)],
+            },
+            Op::JSONSET {
+                timestamp: 0,
+                key: Bytes::from("doc"),
+                path: "$".parse().unwrap(),
+                value: serde_json::json!({ "name": "bob" }),
+            },
+        ];
+        assert_eq!(operations, expected);
+    }
+
+    #[tokio::test]
+    async fn test_parser_scan_where() {
+        let mut parser = Parser::new();
+        let buffer =
+            r#"GET a AND SCAN PREFIX user: WHERE KEY GLOB user:* AND VALUE >= 10 OR VALUE = "a b""#;
+        let operations = parser.parse(buffer.as_bytes()).await.unwrap();
+        let condition = |field, predicate| Condition { field, predicate };
+        let expected = vec![
+            Op::new_get(0, "a"),
+            Op::SCAN {
+                timestamp: 0,
+                range: KeyRange::Prefix(Bytes::from("user:")),
+                filter: Some(Filter {
+                    any_of: vec![
+                        vec![
+                            condition(Field::Key, Predicate::Glob(Bytes::from("user:*"))),
+                            condition(
+                                Field::Value,
+                                Predicate::Compare(Comparison::GreaterOrEqual, 10.0),
+                            ),
+                        ],
+                        vec![condition(
+                            Field::Value,
+                            Predicate::Equals(Bytes::from("a b")),
+                        )],
+                    ],
+                }),
+            },
+        ];
+        assert_eq!(operations, expected);
+
+        let unfiltered = parser.parse("SCAN - m".as_bytes()).await.unwrap();
+        assert_eq!(
+            unfiltered,
+            vec![Op::SCAN {
+                timestamp: 0,
+                range: KeyRange::Between(Bound::Unbounded, Bound::Included(Bytes::from("m"))),
+                filter: None,
+            }]
+        );
+        assert!(parser
+            .parse("SCAN - + WHERE VALUE ~ x".as_bytes())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parser_aggregates() {
+        let mut parser = Parser::new();
+        // WHERE runs to the end of the line, so the filtered aggregate goes last.
+        let buffer =
+            "COUNT - + AND TS.AGGREGATE cpu - + SUM 60 AND SUM PREFIX price: WHERE VALUE > 0";
+        let operations = parser.parse(buffer.as_bytes()).await.unwrap();
+        let expected = vec![
+            Op::AGGREGATE {
+                timestamp: 0,
+                function: Aggregate::Count,
+                range: KeyRange::Between(Bound::Unbounded, Bound::Unbounded),
+                filter: None,
+            },
+            Op::TSAGGREGATE {
+                timestamp: 0,
+                key: Bytes::from("cpu"),
+                from: i64::MIN,
+                to: i64::MAX,
+                aggregation: Aggregation::Sum,
+                bucket: 60,
+            },
+            Op::AGGREGATE {
+                timestamp: 0,
+                function: Aggregate::Sum,
+                range: KeyRange::Prefix(Bytes::from("price:")),
+                filter: Some(Filter {
+                    any_of: vec![vec![Condition {
+                        field: Field::Value,
+                        predicate: Predicate::Compare(Comparison::Greater, 0.0),
+                    }]],
+                }),
+            },
+        ];
+        assert_eq!(operations, expected);
+    }
+
     #[tokio::test]
     async fn test_parser() {
         let mut parser = Parser::new();
@@ -249,6 +519,70 @@ mod tests {
         assert_eq!(operations, expected);
     }
 
+    #[tokio::test]
+    async fn test_parser_keyword_keys_and_values() {
+        let mut parser = Parser::new();
+        let buffer =
+            "SET MAX TO 1 AND SET k TO COUNT AND GET SEARCH AND DEL SUM AND SET GET TO AND";
+        let operations = parser.parse(buffer.as_bytes()).await.unwrap();
+        let expected = vec![
+            Op::new_set(0, "MAX", "1"),
+            Op::new_set(0, "k", "COUNT"),
+            Op::new_get(0, "SEARCH"),
+            Op::new_del(0, "SUM"),
+            Op::new_set(0, "GET", "AND"),
+        ];
+        assert_eq!(operations, expected);
+    }
+
+    #[tokio::test]
+    async fn test_parser_list_commands() {
+        let mut parser = Parser::new();
+        let buffer = r#"RPUSH jobs a b c AND LRANGE jobs 0 -1 AND LPOP jobs"#;
+        let mut reader = BufReader::new(buffer.as_bytes());
+        let operations = parser.parse(&mut reader).await.unwrap();
+        let expected = vec![
+            Op::RPUSH {
+                timestamp: 0,
+                key: "jobs".into(),
+                values: vec!["a".into(), "b".into(), "c".into()],
+            },
+            Op::LRANGE {
+                timestamp: 0,
+                key: "jobs".into(),
+                start: 0,
+                stop: -1,
+            },
+            Op::LPOP {
+                timestamp: 0,
+                key: "jobs".into(),
+            },
+        ];
+        assert_eq!(operations, expected);
+    }
+
+    #[tokio::test]
+    async fn test_parser_bitop_and() {
+        let mut parser = Parser::new();
+        let buffer = r#"BITOP AND both day1 day2 AND BITCOUNT both"#;
+        let mut reader = BufReader::new(buffer.as_bytes());
+        let operations = parser.parse(&mut reader).await.unwrap();
+        let expected = vec![
+            Op::BITOP {
+                timestamp: 0,
+                operation: BitOperation::And,
+                destination: "both".into(),
+                keys: vec!["day1".into(), "day2".into()],
+            },
+            Op::BITCOUNT {
+                timestamp: 0,
+                key: "both".into(),
+                range: None,
+            },
+        ];
+        assert_eq!(operations, expected);
+    }
+
     #[tokio::test]
     async fn test_parser_error() {
         let mut parser = Parser::new();
@@ -1,16 +1,20 @@
 use std::iter;
 
+use bytes::Bytes;
+
 use super::errors::PersistentLayerError;
 
 const PAGE_SIZE: u16 = 8192;
 const HEADER_SIZE: usize = 12;
 
-struct LinePointer<const MAX_LEN: usize>(String, u16);
+// Keys are raw bytes and may legitimately end with zeros, so the real key
+// length is stored in front of the zero padded key instead of trimming.
+struct LinePointer<const MAX_LEN: usize>(Bytes, u16);
 
 impl<const MAX_LEN: usize> LinePointer<MAX_LEN> {
-    fn new(value: String, idx: u16) -> Result<Self, PersistentLayerError> {
-        let key_byte_len = value.as_bytes().len();
-        if key_byte_len > MAX_LEN {
+    fn new(value: Bytes, idx: u16) -> Result<Self, PersistentLayerError> {
+        let key_byte_len = value.len();
+        if key_byte_len > MAX_LEN || key_byte_len > u8::MAX as usize {
             return Err(PersistentLayerError::LinePointerLenError(
                 MAX_LEN,
                 key_byte_len,
@@ -21,8 +25,9 @@ impl<const MAX_LEN: usize> LinePointer<MAX_LEN> {
     }
 
     fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = self.0.bytes().collect();
-        let diff = MAX_LEN - bytes.len();
+        let mut bytes: Vec<u8> = vec![self.0.len() as u8];
+        bytes.extend_from_slice(&self.0);
+        let diff = MAX_LEN - self.0.len();
 
         bytes.extend(iter::repeat(0).take(diff));
         bytes.extend(self.1.to_le_bytes());
@@ -30,28 +35,30 @@ impl<const MAX_LEN: usize> LinePointer<MAX_LEN> {
     }
 
     fn from_bytes(bytes: &[u8]) -> Result<Self, PersistentLayerError> {
-        if bytes.len() != MAX_LEN + 2 {
+        if bytes.len() != MAX_LEN + 3 {
             return Err(PersistentLayerError::LinePointerSerializationError);
         };
 
-        let key_bytes = &bytes[..MAX_LEN];
-        let key = String::from_utf8(key_bytes.to_vec())?;
-        let key = key.trim_end_matches(char::from(0));
+        let key_len = bytes[0] as usize;
+        if key_len > MAX_LEN {
+            return Err(PersistentLayerError::LinePointerSerializationError);
+        }
+        let key = Bytes::copy_from_slice(&bytes[1..1 + key_len]);
 
-        let idx = &bytes[MAX_LEN..];
+        let idx = &bytes[MAX_LEN + 1..];
         let idx = u16::from_le_bytes([idx[0], idx[1]]);
 
-        Ok(Self(key.to_string(), idx))
+        Ok(Self(key, idx))
     }
 }
 
 struct ValueEntry {
     len: u32,
-    value: String,
+    value: Bytes,
 }
 
 impl ValueEntry {
-    fn from_str(value: &str) -> Result<Self, PersistentLayerError> {
+    fn from_slice(value: &[u8]) -> Result<Self, PersistentLayerError> {
         let len: u32 = value
             .len()
             .try_into()
@@ -59,25 +66,32 @@ impl ValueEntry {
 
         Ok(Self {
             len,
-            value: value.to_string(),
+            value: Bytes::copy_from_slice(value),
         })
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut result = self.len.to_le_bytes().to_vec();
 
-        result.extend(self.value.bytes());
+        result.extend_from_slice(&self.value);
 
         result
     }
 
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, PersistentLayerError> {
+        if bytes.len() < 4 {
+            return Err(PersistentLayerError::ValueEntrySerializationError);
+        }
         let len = &bytes[..4];
         let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]);
-        let key = &bytes[4..4 + len as usize];
-        let key = String::from_utf8(key.to_vec())?;
+        let value = bytes
+            .get(4..4 + len as usize)
+            .ok_or(PersistentLayerError::ValueEntrySerializationError)?;
 
-        Ok(Self { len, value: key })
+        Ok(Self {
+            len,
+            value: Bytes::copy_from_slice(value),
+        })
     }
 }
 
@@ -88,11 +102,11 @@ struct Block {
 
 impl Block {
     fn construct(
-        init_data: Vec<(String, String)>,
-    ) -> Result<(Self, Vec<(String, String)>), PersistentLayerError> {
+        init_data: Vec<(Bytes, Bytes)>,
+    ) -> Result<(Self, Vec<(Bytes, Bytes)>), PersistentLayerError> {
         let mut header = Header::default();
         let mut byte_block: Vec<u8> = vec![0u8; PAGE_SIZE as usize];
-        let mut not_fit_records: Vec<(String, String)> = vec![];
+        let mut not_fit_records: Vec<(Bytes, Bytes)> = vec![];
         let header_offset: u16 = HEADER_SIZE
             .try_into()
             .expect("Header size should be in bounds of u16");
@@ -101,12 +115,12 @@ impl Block {
         header.set(HeaderProps::LOWER(header_offset));
         header.set(HeaderProps::UPPER(upper_cursor));
         for (key, value) in init_data.iter() {
-            let value = ValueEntry::from_str(value)?;
+            let value = ValueEntry::from_slice(value)?;
             let value_bytes = value.to_bytes();
             let upper = header.upper();
             let lower = header.lower();
             let new_upper = upper as usize - value_bytes.len();
-            let lp = LinePointer::<32>::new(key.to_string(), new_upper as u16)?;
+            let lp = LinePointer::<32>::new(key.clone(), new_upper as u16)?;
             let lp_bytes = lp.to_bytes();
             let new_lower = lower as usize + lp_bytes.len();
             if header.is_space_to_write(lp_bytes.len() + value_bytes.len()) {
@@ -115,7 +129,7 @@ impl Block {
                 header.set(HeaderProps::LOWER(new_lower as u16));
                 header.set(HeaderProps::UPPER(new_upper as u16));
             } else {
-                not_fit_records.push((key.to_string(), value.value))
+                not_fit_records.push((key.clone(), value.value))
             }
         }
 
@@ -1,4 +1,3 @@
-use std::string::FromUtf8Error;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -12,8 +11,8 @@ pub enum PersistentLayerError {
     #[error("Byte slice is too short!")]
     LinePointerSerializationError,
 
-    #[error("UTF8 error")]
-    FailedParsingUTF8(#[from] FromUtf8Error),
+    #[error("Value entry is shorter than its length prefix")]
+    ValueEntrySerializationError,
 
     #[error("Value too long")]
     ValueTooLong,
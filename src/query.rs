@@ -0,0 +1,277 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+
+use crate::types::Value;
+
+/// The keys a scan or aggregation runs over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyRange {
+    Prefix(Bytes),
+    // Both ends inclusive, `Unbounded` for `-` and `+`.
+    Between(Bound<Bytes>, Bound<Bytes>),
+}
+
+impl KeyRange {
+    pub fn start(&self) -> Bound<Bytes> {
+        match self {
+            KeyRange::Prefix(prefix) => Bound::Included(prefix.clone()),
+            KeyRange::Between(start, _) => start.clone(),
+        }
+    }
+
+    /// Whether a key at or after the start is still inside the range. Keys are
+    /// visited in order, so the first key failing this ends the scan.
+    pub fn before_end(&self, key: &[u8]) -> bool {
+        match self {
+            KeyRange::Prefix(prefix) => key.starts_with(prefix),
+            KeyRange::Between(_, Bound::Included(end)) => key <= &end[..],
+            KeyRange::Between(_, Bound::Excluded(end)) => key < &end[..],
+            KeyRange::Between(_, Bound::Unbounded) => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Key,
+    // Only string values have one, other types never match a value condition.
+    Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    Equals(Bytes),
+    NotEquals(Bytes),
+    Prefix(Bytes),
+    Contains(Bytes),
+    Glob(Bytes),
+    // Compares the field parsed as a number, fields that are not numbers never match.
+    Compare(Comparison, f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub field: Field,
+    pub predicate: Predicate,
+}
+
+/// Conditions joined by AND and OR, AND binding tighter. Stored as a list of
+/// alternatives, each alternative being conditions that must all hold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub any_of: Vec<Vec<Condition>>,
+}
+
+impl Condition {
+    fn matches(&self, key: &[u8], value: &Value) -> bool {
+        let field: &[u8] = match (self.field, value) {
+            (Field::Key, _) => key,
+            (Field::Value, Value::String(bytes)) => bytes,
+            (Field::Value, _) => return false,
+        };
+        match &self.predicate {
+            Predicate::Equals(expected) => field == &expected[..],
+            Predicate::NotEquals(expected) => field != &expected[..],
+            Predicate::Prefix(prefix) => field.starts_with(prefix),
+            Predicate::Contains(needle) => {
+                needle.is_empty()
+                    || field
+                        .windows(needle.len())
+                        .any(|window| window == &needle[..])
+            }
+            Predicate::Glob(pattern) => glob_match(pattern, field),
+            Predicate::Compare(comparison, operand) => {
+                let number = match std::str::from_utf8(field)
+                    .ok()
+                    .and_then(|s| s.parse::<f64>().ok())
+                {
+                    Some(number) => number,
+                    None => return false,
+                };
+                match comparison {
+                    Comparison::Less => number < *operand,
+                    Comparison::LessOrEqual => number <= *operand,
+                    Comparison::Greater => number > *operand,
+                    Comparison::GreaterOrEqual => number >= *operand,
+                }
+            }
+        }
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, key: &[u8], value: &Value) -> bool {
+        self.any_of
+            .iter()
+            .any(|all_of| all_of.iter().all(|condition| condition.matches(key, value)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+/// Running state of an aggregation, fed one value at a time while scanning.
+#[derive(Debug, Default)]
+pub struct Accumulator {
+    count: u64,
+    // Number of values that parsed as numbers and make up sum, min and max.
+    numbers: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Accumulator {
+    /// Every key counts, only string values holding a number feed the other functions.
+    pub fn add(&mut self, value: &Value) {
+        self.count += 1;
+        let number = match value {
+            Value::String(bytes) => std::str::from_utf8(bytes)
+                .ok()
+                .and_then(|s| s.parse::<f64>().ok()),
+            _ => None,
+        };
+        if let Some(number) = number.filter(|n| !n.is_nan()) {
+            if self.numbers == 0 {
+                (self.min, self.max) = (number, number);
+            }
+            self.numbers += 1;
+            self.sum += number;
+            self.min = self.min.min(number);
+            self.max = self.max.max(number);
+        }
+    }
+
+    /// `None` for MIN, MAX and AVG over a range without numbers.
+    pub fn finish(&self, aggregate: Aggregate) -> Option<f64> {
+        match aggregate {
+            Aggregate::Count => Some(self.count as f64),
+            Aggregate::Sum => Some(self.sum),
+            _ if self.numbers == 0 => None,
+            Aggregate::Min => Some(self.min),
+            Aggregate::Max => Some(self.max),
+            Aggregate::Avg => Some(self.sum / self.numbers as f64),
+        }
+    }
+}
+
+/// Glob with `*` for any run of bytes, `?` for a single byte and `\` to escape.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*`: pattern position after it and text position.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            Some(b'\\') if pattern.get(p + 1) == Some(&text[t]) => {
+                p += 2;
+                t += 1;
+                continue;
+            }
+            Some(c) if *c != b'\\' && *c == text[t] => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match backtrack {
+            Some((after_star, matched)) => {
+                backtrack = Some((after_star, matched + 1));
+                p = after_star;
+                t = matched + 1;
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"user:*", b"user:42"));
+        assert!(glob_match(b"user:*:name", b"user:4:2:name"));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(br"a\*b", b"a*b"));
+        assert!(!glob_match(br"a\*b", b"axb"));
+        assert!(!glob_match(b"user:*", b"order:1"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let condition = |field, predicate| Condition { field, predicate };
+        // KEY GLOB user:* AND VALUE > 10 OR VALUE CONTAINS admin
+        let filter = Filter {
+            any_of: vec![
+                vec![
+                    condition(Field::Key, Predicate::Glob(Bytes::from("user:*"))),
+                    condition(Field::Value, Predicate::Compare(Comparison::Greater, 10.0)),
+                ],
+                vec![condition(
+                    Field::Value,
+                    Predicate::Contains(Bytes::from("admin")),
+                )],
+            ],
+        };
+        let value = |text: &str| Value::String(Bytes::from(text.to_string()));
+        assert!(filter.matches(b"user:1", &value("11")));
+        assert!(!filter.matches(b"user:1", &value("9")));
+        assert!(!filter.matches(b"order:1", &value("11")));
+        assert!(filter.matches(b"order:1", &value("superadmin")));
+        assert!(!filter.matches(b"user:1", &Value::List(Default::default())));
+    }
+
+    #[test]
+    fn test_accumulator() {
+        let mut accumulator = Accumulator::default();
+        for text in ["4", "-1.5", "not a number", "10"] {
+            accumulator.add(&Value::String(Bytes::from(text)));
+        }
+        assert_eq!(accumulator.finish(Aggregate::Count), Some(4.0));
+        assert_eq!(accumulator.finish(Aggregate::Sum), Some(12.5));
+        assert_eq!(accumulator.finish(Aggregate::Min), Some(-1.5));
+        assert_eq!(accumulator.finish(Aggregate::Max), Some(10.0));
+        assert_eq!(accumulator.finish(Aggregate::Avg), Some(12.5 / 3.0));
+        assert_eq!(Accumulator::default().finish(Aggregate::Avg), None);
+    }
+
+    #[test]
+    fn test_key_range_end() {
+        let prefix = KeyRange::Prefix(Bytes::from("a:"));
+        assert!(prefix.before_end(b"a:1"));
+        assert!(!prefix.before_end(b"b"));
+        let between = KeyRange::Between(Bound::Unbounded, Bound::Included(Bytes::from("m")));
+        assert!(between.before_end(b"m"));
+        assert!(!between.before_end(b"ma"));
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt;
+
+use bytes::Bytes;
+
+use crate::operation::as_text;
+use crate::types::stream::StreamEntry;
+use crate::types::timeseries::Sample;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Nil,
+    Bulk(Bytes),
+    Integer(i64),
+    Array(Vec<Reply>),
+    // The reply to a write together with the LSN of its WAL record.
+    Written(Box<Reply>, u64),
+}
+
+impl Reply {
+    pub fn score(score: f64) -> Self {
+        Reply::Bulk(Bytes::from(score.to_string()))
+    }
+
+    pub fn sample((timestamp, value): Sample) -> Self {
+        Reply::Array(vec![Reply::Integer(timestamp), Reply::score(value)])
+    }
+
+    pub fn stream_entry((id, fields): StreamEntry) -> Self {
+        Reply::Array(vec![
+            Reply::Bulk(Bytes::from(id.to_string())),
+            Reply::Array(
+                fields
+                    .into_iter()
+                    .flat_map(|(field, value)| [Reply::Bulk(field), Reply::Bulk(value)])
+                    .collect(),
+            ),
+        ])
+    }
+}
+
+impl From<Option<Bytes>> for Reply {
+    fn from(value: Option<Bytes>) -> Self {
+        match value {
+            Some(bytes) => Reply::Bulk(bytes),
+            None => Reply::Nil,
+        }
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::Nil => write!(f, "None"),
+            Reply::Bulk(bytes) => write!(f, "{}", as_text(bytes)),
+            Reply::Integer(number) => write!(f, "{}", number),
+            Reply::Written(reply, lsn) => write!(f, "{} (lsn {})", reply, lsn),
+            Reply::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
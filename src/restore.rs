@@ -0,0 +1,125 @@
+use std::path::Path;
+
+use tokio::fs;
+
+use crate::clock::physical_millis;
+use crate::errors::{FileSystemError, KVStoreError, WALError};
+use crate::filesystem::FileSystem;
+use crate::in_memory::InMemoryLayer;
+use crate::log::{self, OnCorruption, WALFileManager};
+use crate::snapshot::{self, Snapshot};
+
+/// Where a point-in-time restore stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestorePoint {
+    // Up to and including the record with this LSN.
+    Lsn(u64),
+    // Up to and including the records stamped in this millisecond since the epoch.
+    Time(i64),
+}
+
+impl RestorePoint {
+    fn includes(&self, lsn: u64, timestamp: i64) -> bool {
+        match *self {
+            RestorePoint::Lsn(target) => lsn <= target,
+            RestorePoint::Time(millis) => physical_millis(timestamp) <= millis,
+        }
+    }
+}
+
+/// Rebuilds the data in `root` as of `point`, from the newest snapshot taken at
+/// or before it and the WAL records after that snapshot up to it. Nothing in
+/// `root` is changed, so it can be read while a server runs on it.
+pub async fn restore(
+    root: &Path,
+    point: RestorePoint,
+    on_corruption: OnCorruption,
+) -> Result<Snapshot, KVStoreError> {
+    let file_system = FileSystem::new(root.to_path_buf())
+        .await
+        .map_err(KVStoreError::FileSystemError)?;
+    let snapshots = snapshot::list(file_system.get_snapshot_ref().await)
+        .await
+        .map_err(KVStoreError::SnapshotError)?;
+    let mut base = Snapshot {
+        lsn: 0,
+        timestamp: 0,
+        entries: vec![],
+        indexes: vec![],
+    };
+    for (lsn, path) in snapshots.iter().rev() {
+        // Snapshots past an LSN point are passed over without reading them.
+        if !point.includes(*lsn, i64::MIN) {
+            continue;
+        }
+        match Snapshot::load(path).await {
+            Ok(snapshot) if point.includes(snapshot.lsn, snapshot.timestamp) => {
+                base = snapshot;
+                break;
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Skipping snapshot at LSN {}: {}", lsn, e),
+        }
+    }
+    let covered = base.lsn;
+    let mut store = InMemoryLayer::restore(base.entries, base.indexes)
+        .map_err(KVStoreError::MemoryLayerError)?;
+
+    let recovery = log::read_log(file_system.get_wal_ref().await, covered, on_corruption)
+        .await
+        .map_err(KVStoreError::WALError)?;
+    if recovery.first_lsn > covered + 1 {
+        return Err(KVStoreError::WALError(WALError::MissingRecords {
+            from: covered + 1,
+            first: recovery.first_lsn,
+        }));
+    }
+    let (mut lsn, mut timestamp) = (covered, base.timestamp);
+    for (record, op) in recovery.ops {
+        // Timestamps only grow along the log, so the first record past the point ends it.
+        if !point.includes(record, op.timestamp()) {
+            break;
+        }
+        (lsn, timestamp) = (record, op.timestamp());
+        if let Err(e) = store.eval(op) {
+            eprintln!("Error replaying WAL record: {}", e);
+        }
+    }
+    Ok(Snapshot {
+        lsn,
+        timestamp,
+        entries: store.get_snapshot(),
+        indexes: store.index_definitions(),
+    })
+}
+
+/// Lays out a new data directory holding only `state`, with an empty WAL that
+/// carries on from its LSN. A directory that already holds data is refused.
+pub async fn write_data_dir(root: &Path, state: &Snapshot) -> Result<(), KVStoreError> {
+    let file_system = FileSystem::new(root.to_path_buf())
+        .await
+        .map_err(KVStoreError::FileSystemError)?;
+    file_system
+        .init()
+        .await
+        .map_err(KVStoreError::FileSystemError)?;
+    let wal_dir = file_system.get_wal_ref().await;
+    let snapshot_dir = file_system.get_snapshot_ref().await;
+    for dir in [wal_dir, snapshot_dir] {
+        let mut entries = fs::read_dir(dir)
+            .await
+            .map_err(|e| KVStoreError::SnapshotError(e.into()))?;
+        if let Ok(Some(_)) = entries.next_entry().await {
+            return Err(KVStoreError::FileSystemError(FileSystemError::NotEmpty(
+                root.display().to_string(),
+            )));
+        }
+    }
+    state
+        .store(snapshot_dir)
+        .await
+        .map_err(KVStoreError::SnapshotError)?;
+    WALFileManager::create(wal_dir, state.lsn + 1)
+        .await
+        .map_err(KVStoreError::WALError)
+}
@@ -0,0 +1,135 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bytes::Bytes;
+
+use crate::types::Value;
+
+/// Lowercased alphanumeric runs, everything else separates terms.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Inverted index over the string values of every key under a prefix.
+#[derive(Debug, Clone)]
+pub struct FullTextIndex {
+    prefix: Bytes,
+    // term -> key -> number of occurrences of the term in the value
+    postings: HashMap<String, BTreeMap<Bytes, u32>>,
+    // Terms of every indexed key, needed to drop its postings on change.
+    documents: HashMap<Bytes, Vec<String>>,
+}
+
+impl FullTextIndex {
+    pub fn new(prefix: Bytes) -> Self {
+        Self {
+            prefix,
+            postings: HashMap::new(),
+            documents: HashMap::new(),
+        }
+    }
+
+    pub fn covers(&self, key: &[u8]) -> bool {
+        key.starts_with(&self.prefix)
+    }
+
+    /// Re-indexes `key` against its current value, `None` when the key is gone.
+    pub fn update(&mut self, key: &Bytes, value: Option<&Value>) {
+        if !self.covers(key) {
+            return;
+        }
+        for term in self.documents.remove(key).unwrap_or_default() {
+            if let Some(keys) = self.postings.get_mut(&term) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+        let text = match value {
+            Some(Value::String(bytes)) => String::from_utf8_lossy(bytes),
+            _ => return,
+        };
+        let terms = tokenize(&text);
+        if terms.is_empty() {
+            return;
+        }
+        for term in &terms {
+            *self
+                .postings
+                .entry(term.clone())
+                .or_default()
+                .entry(key.clone())
+                .or_default() += 1;
+        }
+        self.documents.insert(key.clone(), terms);
+    }
+
+    /// Keys containing any of the query terms, best match first. Scores are
+    /// tf-idf: terms that appear in few values weigh more, repeated terms add up.
+    pub fn search(&self, query: &str) -> Vec<(Bytes, f64)> {
+        let total = self.documents.len() as f64;
+        let mut scores: BTreeMap<Bytes, f64> = BTreeMap::new();
+        let mut terms = tokenize(query);
+        terms.sort();
+        terms.dedup();
+        for term in terms {
+            let Some(keys) = self.postings.get(&term) else {
+                continue;
+            };
+            let idf = (1.0 + total / keys.len() as f64).ln();
+            for (key, count) in keys {
+                let length = self.documents[key].len() as f64;
+                *scores.entry(key.clone()).or_default() += *count as f64 / length * idf;
+            }
+        }
+        let mut ranked: Vec<(Bytes, f64)> = scores.into_iter().collect();
+        ranked.sort_by(|(a_key, a), (b_key, b)| b.total_cmp(a).then_with(|| a_key.cmp(b_key)));
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(text: &str) -> Value {
+        Value::String(Bytes::from(text.to_string()))
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("The quick-brown FOX, 2 times!"),
+            vec!["the", "quick", "brown", "fox", "2", "times"]
+        );
+    }
+
+    #[test]
+    fn test_search_ranking() {
+        let mut index = FullTextIndex::new(Bytes::from("doc:"));
+        index.update(
+            &Bytes::from("doc:1"),
+            Some(&document("red apples and red pears")),
+        );
+        index.update(&Bytes::from("doc:2"), Some(&document("green apples")));
+        index.update(&Bytes::from("doc:3"), Some(&document("a red car")));
+        index.update(&Bytes::from("other"), Some(&document("red red red")));
+
+        let keys = |index: &FullTextIndex, query: &str| -> Vec<Bytes> {
+            index
+                .search(query)
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect()
+        };
+        assert_eq!(keys(&index, "red apples"), vec!["doc:1", "doc:2", "doc:3"]);
+        assert_eq!(keys(&index, "GREEN"), vec!["doc:2"]);
+        assert!(keys(&index, "bananas").is_empty());
+
+        index.update(&Bytes::from("doc:2"), None);
+        assert!(keys(&index, "green").is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::io::{AsyncBufRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::errors::{MemoryLayerErrors, ParserError};
+use crate::operation::Op;
+use crate::parser::Parser;
+use crate::reply::Reply;
+
+pub type ReplySender = oneshot::Sender<Result<Reply, MemoryLayerErrors>>;
+
+/// A single operation sent by a session to the store, answered through `reply`.
+pub struct Request {
+    pub op: Op,
+    pub reply: ReplySender,
+}
+
+/// Reads commands line by line from one client and writes back a line per operation.
+pub async fn serve<R, W>(mut reader: R, mut writer: W, requests: mpsc::Sender<Request>)
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut parser = Parser::new();
+    loop {
+        let lines = match parser.parse(&mut reader).await {
+            Ok(ops) => {
+                let mut lines = Vec::with_capacity(ops.len());
+                for op in ops {
+                    let line = match execute(&requests, op).await {
+                        Some(Ok(reply)) => format!("Result: {}\n", reply),
+                        Some(Err(e)) => format!("Error: {}\n", e),
+                        // The store is gone, nothing left to serve.
+                        None => return,
+                    };
+                    lines.push(line);
+                }
+                lines
+            }
+            Err(ParserError::EndOfInput) => return,
+            Err(ParserError::NoOperations) => continue,
+            Err(e) => vec![format!("Error parsing input: {}\n", e)],
+        };
+        for line in lines {
+            if let Err(e) = writer.write_all(line.as_bytes()).await {
+                eprintln!("Error writing to client: {:?}", e);
+                return;
+            }
+        }
+        if writer.flush().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Sends one operation to the store and waits for its reply. Blocking pops give up
+/// after their timeout and reply `Nil`; dropping the receiver unregisters the waiter.
+pub async fn execute(
+    requests: &mpsc::Sender<Request>,
+    op: Op,
+) -> Option<Result<Reply, MemoryLayerErrors>> {
+    let timeout = op.block_timeout();
+    let (reply, response) = oneshot::channel();
+    requests.send(Request { op, reply }).await.ok()?;
+    match timeout {
+        Some(seconds) if seconds > 0.0 => {
+            match tokio::time::timeout(Duration::from_secs_f64(seconds), response).await {
+                Ok(result) => result.ok(),
+                Err(_) => Some(Ok(Reply::Nil)),
+            }
+        }
+        _ => response.await.ok(),
+    }
+}
+
+pub struct Waiter {
+    keys: Vec<Bytes>,
+    pub front: bool,
+    pub reply: ReplySender,
+}
+
+/// Sessions parked in BLPOP/BRPOP, queued per key in arrival order.
+pub struct BlockedClients {
+    next_id: u64,
+    waiters: HashMap<u64, Waiter>,
+    queues: HashMap<Bytes, VecDeque<u64>>,
+}
+
+impl BlockedClients {
+    pub fn new() -> Self {
+        Self {
+            next_id: 0,
+            waiters: HashMap::new(),
+            queues: HashMap::new(),
+        }
+    }
+
+    pub fn block(&mut self, keys: Vec<Bytes>, front: bool, reply: ReplySender) {
+        self.remove_timed_out();
+        let id = self.next_id;
+        self.next_id += 1;
+        for key in &keys {
+            self.queues.entry(key.clone()).or_default().push_back(id);
+        }
+        self.waiters.insert(id, Waiter { keys, front, reply });
+    }
+
+    /// Takes the longest waiting client on `key` that is still listening.
+    pub fn next_waiter(&mut self, key: &Bytes) -> Option<Waiter> {
+        loop {
+            let queue = self.queues.get_mut(key)?;
+            let id = queue.pop_front();
+            if queue.is_empty() {
+                self.queues.remove(key);
+            }
+            let id = id?;
+            let waiter = match self.waiters.remove(&id) {
+                Some(waiter) => waiter,
+                None => continue,
+            };
+            if waiter.reply.is_closed() {
+                continue;
+            }
+            // A client blocked on several keys is served once, drop it from the other queues.
+            for other in waiter.keys.iter().filter(|other| *other != key) {
+                self.forget(other, id);
+            }
+            return Some(waiter);
+        }
+    }
+
+    fn forget(&mut self, key: &Bytes, id: u64) {
+        if let Some(queue) = self.queues.get_mut(key) {
+            queue.retain(|queued| *queued != id);
+            if queue.is_empty() {
+                self.queues.remove(key);
+            }
+        }
+    }
+
+    fn remove_timed_out(&mut self) {
+        self.waiters.retain(|_, waiter| !waiter.reply.is_closed());
+        let waiters = &self.waiters;
+        self.queues.retain(|_, queue| {
+            queue.retain(|id| waiters.contains_key(id));
+            !queue.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_waiters_are_served_in_fifo_order() {
+        let mut blocked = BlockedClients::new();
+        let key = Bytes::from("jobs");
+        let (first, _first_rx) = oneshot::channel();
+        let (gone, gone_rx) = oneshot::channel();
+        let (second, _second_rx) = oneshot::channel();
+        blocked.block(vec![key.clone()], true, first);
+        blocked.block(vec![key.clone()], true, gone);
+        blocked.block(vec![Bytes::from("other"), key.clone()], false, second);
+        drop(gone_rx);
+
+        assert!(blocked.next_waiter(&key).unwrap().front);
+        // The timed out client is skipped, the multi-key one is next.
+        assert!(!blocked.next_waiter(&key).unwrap().front);
+        assert!(blocked.next_waiter(&key).is_none());
+        assert!(blocked.next_waiter(&Bytes::from("other")).is_none());
+        assert!(blocked.waiters.is_empty());
+        assert!(blocked.queues.is_empty());
+    }
+}
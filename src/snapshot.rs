@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::fs::{self, File};
+
+use crate::bytecode_serializer::BytecodeSerializer;
+use crate::errors::SnapshotError;
+use crate::operation::Op;
+use crate::types::Value;
+
+const SNAPSHOT_PREFIX: &str = "snapshot_";
+
+/// Snapshots kept after a checkpoint. The WAL is kept back to the oldest of
+/// them, so startup can still fall back to it if the newest one is damaged.
+pub const KEPT_SNAPSHOTS: usize = 2;
+
+/// The whole in-memory state as of a point in the WAL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    // LSN of the last WAL record the snapshot includes, zero for an empty log.
+    pub lsn: u64,
+    pub timestamp: i64,
+    pub entries: Vec<(Bytes, Value)>,
+    // INDEX.CREATE and SEARCH.CREATE ops that rebuild the indexes over the entries.
+    pub indexes: Vec<Op>,
+}
+
+/// When checkpoints are taken without being asked for, `None` turns a trigger off.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CheckpointPolicy {
+    pub interval: Option<Duration>,
+    pub after_writes: Option<u64>,
+}
+
+// Zero padded so the names sort in LSN order.
+fn file_name(lsn: u64) -> String {
+    format!("{}{:020}", SNAPSHOT_PREFIX, lsn)
+}
+
+fn snapshot_lsn(path: &Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix(SNAPSHOT_PREFIX)?
+        .parse()
+        .ok()
+}
+
+impl Snapshot {
+    /// Writes the snapshot through a synced temporary file and a rename, so a
+    /// crash never leaves a partial snapshot under its final name.
+    pub async fn store(&self, dir: &Path) -> Result<PathBuf, SnapshotError> {
+        let bytes = BytecodeSerializer::snapshot_to_bytes(self);
+        let path = dir.join(file_name(self.lsn));
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, bytes).await?;
+        File::open(&temp).await?.sync_all().await?;
+        fs::rename(&temp, &path).await?;
+        File::open(dir).await?.sync_all().await?;
+        Ok(path)
+    }
+
+    pub async fn load(path: &Path) -> Result<Self, SnapshotError> {
+        let bytes = fs::read(path).await?;
+        BytecodeSerializer::snapshot_from_bytes(&bytes).map_err(|e| SnapshotError::Invalid {
+            path: path.display().to_string(),
+            reason: format!("{:?}", e),
+        })
+    }
+}
+
+/// Snapshot files with their LSNs, oldest first.
+pub async fn list(dir: &Path) -> Result<Vec<(u64, PathBuf)>, SnapshotError> {
+    let mut snapshots = vec![];
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_none() {
+            snapshots.extend(snapshot_lsn(&path).map(|lsn| (lsn, path)));
+        }
+    }
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+/// Deletes all but the newest `keep` snapshots and returns the LSN of the
+/// oldest one left.
+pub async fn prune(dir: &Path, keep: usize) -> Result<Option<u64>, SnapshotError> {
+    let mut snapshots = list(dir).await?;
+    let old = snapshots.len().saturating_sub(keep);
+    for (_, path) in snapshots.drain(..old) {
+        fs::remove_file(path).await?;
+    }
+    Ok(snapshots.first().map(|(lsn, _)| *lsn))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_prune_keeps_the_newest() {
+        let dir = std::env::temp_dir().join(format!("snapshot_prune_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir).await;
+        fs::create_dir_all(&dir).await.unwrap();
+        for lsn in [9, 120, 40] {
+            let snapshot = Snapshot {
+                lsn,
+                timestamp: 0,
+                entries: vec![],
+                indexes: vec![],
+            };
+            snapshot.store(&dir).await.unwrap();
+        }
+        fs::write(dir.join("snapshot_00000000000000000200.tmp"), b"partial")
+            .await
+            .unwrap();
+
+        assert_eq!(prune(&dir, 2).await.unwrap(), Some(40));
+        let left: Vec<u64> = list(&dir)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(lsn, _)| lsn)
+            .collect();
+        assert_eq!(left, vec![40, 120]);
+        let newest = Snapshot::load(&dir.join(file_name(120))).await.unwrap();
+        assert_eq!(newest.lsn, 120);
+    }
+}
@@ -1,11 +1,38 @@
-use crate::kvstore::KvStore;
+use tokio::io::BufReader;
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::mpsc::Sender;
+use tokio::task;
+
+use crate::session::{self, Request};
 
 pub struct TcpAdapter {
-    kv_store: KvStore,
+    listener: TcpListener,
+    requests: Sender<Request>,
 }
 
 impl TcpAdapter {
-    pub async fn new(kv_store: KvStore) -> Self {
-        Self { kv_store }
+    pub async fn new<A: ToSocketAddrs>(
+        address: A,
+        requests: Sender<Request>,
+    ) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(address).await?;
+        Ok(Self { listener, requests })
+    }
+
+    /// Accepts connections forever, every connection gets its own session task.
+    pub async fn run(self) {
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, _)) => {
+                    let (reader, writer) = stream.into_split();
+                    task::spawn(session::serve(
+                        BufReader::new(reader),
+                        writer,
+                        self.requests.clone(),
+                    ));
+                }
+                Err(e) => eprintln!("Error accepting connection: {:?}", e),
+            }
+        }
     }
 }
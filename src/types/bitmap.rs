@@ -0,0 +1,143 @@
+//! Bit level helpers over string values. Offset 0 is the most significant
+//! bit of the first byte, strings grow with zero bytes when written past the end.
+
+use bytes::BytesMut;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+/// Sets the bit and returns its previous value.
+pub fn set_bit(bytes: &mut BytesMut, offset: u64, bit: bool) -> bool {
+    let byte_index = (offset / 8) as usize;
+    let mask = 0b1000_0000u8 >> (offset % 8);
+    if bytes.len() <= byte_index {
+        bytes.resize(byte_index + 1, 0);
+    }
+    let previous = bytes[byte_index] & mask != 0;
+    if bit {
+        bytes[byte_index] |= mask;
+    } else {
+        bytes[byte_index] &= !mask;
+    }
+    previous
+}
+
+pub fn get_bit(bytes: &[u8], offset: u64) -> bool {
+    let byte_index = (offset / 8) as usize;
+    let mask = 0b1000_0000u8 >> (offset % 8);
+    bytes.get(byte_index).is_some_and(|byte| byte & mask != 0)
+}
+
+// Resolves an inclusive byte range with negative indexes counted from the end.
+fn byte_range(len: usize, start: i64, end: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 { len + end } else { end.min(len - 1) };
+    if start > end || start >= len {
+        return None;
+    }
+    Some((start as usize, end as usize))
+}
+
+pub fn bit_count(bytes: &[u8], range: Option<(i64, i64)>) -> u64 {
+    let (start, end) = match range {
+        Some((start, end)) => match byte_range(bytes.len(), start, end) {
+            Some(range) => range,
+            None => return 0,
+        },
+        None if bytes.is_empty() => return 0,
+        None => (0, bytes.len() - 1),
+    };
+    bytes[start..=end]
+        .iter()
+        .map(|byte| byte.count_ones() as u64)
+        .sum()
+}
+
+/// Position of the first bit equal to `bit`, or -1. When looking for a clear
+/// bit without an explicit end, the string is treated as padded with zeros.
+pub fn bit_pos(bytes: &[u8], bit: bool, start: Option<i64>, end: Option<i64>) -> i64 {
+    let explicit_end = end.is_some();
+    let range = byte_range(bytes.len(), start.unwrap_or(0), end.unwrap_or(-1));
+    let (start, end) = match range {
+        Some(range) => range,
+        None if !bit && !explicit_end && bytes.is_empty() => return 0,
+        None => return -1,
+    };
+    for (index, byte) in bytes[start..=end].iter().enumerate() {
+        let candidate = if bit { *byte } else { !*byte };
+        if candidate != 0 {
+            let position = (start + index) * 8 + candidate.leading_zeros() as usize;
+            return position as i64;
+        }
+    }
+    if !bit && !explicit_end {
+        return ((end + 1) * 8) as i64;
+    }
+    -1
+}
+
+/// Combines the sources byte by byte, shorter sources are padded with zeros.
+pub fn bit_op(operation: BitOperation, sources: &[&[u8]]) -> Vec<u8> {
+    let len = sources.iter().map(|source| source.len()).max().unwrap_or(0);
+    let byte_at = |source: &[u8], index: usize| source.get(index).copied().unwrap_or(0);
+    (0..len)
+        .map(|index| {
+            let mut values = sources.iter().map(|source| byte_at(source, index));
+            let first = values.next().unwrap_or(0);
+            match operation {
+                BitOperation::Not => !first,
+                BitOperation::And => values.fold(first, |acc, byte| acc & byte),
+                BitOperation::Or => values.fold(first, |acc, byte| acc | byte),
+                BitOperation::Xor => values.fold(first, |acc, byte| acc ^ byte),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_get_bit() {
+        let mut bytes = BytesMut::new();
+        assert!(!set_bit(&mut bytes, 9, true));
+        assert_eq!(&bytes[..], &[0b0000_0000, 0b0100_0000]);
+        assert!(get_bit(&bytes, 9));
+        assert!(set_bit(&mut bytes, 9, false));
+        assert!(!get_bit(&bytes, 9));
+        assert!(!get_bit(&bytes, 1000));
+    }
+
+    #[test]
+    fn test_bit_count_and_pos() {
+        let bytes = [0b1111_1111, 0b1111_0000, 0b0000_0000];
+        assert_eq!(bit_count(&bytes, None), 12);
+        assert_eq!(bit_count(&bytes, Some((1, 1))), 4);
+        assert_eq!(bit_count(&bytes, Some((-2, -1))), 4);
+        assert_eq!(bit_pos(&bytes, false, None, None), 12);
+        assert_eq!(bit_pos(&bytes, true, Some(2), None), -1);
+        assert_eq!(bit_pos(&[0xff], false, None, None), 8);
+        assert_eq!(bit_pos(&[0xff], false, Some(0), Some(0)), -1);
+    }
+
+    #[test]
+    fn test_bit_op() {
+        let a: &[u8] = &[0b1100_0000, 0b1111_1111];
+        let b: &[u8] = &[0b1010_0000];
+        assert_eq!(bit_op(BitOperation::And, &[a, b]), vec![0b1000_0000, 0]);
+        assert_eq!(bit_op(BitOperation::Or, &[a, b]), vec![0b1110_0000, 0xff]);
+        assert_eq!(bit_op(BitOperation::Xor, &[a, b]), vec![0b0110_0000, 0xff]);
+        assert_eq!(bit_op(BitOperation::Not, &[b]), vec![0b0101_1111]);
+    }
+}
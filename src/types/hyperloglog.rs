@@ -0,0 +1,234 @@
+//! HyperLogLog cardinality estimator stored inside a plain string value.
+//!
+//! Encoding (all integers little endian):
+//! `b"HYLL"`, one encoding byte, then either
+//! - dense: `REGISTERS` 6 bit registers packed into `DENSE_SIZE` bytes
+//! - sparse: `u16` count followed by `(u16 index, u8 value)` for every non zero register
+//!
+//! The hash function is fixed (MurmurHash64A) so stored values stay valid across builds.
+
+const MAGIC: &[u8; 4] = b"HYLL";
+const HEADER_SIZE: usize = 5;
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+const PRECISION: u32 = 14;
+const REGISTERS: usize = 1 << PRECISION;
+const REGISTER_BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+const DENSE_SIZE: usize = REGISTERS * REGISTER_BITS / 8;
+// Past this many non zero registers the sparse form is no smaller than dense.
+const SPARSE_MAX_REGISTERS: usize = (DENSE_SIZE - 2) / 3;
+const HASH_SEED: u64 = 0xadc83b19;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns None when the bytes are not a valid HyperLogLog encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+            return None;
+        }
+        let payload = &bytes[HEADER_SIZE..];
+        let mut registers = vec![0u8; REGISTERS];
+        match bytes[4] {
+            DENSE => {
+                if payload.len() != DENSE_SIZE {
+                    return None;
+                }
+                for (index, register) in registers.iter_mut().enumerate() {
+                    *register = Self::read_dense(payload, index);
+                }
+            }
+            SPARSE => {
+                let count = u16::from_le_bytes(payload.get(..2)?.try_into().ok()?) as usize;
+                let entries = payload.get(2..)?;
+                if entries.len() != count * 3 {
+                    return None;
+                }
+                for entry in entries.chunks_exact(3) {
+                    let index = u16::from_le_bytes([entry[0], entry[1]]) as usize;
+                    if index >= REGISTERS || entry[2] > REGISTER_MAX {
+                        return None;
+                    }
+                    registers[index] = entry[2];
+                }
+            }
+            _ => return None,
+        }
+        Some(Self { registers })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        let non_zero = self.registers.iter().filter(|value| **value != 0).count();
+        if non_zero <= SPARSE_MAX_REGISTERS {
+            bytes.push(SPARSE);
+            bytes.extend((non_zero as u16).to_le_bytes());
+            for (index, value) in self.registers.iter().enumerate() {
+                if *value != 0 {
+                    bytes.extend((index as u16).to_le_bytes());
+                    bytes.push(*value);
+                }
+            }
+        } else {
+            bytes.push(DENSE);
+            let mut dense = vec![0u8; DENSE_SIZE];
+            for (index, value) in self.registers.iter().enumerate() {
+                Self::write_dense(&mut dense, index, *value);
+            }
+            bytes.extend(dense);
+        }
+        bytes
+    }
+
+    fn read_dense(dense: &[u8], index: usize) -> u8 {
+        let bit = index * REGISTER_BITS;
+        let (byte, shift) = (bit / 8, bit % 8);
+        let low = dense[byte] as u16;
+        let high = dense.get(byte + 1).copied().unwrap_or(0) as u16;
+        (((high << 8 | low) >> shift) as u8) & REGISTER_MAX
+    }
+
+    fn write_dense(dense: &mut [u8], index: usize, value: u8) {
+        let bit = index * REGISTER_BITS;
+        let (byte, shift) = (bit / 8, bit % 8);
+        let value = (value as u16) << shift;
+        dense[byte] |= value as u8;
+        if let Some(next) = dense.get_mut(byte + 1) {
+            *next |= (value >> 8) as u8;
+        }
+    }
+
+    /// Returns true if any register changed.
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let hash = murmur_hash64a(element, HASH_SEED);
+        let index = (hash as usize) & (REGISTERS - 1);
+        let remaining = (hash >> PRECISION) | (1 << (64 - PRECISION));
+        let rank = (remaining.trailing_zeros() + 1) as u8;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+            return true;
+        }
+        false
+    }
+
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let mut zeros = 0usize;
+        let mut sum = 0.0;
+        for register in &self.registers {
+            if *register == 0 {
+                zeros += 1;
+            }
+            sum += 2f64.powi(-(*register as i32));
+        }
+        let estimate = alpha * m * m / sum;
+        // Small range correction, linear counting is more accurate there.
+        if estimate <= 2.5 * m && zeros > 0 {
+            return (m * (m / zeros as f64).ln()).round() as u64;
+        }
+        estimate.round() as u64
+    }
+}
+
+fn murmur_hash64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+    let mut hash = seed ^ (data.len() as u64).wrapping_mul(M);
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("chunk is 8 bytes"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        hash ^= k;
+        hash = hash.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            hash ^= (*byte as u64) << (8 * i);
+        }
+        hash = hash.wrapping_mul(M);
+    }
+
+    hash ^= hash >> R;
+    hash = hash.wrapping_mul(M);
+    hash ^= hash >> R;
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(range: std::ops::Range<u32>) -> HyperLogLog {
+        let mut hll = HyperLogLog::new();
+        for i in range {
+            hll.add(format!("visitor:{}", i).as_bytes());
+        }
+        hll
+    }
+
+    #[test]
+    fn test_count_is_close() {
+        for expected in [10u32, 1_000, 50_000] {
+            let count = filled(0..expected).count() as f64;
+            let error = (count - expected as f64).abs() / expected as f64;
+            assert!(error < 0.02, "expected {} got {}", expected, count);
+        }
+    }
+
+    #[test]
+    fn test_encoding_roundtrip() {
+        for hll in [HyperLogLog::new(), filled(0..100), filled(0..100_000)] {
+            let bytes = hll.to_bytes();
+            assert_eq!(HyperLogLog::from_bytes(&bytes), Some(hll));
+        }
+        assert_eq!(filled(0..100).to_bytes()[4], SPARSE);
+        assert_eq!(filled(0..100_000).to_bytes()[4], DENSE);
+        assert_eq!(HyperLogLog::from_bytes(b"not an hll"), None);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut first = filled(0..1_000);
+        first.merge(&filled(500..1_500));
+        let count = first.count() as f64;
+        assert!((count - 1_500.0).abs() / 1_500.0 < 0.02);
+    }
+
+    #[test]
+    fn test_hash_is_stable() {
+        // Pinned values, changing them would silently corrupt stored counters.
+        assert_eq!(murmur_hash64a(b"hello", HASH_SEED), 0x0f656f01eecfe400);
+        assert_eq!(
+            murmur_hash64a(b"visitor:12345", HASH_SEED),
+            0x4751ce3d50f7c665
+        );
+    }
+}
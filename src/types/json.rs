@@ -0,0 +1,197 @@
+use std::str::FromStr;
+
+use serde_json::Value as JsonValue;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Field(String),
+    // Negative indexes count from the end of the array.
+    Index(i64),
+}
+
+/// A restricted JSONPath: `$` followed by `.field`, `["field"]` or `[index]` steps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath(Vec<PathSegment>);
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum JsonError {
+    #[error("New documents can only be created at the root path")]
+    NotAtRoot,
+
+    #[error("Path does not exist in the document")]
+    PathNotFound,
+
+    #[error("Path does not point to an array")]
+    NotAnArray,
+}
+
+impl JsonPath {
+    pub fn root() -> Self {
+        Self(vec![])
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.0
+    }
+
+    pub fn get<'a>(&self, document: &'a JsonValue) -> Option<&'a JsonValue> {
+        self.0
+            .iter()
+            .try_fold(document, |node, segment| match (segment, node) {
+                (PathSegment::Field(field), JsonValue::Object(object)) => object.get(field),
+                (PathSegment::Index(index), JsonValue::Array(array)) => {
+                    array.get(resolve_index(*index, array.len())?)
+                }
+                _ => None,
+            })
+    }
+
+    fn get_mut<'a>(
+        segments: &[PathSegment],
+        document: &'a mut JsonValue,
+    ) -> Option<&'a mut JsonValue> {
+        segments
+            .iter()
+            .try_fold(document, |node, segment| match (segment, node) {
+                (PathSegment::Field(field), JsonValue::Object(object)) => object.get_mut(field),
+                (PathSegment::Index(index), JsonValue::Array(array)) => {
+                    let index = resolve_index(*index, array.len())?;
+                    array.get_mut(index)
+                }
+                _ => None,
+            })
+    }
+
+    /// Replaces the value at the path. The parent has to exist, a missing field of
+    /// an object is added but arrays only accept existing indexes.
+    pub fn set(&self, document: &mut JsonValue, value: JsonValue) -> Result<(), JsonError> {
+        let (last, parents) = match self.0.split_last() {
+            Some(split) => split,
+            None => {
+                *document = value;
+                return Ok(());
+            }
+        };
+        let parent = Self::get_mut(parents, document).ok_or(JsonError::PathNotFound)?;
+        match (last, parent) {
+            (PathSegment::Field(field), JsonValue::Object(object)) => {
+                object.insert(field.clone(), value);
+            }
+            (PathSegment::Index(index), JsonValue::Array(array)) => {
+                let index = resolve_index(*index, array.len()).ok_or(JsonError::PathNotFound)?;
+                array[index] = value;
+            }
+            _ => return Err(JsonError::PathNotFound),
+        }
+        Ok(())
+    }
+
+    /// Appends values to the array at the path and returns its new length.
+    pub fn append(
+        &self,
+        document: &mut JsonValue,
+        values: Vec<JsonValue>,
+    ) -> Result<usize, JsonError> {
+        match Self::get_mut(&self.0, document) {
+            Some(JsonValue::Array(array)) => {
+                array.extend(values);
+                Ok(array.len())
+            }
+            Some(_) => Err(JsonError::NotAnArray),
+            None => Err(JsonError::PathNotFound),
+        }
+    }
+}
+
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+impl From<Vec<PathSegment>> for JsonPath {
+    fn from(segments: Vec<PathSegment>) -> Self {
+        Self(segments)
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = ();
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let mut rest = path.strip_prefix('$').ok_or(())?;
+        let mut segments = vec![];
+        while !rest.is_empty() {
+            if let Some(after_dot) = rest.strip_prefix('.') {
+                let end = after_dot.find(['.', '[']).unwrap_or(after_dot.len());
+                if end == 0 {
+                    return Err(());
+                }
+                segments.push(PathSegment::Field(after_dot[..end].to_string()));
+                rest = &after_dot[end..];
+            } else if let Some(after_bracket) = rest.strip_prefix('[') {
+                let end = after_bracket.find(']').ok_or(())?;
+                let inner = &after_bracket[..end];
+                let segment = match inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                    Some(field) => PathSegment::Field(field.to_string()),
+                    None => PathSegment::Index(inner.parse().map_err(|_| ())?),
+                };
+                segments.push(segment);
+                rest = &after_bracket[end + 1..];
+            } else {
+                return Err(());
+            }
+        }
+        Ok(Self(segments))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_path_parsing() {
+        let path: JsonPath = "$.servers[0][\"host.name\"]".parse().unwrap();
+        assert_eq!(
+            path,
+            JsonPath(vec![
+                PathSegment::Field("servers".to_string()),
+                PathSegment::Index(0),
+                PathSegment::Field("host.name".to_string()),
+            ])
+        );
+        assert!("$".parse::<JsonPath>().unwrap().is_root());
+        assert!("servers".parse::<JsonPath>().is_err());
+        assert!("$..a".parse::<JsonPath>().is_err());
+        assert!("$[x]".parse::<JsonPath>().is_err());
+    }
+
+    #[test]
+    fn test_set_get_and_append() {
+        let mut document = json!({"db": {"port": 5432}, "tags": ["a"]});
+        let port: JsonPath = "$.db.port".parse().unwrap();
+        port.set(&mut document, json!(6432)).unwrap();
+        assert_eq!(port.get(&document), Some(&json!(6432)));
+
+        let tags: JsonPath = "$.tags".parse().unwrap();
+        assert_eq!(tags.append(&mut document, vec![json!("b")]), Ok(2));
+        let last: JsonPath = "$.tags[-1]".parse().unwrap();
+        assert_eq!(last.get(&document), Some(&json!("b")));
+
+        assert_eq!(
+            port.append(&mut document, vec![json!(1)]),
+            Err(JsonError::NotAnArray)
+        );
+        let missing: JsonPath = "$.cache.size".parse().unwrap();
+        assert_eq!(
+            missing.set(&mut document, json!(1)),
+            Err(JsonError::PathNotFound)
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use bytes::Bytes;
+
+use crate::reply::Reply;
+
+pub mod bitmap;
+pub mod hyperloglog;
+pub mod json;
+pub mod sorted_set;
+pub mod stream;
+pub mod timeseries;
+
+use sorted_set::SortedSet;
+use stream::Stream;
+use timeseries::TimeSeries;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(Bytes),
+    List(VecDeque<Bytes>),
+    Hash(BTreeMap<Bytes, Bytes>),
+    SortedSet(SortedSet),
+    Set(BTreeSet<Bytes>),
+    Stream(Stream),
+    TimeSeries(TimeSeries),
+    Json(serde_json::Value),
+}
+
+impl Value {
+    pub fn into_reply(self) -> Reply {
+        match self {
+            Value::String(bytes) => Reply::Bulk(bytes),
+            Value::List(list) => Reply::Array(list.into_iter().map(Reply::Bulk).collect()),
+            Value::Hash(hash) => Reply::Array(
+                hash.into_iter()
+                    .flat_map(|(field, value)| [Reply::Bulk(field), Reply::Bulk(value)])
+                    .collect(),
+            ),
+            Value::SortedSet(set) => Reply::Array(
+                set.iter()
+                    .flat_map(|(member, score)| [Reply::Bulk(member.clone()), Reply::score(score)])
+                    .collect(),
+            ),
+            Value::Set(set) => Reply::Array(set.into_iter().map(Reply::Bulk).collect()),
+            Value::Stream(stream) => Reply::Array(
+                stream
+                    .range(stream::StreamId::MIN, stream::StreamId::MAX)
+                    .into_iter()
+                    .map(Reply::stream_entry)
+                    .collect(),
+            ),
+            Value::TimeSeries(series) => Reply::Array(
+                series
+                    .range(i64::MIN, i64::MAX)
+                    .into_iter()
+                    .map(Reply::sample)
+                    .collect(),
+            ),
+            Value::Json(document) => Reply::Bulk(Bytes::from(document.to_string())),
+        }
+    }
+}
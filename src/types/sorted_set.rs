@@ -0,0 +1,169 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+use bytes::Bytes;
+
+// f64 has no total order, so scores are wrapped to be usable as BTreeSet keys.
+#[derive(Debug, Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Members ordered by (score, member), with a side table for O(1) score lookups.
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: HashMap<Bytes, f64>,
+    ordered: BTreeSet<(Score, Bytes)>,
+}
+
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.ordered == other.ordered
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Returns true if the member was not present before.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.ordered.remove(&(Score(previous), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        previous.is_none()
+    }
+
+    pub fn remove(&mut self, member: &Bytes) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.ordered.remove(&(Score(score), member.clone()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn score(&self, member: &Bytes) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    pub fn rank(&self, member: &Bytes) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(self.ordered.range(..(Score(score), member.clone())).count())
+    }
+
+    /// Inclusive rank range, negative indexes count from the highest score.
+    pub fn range_by_rank(&self, start: i64, stop: i64) -> Vec<(Bytes, f64)> {
+        let len = self.len() as i64;
+        let start = if start < 0 {
+            (len + start).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 {
+            len + stop
+        } else {
+            stop.min(len - 1)
+        };
+        if start > stop || start >= len {
+            return vec![];
+        }
+        self.ordered
+            .iter()
+            .skip(start as usize)
+            .take((stop - start + 1) as usize)
+            .map(|(score, member)| (member.clone(), score.0))
+            .collect()
+    }
+
+    /// Inclusive score range.
+    pub fn range_by_score(&self, min: f64, max: f64) -> Vec<(Bytes, f64)> {
+        self.ordered
+            .iter()
+            .skip_while(|(score, _)| score.0 < min)
+            .take_while(|(score, _)| score.0 <= max)
+            .map(|(score, member)| (member.clone(), score.0))
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.ordered.iter().map(|(score, member)| (member, score.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaderboard() -> SortedSet {
+        let mut set = SortedSet::new();
+        set.insert(Bytes::from("carol"), 30.0);
+        set.insert(Bytes::from("alice"), 10.0);
+        set.insert(Bytes::from("bob"), 20.0);
+        set
+    }
+
+    #[test]
+    fn test_sorted_set_order_and_rank() {
+        let set = leaderboard();
+        let members: Vec<Bytes> = set
+            .range_by_rank(0, -1)
+            .into_iter()
+            .map(|(m, _)| m)
+            .collect();
+        assert_eq!(members, vec!["alice", "bob", "carol"]);
+        assert_eq!(set.rank(&Bytes::from("carol")), Some(2));
+        assert_eq!(set.rank(&Bytes::from("dave")), None);
+    }
+
+    #[test]
+    fn test_sorted_set_update_score() {
+        let mut set = leaderboard();
+        assert!(!set.insert(Bytes::from("alice"), 40.0));
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.rank(&Bytes::from("alice")), Some(2));
+        assert_eq!(set.score(&Bytes::from("alice")), Some(40.0));
+    }
+
+    #[test]
+    fn test_sorted_set_range_by_score() {
+        let mut set = leaderboard();
+        let range = set.range_by_score(15.0, f64::INFINITY);
+        assert_eq!(
+            range,
+            vec![(Bytes::from("bob"), 20.0), (Bytes::from("carol"), 30.0)]
+        );
+        assert!(set.remove(&Bytes::from("bob")));
+        assert_eq!(set.range_by_score(15.0, 25.0), vec![]);
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use bytes::Bytes;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// Parses a range bound, `-` and `+` are the smallest and largest ids, a
+    /// bare millisecond value covers every sequence number in it.
+    pub fn parse_bound(bound: &str, is_end: bool) -> Option<Self> {
+        match bound {
+            "-" => Some(Self::MIN),
+            "+" => Some(Self::MAX),
+            _ if bound.contains('-') => bound.parse().ok(),
+            _ => {
+                let ms = bound.parse().ok()?;
+                let seq = if is_end { u64::MAX } else { 0 };
+                Some(Self { ms, seq })
+            }
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = ();
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        let (ms, seq) = id.split_once('-').unwrap_or((id, "0"));
+        Ok(Self {
+            ms: ms.parse().map_err(|_| ())?,
+            seq: seq.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+/// Id requested by XADD. `Auto` takes its milliseconds from the op's HLC stamp
+/// when the op is applied, so replaying the WAL generates the same ids again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum XAddId {
+    Auto { ms: u64 },
+    Explicit(StreamId),
+}
+
+pub type StreamEntry = (StreamId, Vec<(Bytes, Bytes)>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingEntry {
+    pub consumer: Bytes,
+    pub delivered_at: i64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ConsumerGroup {
+    pub last_delivered: StreamId,
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum StreamError {
+    #[error("The ID specified in XADD is equal or smaller than the target stream top item")]
+    IdTooSmall,
+
+    #[error("Consumer Group name already exists")]
+    GroupExists,
+
+    #[error("No such consumer group for this stream")]
+    NoGroup,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Vec<(Bytes, Bytes)>>,
+    last_id: StreamId,
+    groups: BTreeMap<Bytes, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rebuilds a stream read back from a snapshot.
+    pub fn from_parts(
+        entries: BTreeMap<StreamId, Vec<(Bytes, Bytes)>>,
+        last_id: StreamId,
+        groups: BTreeMap<Bytes, ConsumerGroup>,
+    ) -> Self {
+        Self {
+            entries,
+            last_id,
+            groups,
+        }
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn groups(&self) -> &BTreeMap<Bytes, ConsumerGroup> {
+        &self.groups
+    }
+
+    pub fn add(
+        &mut self,
+        id: XAddId,
+        fields: Vec<(Bytes, Bytes)>,
+    ) -> Result<StreamId, StreamError> {
+        let id = match id {
+            XAddId::Auto { ms } if ms > self.last_id.ms => StreamId::new(ms, 0),
+            // The clock went back or several entries share a millisecond.
+            XAddId::Auto { .. } => StreamId::new(self.last_id.ms, self.last_id.seq + 1),
+            XAddId::Explicit(id) => id,
+        };
+        if id <= self.last_id {
+            return Err(StreamError::IdTooSmall);
+        }
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        Ok(id)
+    }
+
+    pub fn range(&self, start: StreamId, end: StreamId) -> Vec<StreamEntry> {
+        if start > end {
+            return vec![];
+        }
+        self.entries
+            .range(start..=end)
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect()
+    }
+
+    /// Entries strictly after `after`.
+    pub fn read_after(&self, after: StreamId, count: Option<usize>) -> Vec<StreamEntry> {
+        self.entries
+            .range((std::ops::Bound::Excluded(after), std::ops::Bound::Unbounded))
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect()
+    }
+
+    /// `start` of None means only entries added after the group is created.
+    pub fn create_group(
+        &mut self,
+        name: Bytes,
+        start: Option<StreamId>,
+    ) -> Result<(), StreamError> {
+        if self.groups.contains_key(&name) {
+            return Err(StreamError::GroupExists);
+        }
+        let group = ConsumerGroup {
+            last_delivered: start.unwrap_or(self.last_id),
+            pending: BTreeMap::new(),
+        };
+        self.groups.insert(name, group);
+        Ok(())
+    }
+
+    /// With `after` of None new entries are delivered to the consumer and
+    /// added to the pending list, otherwise the consumer's own pending
+    /// entries after that id are returned again.
+    pub fn read_group(
+        &mut self,
+        group: &Bytes,
+        consumer: Bytes,
+        after: Option<StreamId>,
+        count: Option<usize>,
+        now: i64,
+    ) -> Result<Vec<StreamEntry>, StreamError> {
+        let entries = &self.entries;
+        let group = self.groups.get_mut(group).ok_or(StreamError::NoGroup)?;
+        let count = count.unwrap_or(usize::MAX);
+        match after {
+            None => {
+                let delivered: Vec<StreamEntry> = entries
+                    .range((
+                        std::ops::Bound::Excluded(group.last_delivered),
+                        std::ops::Bound::Unbounded,
+                    ))
+                    .take(count)
+                    .map(|(id, fields)| (*id, fields.clone()))
+                    .collect();
+                for (id, _) in &delivered {
+                    group.pending.insert(
+                        *id,
+                        PendingEntry {
+                            consumer: consumer.clone(),
+                            delivered_at: now,
+                            delivery_count: 1,
+                        },
+                    );
+                    group.last_delivered = *id;
+                }
+                Ok(delivered)
+            }
+            Some(after) => {
+                let mut history = vec![];
+                for (id, pending) in group
+                    .pending
+                    .range_mut((std::ops::Bound::Excluded(after), std::ops::Bound::Unbounded))
+                {
+                    if history.len() == count {
+                        break;
+                    }
+                    if pending.consumer != consumer {
+                        continue;
+                    }
+                    pending.delivered_at = now;
+                    pending.delivery_count += 1;
+                    let fields = entries.get(id).cloned().unwrap_or_default();
+                    history.push((*id, fields));
+                }
+                Ok(history)
+            }
+        }
+    }
+
+    pub fn ack(&mut self, group: &Bytes, ids: &[StreamId]) -> Result<usize, StreamError> {
+        let group = self.groups.get_mut(group).ok_or(StreamError::NoGroup)?;
+        Ok(ids
+            .iter()
+            .filter(|id| group.pending.remove(id).is_some())
+            .count())
+    }
+
+    pub fn pending(&self, group: &Bytes) -> Result<Vec<(StreamId, PendingEntry)>, StreamError> {
+        let group = self.groups.get(group).ok_or(StreamError::NoGroup)?;
+        Ok(group
+            .pending
+            .iter()
+            .map(|(id, pending)| (*id, pending.clone()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(value: &str) -> Vec<(Bytes, Bytes)> {
+        vec![(Bytes::from("event"), Bytes::from(value.to_string()))]
+    }
+
+    #[test]
+    fn test_auto_ids_are_monotonic() {
+        let mut stream = Stream::new();
+        let first = stream.add(XAddId::Auto { ms: 100 }, fields("a")).unwrap();
+        let second = stream.add(XAddId::Auto { ms: 100 }, fields("b")).unwrap();
+        let third = stream.add(XAddId::Auto { ms: 50 }, fields("c")).unwrap();
+        assert_eq!(first, StreamId::new(100, 0));
+        assert_eq!(second, StreamId::new(100, 1));
+        assert_eq!(third, StreamId::new(100, 2));
+        assert_eq!(
+            stream.add(XAddId::Explicit(StreamId::new(1, 0)), fields("d")),
+            Err(StreamError::IdTooSmall)
+        );
+    }
+
+    #[test]
+    fn test_range_and_read() {
+        let mut stream = Stream::new();
+        for ms in 1..=5 {
+            stream.add(XAddId::Auto { ms }, fields("x")).unwrap();
+        }
+        let range = stream.range(
+            StreamId::parse_bound("2", false).unwrap(),
+            StreamId::parse_bound("4", true).unwrap(),
+        );
+        assert_eq!(range.len(), 3);
+        let read = stream.read_after(StreamId::new(4, 0), None);
+        assert_eq!(read, vec![(StreamId::new(5, 0), fields("x"))]);
+    }
+
+    #[test]
+    fn test_consumer_group_delivery_and_ack() {
+        let mut stream = Stream::new();
+        stream.add(XAddId::Auto { ms: 1 }, fields("a")).unwrap();
+        let group = Bytes::from("workers");
+        stream
+            .create_group(group.clone(), Some(StreamId::MIN))
+            .unwrap();
+        stream.add(XAddId::Auto { ms: 2 }, fields("b")).unwrap();
+
+        let delivered = stream
+            .read_group(&group, Bytes::from("w1"), None, Some(1), 10)
+            .unwrap();
+        assert_eq!(delivered, vec![(StreamId::new(1, 0), fields("a"))]);
+        let delivered = stream
+            .read_group(&group, Bytes::from("w2"), None, None, 11)
+            .unwrap();
+        assert_eq!(delivered, vec![(StreamId::new(2, 0), fields("b"))]);
+
+        let history = stream
+            .read_group(&group, Bytes::from("w1"), Some(StreamId::MIN), None, 12)
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(stream.pending(&group).unwrap()[0].1.delivery_count, 2);
+
+        assert_eq!(stream.ack(&group, &[StreamId::new(1, 0)]).unwrap(), 1);
+        assert_eq!(stream.pending(&group).unwrap().len(), 1);
+        assert_eq!(
+            stream.create_group(group, None),
+            Err(StreamError::GroupExists)
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+
+/// Timestamps are milliseconds since the epoch.
+pub type Sample = (i64, f64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    Avg,
+    Min,
+    Max,
+    Sum,
+}
+
+impl Aggregation {
+    fn apply(self, values: &[f64]) -> f64 {
+        match self {
+            Aggregation::Sum => values.iter().sum(),
+            Aggregation::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregation::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Aggregation::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TimeSeries {
+    samples: BTreeMap<i64, f64>,
+    // Zero keeps every sample.
+    retention: u64,
+}
+
+impl TimeSeries {
+    pub fn retention(&self) -> u64 {
+        self.retention
+    }
+
+    pub fn set_retention(&mut self, retention: u64) {
+        self.retention = retention;
+        self.trim();
+    }
+
+    /// Adds a sample, a second sample at the same timestamp replaces the first.
+    pub fn add(&mut self, timestamp: i64, value: f64) {
+        self.samples.insert(timestamp, value);
+        self.trim();
+    }
+
+    pub fn range(&self, from: i64, to: i64) -> Vec<Sample> {
+        if from > to {
+            return vec![];
+        }
+        self.samples
+            .range(from..=to)
+            .map(|(timestamp, value)| (*timestamp, *value))
+            .collect()
+    }
+
+    /// Aggregates the samples in `from..=to` into buckets of `bucket` milliseconds.
+    /// Buckets are aligned to multiples of their width and empty ones are skipped.
+    /// A bucket that would start before `i64::MIN` starts at `i64::MIN`.
+    pub fn aggregate(
+        &self,
+        from: i64,
+        to: i64,
+        aggregation: Aggregation,
+        bucket: u64,
+    ) -> Vec<Sample> {
+        let bucket = bucket.max(1).min(i64::MAX as u64) as i64;
+        let mut buckets: BTreeMap<i64, Vec<f64>> = BTreeMap::new();
+        for (timestamp, value) in self.range(from, to) {
+            let start = timestamp.saturating_sub(timestamp.rem_euclid(bucket));
+            buckets.entry(start).or_default().push(value);
+        }
+        buckets
+            .into_iter()
+            .map(|(start, values)| (start, aggregation.apply(&values)))
+            .collect()
+    }
+
+    // Retention is measured from the newest sample rather than the wall clock, so
+    // replaying the WAL later drops exactly the samples that were dropped live.
+    fn trim(&mut self) {
+        let newest = match self.samples.keys().next_back() {
+            Some(newest) if self.retention > 0 => *newest,
+            _ => return,
+        };
+        let cutoff = newest.saturating_sub(self.retention.min(i64::MAX as u64) as i64);
+        self.samples = self.samples.split_off(&cutoff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retention_drops_old_samples() {
+        let mut series = TimeSeries::default();
+        series.set_retention(1_000);
+        series.add(1_000, 1.0);
+        series.add(1_500, 2.0);
+        series.add(2_200, 3.0);
+        assert_eq!(
+            series.range(i64::MIN, i64::MAX),
+            vec![(1_500, 2.0), (2_200, 3.0)]
+        );
+
+        series.set_retention(500);
+        assert_eq!(series.range(i64::MIN, i64::MAX), vec![(2_200, 3.0)]);
+    }
+
+    #[test]
+    fn test_aggregate_buckets() {
+        let mut series = TimeSeries::default();
+        for (timestamp, value) in [
+            (0, 1.0),
+            (400, 3.0),
+            (1_000, 10.0),
+            (2_900, 4.0),
+            (2_999, 8.0),
+        ] {
+            series.add(timestamp, value);
+        }
+        assert_eq!(
+            series.aggregate(0, 3_000, Aggregation::Avg, 1_000),
+            vec![(0, 2.0), (1_000, 10.0), (2_000, 6.0)]
+        );
+        assert_eq!(
+            series.aggregate(0, 3_000, Aggregation::Max, 1_000),
+            vec![(0, 3.0), (1_000, 10.0), (2_000, 8.0)]
+        );
+        assert_eq!(
+            series.aggregate(400, 2_900, Aggregation::Sum, 5_000),
+            vec![(0, 17.0)]
+        );
+        assert_eq!(
+            series.aggregate(-10, 10, Aggregation::Min, 100),
+            vec![(0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_extreme_timestamps() {
+        let mut series = TimeSeries::default();
+        series.add(i64::MIN, 1.0);
+        series.add(i64::MIN + 1, 2.0);
+        series.add(i64::MAX, 4.0);
+        assert_eq!(
+            series.aggregate(i64::MIN, i64::MAX, Aggregation::Sum, 7),
+            vec![
+                (i64::MIN, 1.0),
+                (i64::MIN + 1, 2.0),
+                (i64::MAX - i64::MAX.rem_euclid(7), 4.0)
+            ]
+        );
+        assert_eq!(
+            series.aggregate(i64::MIN, i64::MAX, Aggregation::Sum, u64::MAX),
+            vec![(i64::MIN, 1.0), (i64::MIN + 1, 2.0), (i64::MAX, 4.0)]
+        );
+    }
+}
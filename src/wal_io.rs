@@ -1,8 +1,25 @@
+use std::time::Duration;
+
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
 
 use crate::errors::WALError;
 
+/// When the WAL forces its writes to disk. Every mode hands each batch of
+/// records to the OS as soon as it is written, so a crash of the process alone
+/// loses nothing that reached the WAL task; the modes differ in what a power
+/// failure or kernel crash can take with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// fsync after every batch. Nothing written to the WAL is lost.
+    Always,
+    /// fsync at most once per interval. Loses up to the last interval of writes.
+    Interval(Duration),
+    /// Never fsync, the OS writes pages back on its own schedule, typically
+    /// within 30 seconds on Linux. Loses whatever it had not written back yet.
+    Os,
+}
+
 pub struct WALio {
     file_handle: File,
     batch: Vec<u8>,
@@ -26,14 +43,15 @@ impl WALio {
 
     pub async fn flush(&mut self) -> Result<(), WALError> {
         self.file_handle.write_all(&self.batch).await?;
+        // tokio only waits for the write to reach the OS on flush.
+        self.file_handle.flush().await?;
         self.batch.clear();
         Ok(())
     }
 
-    pub async fn recover(&mut self) -> Result<Vec<u8>, WALError> {
-        let mut buffer = Vec::new();
-        self.file_handle.read_to_end(&mut buffer).await?;
-        Ok(buffer)
+    pub async fn sync(&mut self) -> Result<(), WALError> {
+        self.file_handle.sync_data().await?;
+        Ok(())
     }
 
     pub fn set_new_file_handle(&mut self, file_handle: File) {
@@ -44,6 +62,7 @@ impl WALio {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tokio::io::AsyncReadExt;
 
     #[tokio::test]
     async fn test_wal_io() {